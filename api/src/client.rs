@@ -0,0 +1,244 @@
+use crate::*;
+use anyhow::{bail, Result};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{Method, RequestBuilder};
+use serde::de::DeserializeOwned;
+
+/// 路径里的一段，除了 RFC 3986 的 unreserved 字符都转义
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn category_path(name: &str) -> String {
+    format!("/categories/{}", utf8_percent_encode(name, PATH_SEGMENT))
+}
+
+/// 服务端 HTTP 接口的客户端
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: String,
+    client: reqwest::Client,
+    /// 控制接口和网页接口要用的 token
+    token: Option<String>,
+}
+
+impl Client {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_client(base_url, reqwest::Client::new())
+    }
+
+    pub fn with_client(base_url: impl Into<String>, client: reqwest::Client) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        Self {
+            base_url,
+            client,
+            token: None,
+        }
+    }
+
+    /// 带上 token，admin token 才能调用控制接口和修改分类
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// 出错时把服务端返回的 [`ErrorBody`] 转成错误
+    async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await?;
+            match serde_json::from_str::<ErrorBody>(&body) {
+                Ok(e) => bail!("{} ({}): {}", status, e.code, e.error),
+                Err(_) => bail!("{}: {}", status, body),
+            }
+        }
+        Ok(response.json().await?)
+    }
+
+    pub async fn categories(&self) -> Result<Vec<Category>> {
+        Self::send(self.request(Method::GET, "/categories")).await
+    }
+
+    pub async fn create_category(&self, category: &NewCategory) -> Result<Category> {
+        Self::send(self.request(Method::POST, "/categories").json(category)).await
+    }
+
+    pub async fn patch_category(&self, name: &str, patch: &CategoryPatch) -> Result<Category> {
+        let path = category_path(name);
+        Self::send(self.request(Method::PATCH, &path).json(patch)).await
+    }
+
+    pub async fn delete_category(&self, name: &str) -> Result<()> {
+        let path = category_path(name);
+        let _: serde_json::Value = Self::send(self.request(Method::DELETE, &path)).await?;
+        Ok(())
+    }
+
+    /// 北京时间这一天的汇总，分类 => 链接
+    pub async fn summary(&self, date: NaiveDate) -> Result<LegacySummary> {
+        let query = SummaryQuery {
+            from: Some(date),
+            ..Default::default()
+        };
+        Self::send(self.request(Method::GET, "/summary").query(&query)).await
+    }
+
+    pub async fn summary_grouped(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        group_by: GroupBy,
+    ) -> Result<GroupedSummary> {
+        let query = SummaryQuery {
+            from: Some(from),
+            to: Some(to),
+            group_by: Some(group_by),
+            ..Default::default()
+        };
+        Self::send(self.request(Method::GET, "/summary").query(&query)).await
+    }
+
+    pub async fn kpi(&self, from: NaiveDate, to: NaiveDate) -> Result<KpiRange> {
+        let query = KpiQuery {
+            from: Some(from),
+            to: Some(to),
+            ..Default::default()
+        };
+        Self::send(self.request(Method::GET, "/kpi").query(&query)).await
+    }
+
+    pub async fn items(&self, query: &ItemQuery) -> Result<ItemPage> {
+        Self::send(self.request(Method::GET, "/items").query(query)).await
+    }
+
+    pub async fn review(&self, id: &str, review: &ReviewRequest) -> Result<Item> {
+        let path = format!("/items/{}/review", id);
+        Self::send(self.request(Method::POST, &path).json(review)).await
+    }
+
+    pub async fn control(&self) -> Result<ControlState> {
+        Self::send(self.request(Method::GET, "/control")).await
+    }
+
+    /// 全部暂停或者恢复
+    pub async fn set_paused(&self, paused: bool) -> Result<ControlState> {
+        let body = PauseRequest { paused };
+        Self::send(self.request(Method::PUT, "/control").json(&body)).await
+    }
+
+    pub async fn set_source_paused(&self, source: ItemKind, paused: bool) -> Result<ControlState> {
+        let path = format!("/control/{}", source.as_str());
+        let body = PauseRequest { paused };
+        Self::send(self.request(Method::PUT, &path).json(&body)).await
+    }
+
+    /// 马上拉取一轮
+    pub async fn run_now(&self, source: ItemKind) -> Result<ControlState> {
+        let path = format!("/control/{}/run", source.as_str());
+        Self::send(self.request(Method::POST, &path)).await
+    }
+
+    pub async fn webhook_deliveries(&self, query: &DeliveryQuery) -> Result<Vec<WebhookDelivery>> {
+        Self::send(
+            self.request(Method::GET, "/webhooks/deliveries")
+                .query(query),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_summary() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/summary"))
+            .and(query_param("from", "2021-10-18"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "音乐": ["BV1xx411c7mD"],
+            })))
+            .mount(&server)
+            .await;
+        let client = Client::new(format!("{}/", server.uri()));
+        let summary = client
+            .summary(NaiveDate::from_ymd(2021, 10, 18))
+            .await
+            .unwrap();
+        assert_eq!(summary["音乐"], vec!["BV1xx411c7mD".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_error_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("PATCH"))
+            .and(path("/categories/music"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(serde_json::json!({
+                "code": "not_found",
+                "error": "不存在分类 music",
+            })))
+            .mount(&server)
+            .await;
+        let client = Client::new(server.uri());
+        let e = client
+            .patch_category("music", &CategoryPatch::default())
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "404 Not Found (not_found): 不存在分类 music");
+    }
+
+    #[tokio::test]
+    async fn test_category_path() {
+        assert_eq!(
+            category_path("鬼畜/整活"),
+            "/categories/%E9%AC%BC%E7%95%9C%2F%E6%95%B4%E6%B4%BB"
+        );
+        assert_eq!(category_path("a b?#%"), "/categories/a%20b%3F%23%25");
+        let server = MockServer::start().await;
+        Mock::given(method("DELETE"))
+            .and(path("/categories/%E9%AC%BC%E7%95%9C%2F%E6%95%B4%E6%B4%BB"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "msg": "ok",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = Client::new(server.uri());
+        client.delete_category("鬼畜/整活").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_now() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/control/video/run"))
+            .and(header("Authorization", "Bearer secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "paused": false,
+                "sources": [{ "source": "video", "paused": false }],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = Client::new(server.uri()).with_token("secret");
+        let state = client.run_now(ItemKind::Video).await.unwrap();
+        assert_eq!(state.sources[0].source, ItemKind::Video);
+    }
+}
@@ -0,0 +1,608 @@
+//! 服务端 HTTP 接口的类型和客户端，服务端、gen-article 和 bin 里的工具共用
+
+#[macro_use]
+extern crate serde;
+
+mod client;
+mod openapi;
+
+pub use client::Client;
+pub use openapi::openapi;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use schemars::JsonSchema;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemKind {
+    Video,
+    Dynamic,
+}
+impl ItemKind {
+    /// 也是拉取的来源
+    pub const ALL: [ItemKind; 2] = [ItemKind::Video, ItemKind::Dynamic];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ItemKind::Video => "video",
+            ItemKind::Dynamic => "dynamic",
+        }
+    }
+    /// 从 id 猜类型，只在没有其他信息的时候用（比如 HTTP 接口手动添加）
+    pub fn guess(id: &str) -> Self {
+        if id.starts_with("BV") {
+            ItemKind::Video
+        } else {
+            ItemKind::Dynamic
+        }
+    }
+}
+impl std::str::FromStr for ItemKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "video" => ItemKind::Video,
+            "dynamic" => ItemKind::Dynamic,
+            _ => anyhow::bail!("unknown item kind: {}", s),
+        })
+    }
+}
+
+/// 接口出错时的 body
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorBody {
+    /// 给程序判断用，不会变：bad_request、unauthorized、not_found、conflict、upstream_error、internal_error
+    pub code: String,
+    /// 给人看的错误信息
+    pub error: String,
+    /// 完整的错误链，只在开发环境返回
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// 分类
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Category {
+    pub name: String,
+    /// 出现在哪种卡片上
+    pub kind: ItemKind,
+    /// 越小越靠前
+    pub sort_order: i64,
+    /// 旧的分类名，如 `ok` => `动态`
+    pub aliases: Vec<String>,
+    pub active: bool,
+    /// 日报里的小标题，为空时用分类名
+    pub section_title: Option<String>,
+}
+
+fn default_kind() -> ItemKind {
+    ItemKind::Video
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// `POST /categories`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NewCategory {
+    pub name: String,
+    #[serde(default = "default_kind")]
+    pub kind: ItemKind,
+    /// 不填时排在最后
+    pub sort_order: Option<i64>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+    pub section_title: Option<String>,
+}
+
+/// `PATCH /categories/{name}`，只修改填了的字段
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CategoryPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<ItemKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_order: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active: Option<bool>,
+    /// 空字符串表示清空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub section_title: Option<String>,
+}
+
+/// 动态或者视频
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Item {
+    /// BV 号或者动态 id
+    pub id: String,
+    pub kind: ItemKind,
+    /// 视频标题，动态的正文
+    pub title: String,
+    /// 视频简介，动态没有
+    pub description: String,
+    /// 旧数据没有
+    pub uploader_uid: Option<i64>,
+    pub uploader_name: String,
+    /// 视频封面，动态的第一张图
+    pub cover_url: String,
+    /// 卡片上的图片在飞书的 image_key
+    pub image_key: String,
+    /// 视频长度，秒
+    pub duration: Option<u32>,
+    /// 动态的图片数
+    pub picture_count: Option<u32>,
+    /// 从哪些监控的 tag 拉到的
+    pub source_tags: Vec<String>,
+    /// 推送时的播放数，旧数据可能没有
+    pub view_count: Option<u32>,
+    pub like_count: Option<u32>,
+    /// 评论数
+    pub reply_count: Option<u32>,
+    pub danmaku_count: Option<u32>,
+    pub message_id: String,
+    /// 视频、动态的发布时间
+    pub published_at: DateTime<Utc>,
+    /// 推送到飞书的时间
+    pub pushed_at: DateTime<Utc>,
+    pub category: Option<String>,
+    pub marker: Option<String>,
+    pub marked_at: Option<DateTime<Utc>>,
+}
+
+/// 列表接口的排序字段
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemSort {
+    PublishedAt,
+    PushedAt,
+    MarkedAt,
+}
+impl Default for ItemSort {
+    fn default() -> Self {
+        ItemSort::PublishedAt
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+impl Default for Order {
+    fn default() -> Self {
+        Order::Desc
+    }
+}
+
+fn default_limit() -> u32 {
+    20
+}
+
+/// `GET /items` 的参数，都是可选的
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ItemQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<ItemKind>,
+    /// 可以用旧的分类名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// 只要还没分类的
+    #[serde(default)]
+    pub uncategorized: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploader_uid: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marker: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published_from: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published_to: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marked_from: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marked_to: Option<DateTime<Utc>>,
+    /// 搜索视频标题、简介和动态正文
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    #[serde(default)]
+    pub sort: ItemSort,
+    #[serde(default)]
+    pub order: Order,
+    /// 上一页返回的 `next_cursor`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// 一页多少条，最多 100
+    #[serde(default = "default_limit")]
+    pub limit: u32,
+}
+impl Default for ItemQuery {
+    fn default() -> Self {
+        Self {
+            kind: None,
+            category: None,
+            uncategorized: false,
+            uploader_uid: None,
+            uploader: None,
+            marker: None,
+            published_from: None,
+            published_to: None,
+            marked_from: None,
+            marked_to: None,
+            q: None,
+            sort: ItemSort::default(),
+            order: Order::default(),
+            cursor: None,
+            limit: default_limit(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ItemPage {
+    pub items: Vec<Item>,
+    /// 没有下一页时为空
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Review {
+    Categorize { category: String },
+    Reject,
+    Undo,
+}
+
+/// `POST /items/{id}/review`，标记人是 token 对应的名字
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ReviewRequest {
+    #[serde(flatten)]
+    pub review: Review,
+}
+
+/// 汇总的分组方式
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    Day,
+    Category,
+    Uploader,
+}
+
+/// `GET /summary` 的参数
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SummaryQuery {
+    /// 旧的参数，查这一天
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub t: Option<DateTime<Utc>>,
+    /// 北京时间的日期，两头都包含，`to` 不填时只查 `from` 这一天
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<NaiveDate>,
+    /// 不填时返回兼容的格式：分类 => 链接
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_by: Option<GroupBy>,
+}
+
+/// 兼容的汇总格式：分类 => 链接，视频是 BV 号，动态是链接
+pub type LegacySummary = BTreeMap<String, Vec<String>>;
+
+/// 汇总里的一条
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SummaryItem {
+    pub id: String,
+    pub kind: ItemKind,
+    /// 视频是 BV 号，动态是链接，和兼容模式一样
+    pub link: String,
+    pub title: String,
+    pub uploader_uid: Option<i64>,
+    pub uploader_name: String,
+    pub category: String,
+    pub published_at: DateTime<Utc>,
+    pub marker: Option<String>,
+    pub marked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SummaryGroup {
+    /// 日期（`2021-10-18`）、分类名或者 UP 主名字
+    pub key: String,
+    pub items: Vec<SummaryItem>,
+}
+
+/// 填了 `group_by` 时 `GET /summary` 的返回
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct GroupedSummary {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub group_by: GroupBy,
+    pub groups: Vec<SummaryGroup>,
+}
+
+/// `GET /kpi` 的参数
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct KpiQuery {
+    /// 旧的参数，查这一天，返回 名字 => 次数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub t: Option<DateTime<Utc>>,
+    /// 北京时间的日期，两头都包含，返回每个人的详细统计
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<NaiveDate>,
+}
+
+/// 旧的 KPI 格式
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct KpiEntry {
+    pub name: String,
+    pub times: u32,
+}
+
+/// 一个人在一段时间里的标记
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ReviewerKpi {
+    pub user_id: String,
+    pub name: String,
+    pub total: u32,
+    pub accepted: u32,
+    pub rejected: u32,
+    /// 分类 => 选入数
+    pub by_category: BTreeMap<String, u32>,
+    /// video / dynamic => 标记数
+    pub by_kind: BTreeMap<String, u32>,
+    /// 从推送到标记的时间，秒
+    pub avg_latency_secs: i64,
+    pub median_latency_secs: i64,
+}
+
+/// 填了 `from` 时 `GET /kpi` 的返回
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct KpiRange {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub reviewers: Vec<ReviewerKpi>,
+}
+
+/// webhook 的事件
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum WebhookEvent {
+    /// 选入了某个分类，重新分类也会触发
+    #[serde(rename = "item.categorized")]
+    ItemCategorized,
+    #[serde(rename = "item.rejected")]
+    ItemRejected,
+    /// 撤销了选入或者拒绝
+    #[serde(rename = "item.reset")]
+    ItemReset,
+}
+impl WebhookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::ItemCategorized => "item.categorized",
+            WebhookEvent::ItemRejected => "item.rejected",
+            WebhookEvent::ItemReset => "item.reset",
+        }
+    }
+}
+impl std::str::FromStr for WebhookEvent {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "item.categorized" => WebhookEvent::ItemCategorized,
+            "item.rejected" => WebhookEvent::ItemRejected,
+            "item.reset" => WebhookEvent::ItemReset,
+            _ => anyhow::bail!("unknown webhook event: {}", s),
+        })
+    }
+}
+
+/// webhook 发出去的 body
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    /// 事件发生的时间
+    pub timestamp: DateTime<Utc>,
+    /// 事件发生之后的条目
+    pub item: Item,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// 等待投递或者重试
+    Pending,
+    Delivered,
+    /// 重试次数用完了
+    Failed,
+}
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+impl std::str::FromStr for DeliveryStatus {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "pending" => DeliveryStatus::Pending,
+            "delivered" => DeliveryStatus::Delivered,
+            "failed" => DeliveryStatus::Failed,
+            _ => anyhow::bail!("unknown delivery status: {}", s),
+        })
+    }
+}
+
+/// 一次 webhook 投递
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    /// 配置里的 webhook 名字
+    pub webhook: String,
+    pub event: WebhookEvent,
+    pub item_id: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// 最后一次请求的 HTTP 状态码，请求没发出去时为空
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+fn default_delivery_limit() -> u32 {
+    50
+}
+
+/// `GET /webhooks/deliveries` 的参数，新的在前
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DeliveryQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<DeliveryStatus>,
+    /// 只返回 id 比这个小的，用来翻页
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<i64>,
+    /// 最多 200
+    #[serde(default = "default_delivery_limit")]
+    pub limit: u32,
+}
+impl Default for DeliveryQuery {
+    fn default() -> Self {
+        Self {
+            webhook: None,
+            status: None,
+            before: None,
+            limit: default_delivery_limit(),
+        }
+    }
+}
+
+/// `GET /events` 推送的事件类型，也是 SSE 里的 `event` 字段
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum EventKind {
+    /// 新的候选推送到了筛选群
+    #[serde(rename = "candidate.sent")]
+    CandidateSent,
+    #[serde(rename = "item.categorized")]
+    ItemCategorized,
+    #[serde(rename = "item.rejected")]
+    ItemRejected,
+    /// 撤销了选入或者拒绝
+    #[serde(rename = "item.reset")]
+    ItemReset,
+}
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::CandidateSent => "candidate.sent",
+            EventKind::ItemCategorized => "item.categorized",
+            EventKind::ItemRejected => "item.rejected",
+            EventKind::ItemReset => "item.reset",
+        }
+    }
+}
+impl std::str::FromStr for EventKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "candidate.sent" => EventKind::CandidateSent,
+            "item.categorized" => EventKind::ItemCategorized,
+            "item.rejected" => EventKind::ItemRejected,
+            "item.reset" => EventKind::ItemReset,
+            _ => anyhow::bail!("unknown event kind: {}", s),
+        })
+    }
+}
+
+/// `GET /events` 里每条 SSE 的 `data`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Event {
+    /// 递增，也是 SSE 里的 `id`，断线重连时放在 `Last-Event-ID` 里
+    pub id: i64,
+    pub kind: EventKind,
+    pub created_at: DateTime<Utc>,
+    /// 事件发生之后的条目
+    pub item: Item,
+}
+
+/// 重连时错过的事件太多或者已经清理掉了，`GET /events` 不补事件，先发一条 `reset`，
+/// 客户端要重新拉取全量，之后的事件从 `last_event_id` 接着发
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EventReset {
+    pub last_event_id: i64,
+}
+impl EventReset {
+    /// SSE 里的 `event` 字段
+    pub const KIND: &'static str = "reset";
+}
+
+/// 一项检查的结果
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Check {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// 一个后台任务的状态
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TaskHealth {
+    pub name: String,
+    /// 挂了正在等待重启的时候是 false
+    pub running: bool,
+    /// 最近有没有报告过，不检查的任务总是 true
+    pub fresh: bool,
+    /// 启动之后重启了几次
+    pub restarts: u32,
+    /// 这一次启动的时间
+    pub started_at: DateTime<Utc>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+/// `/healthz` 和 `/readyz` 的返回，`ok` 为 false 时状态码是 503
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Health {
+    pub ok: bool,
+    pub database: Check,
+    /// 飞书的 tenant_access_token
+    pub token: Check,
+    pub tasks: Vec<TaskHealth>,
+}
+
+/// 一个拉取来源的开关
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SourceState {
+    pub source: ItemKind,
+    pub paused: bool,
+}
+
+/// `/control` 的返回
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControlState {
+    /// 全部暂停，优先于各个来源的开关
+    pub paused: bool,
+    pub sources: Vec<SourceState>,
+}
+
+/// 暂停或者恢复
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PauseRequest {
+    pub paused: bool,
+}
@@ -0,0 +1,337 @@
+//! 从类型生成 OpenAPI 文档，服务端在 `/openapi.json` 返回
+use crate::*;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use serde_json::{json, Value};
+
+fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(gen.subschema_for::<T>()).expect("schema 一定能序列化")
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+fn path_param(name: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" },
+    })
+}
+
+/// 把参数结构体的字段展开成 query 参数
+fn query_params<T: JsonSchema>(gen: &mut SchemaGenerator) -> Vec<Value> {
+    gen.subschema_for::<T>();
+    let object = match gen.definitions_mut().remove(&T::schema_name()) {
+        Some(Schema::Object(schema)) => schema.object.expect("参数必须是结构体"),
+        _ => panic!("{} 不是结构体", T::schema_name()),
+    };
+    let required = object.required;
+    object
+        .properties
+        .into_iter()
+        .map(|(name, schema)| {
+            json!({
+                "name": name,
+                "in": "query",
+                "required": required.contains(&name),
+                "schema": schema,
+            })
+        })
+        .collect()
+}
+
+/// 一个接口，出错时都返回 [`ErrorBody`]
+fn operation(
+    gen: &mut SchemaGenerator,
+    summary: &str,
+    parameters: Vec<Value>,
+    request: Option<Value>,
+    response: Value,
+) -> Value {
+    let mut op = json!({
+        "summary": summary,
+        "parameters": parameters,
+        "responses": {
+            "200": { "description": "OK", "content": json_content(response) },
+            "default": {
+                "description": "出错",
+                "content": json_content(schema::<ErrorBody>(gen)),
+            },
+        },
+    });
+    if let Some(request) = request {
+        op["requestBody"] = json!({ "required": true, "content": json_content(request) });
+    }
+    op
+}
+
+pub fn openapi() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let g = &mut gen;
+    let ok = json!({ "type": "object", "properties": { "msg": { "type": "string" } } });
+
+    let list_categories = schema::<Vec<Category>>(g);
+    let new_category = schema::<NewCategory>(g);
+    let category = schema::<Category>(g);
+    let category_patch = schema::<CategoryPatch>(g);
+    let summary_params = query_params::<SummaryQuery>(g);
+    let summary = json!({
+        "oneOf": [schema::<LegacySummary>(g), schema::<GroupedSummary>(g)],
+    });
+    let kpi_params = query_params::<KpiQuery>(g);
+    let kpi = json!({
+        "oneOf": [schema::<Vec<KpiEntry>>(g), schema::<KpiRange>(g)],
+    });
+    let item_params = query_params::<ItemQuery>(g);
+    let item_page = schema::<ItemPage>(g);
+    let review = schema::<ReviewRequest>(g);
+    let item = schema::<Item>(g);
+    let delivery_params = query_params::<DeliveryQuery>(g);
+    let deliveries = schema::<Vec<WebhookDelivery>>(g);
+    // 不是接口的返回，放进 components 给 webhook 的接收方参考
+    schema::<WebhookPayload>(g);
+    let event = schema::<Event>(g);
+    schema::<EventReset>(g);
+    let events = json!({
+        "summary": "事件流（SSE），断线重连时用 Last-Event-ID 从数据库补上错过的事件，\
+                    错过的太多时发一条 reset（EventReset），客户端要重新拉取",
+        "parameters": [
+            {
+                "name": "Last-Event-ID",
+                "in": "header",
+                "required": false,
+                "schema": { "type": "integer" },
+            },
+            {
+                "name": "last_event_id",
+                "in": "query",
+                "required": false,
+                "description": "和 Last-Event-ID 一样，给不能设置请求头的客户端用",
+                "schema": { "type": "integer" },
+            },
+            {
+                "name": "token",
+                "in": "query",
+                "required": false,
+                "description": "和 Authorization 一样，给不能设置请求头的客户端用",
+                "schema": { "type": "string" },
+            },
+        ],
+        "responses": {
+            "200": {
+                "description": "每条事件的 event 是事件类型，data 是 json",
+                "content": { "text/event-stream": { "schema": event } },
+            },
+        },
+    });
+    let health = schema::<Health>(g);
+    let health_check = |summary: &str| {
+        json!({
+            "summary": summary,
+            "responses": {
+                "200": { "description": "正常", "content": json_content(health.clone()) },
+                "503": { "description": "不正常", "content": json_content(health.clone()) },
+            },
+        })
+    };
+
+    let control_state = schema::<ControlState>(g);
+    let pause = schema::<PauseRequest>(g);
+    let source_param = json!({
+        "name": "source",
+        "in": "path",
+        "required": true,
+        "schema": schema::<ItemKind>(g),
+    });
+    // 控制接口要带 admin token
+    let mut control = json!({
+        "get": operation(g, "拉取的开关", vec![], None, control_state.clone()),
+        "put": operation(g, "全部暂停或者恢复", vec![], Some(pause.clone()), control_state.clone()),
+    });
+    let mut control_source = json!({
+        "put": operation(
+            g,
+            "暂停或者恢复一个来源",
+            vec![source_param.clone()],
+            Some(pause),
+            control_state.clone(),
+        ),
+    });
+    let mut control_run = json!({
+        "post": operation(
+            g,
+            "马上拉取一轮，来源暂停时返回 409",
+            vec![source_param],
+            None,
+            control_state,
+        ),
+    });
+    for path in [&mut control, &mut control_source, &mut control_run].iter_mut() {
+        for op in path.as_object_mut().unwrap().values_mut() {
+            op["security"] = json!([{ "admin_token": [] }]);
+        }
+    }
+
+    let mut paths = json!({
+        "/categories": {
+            "get": operation(g, "列出所有分类", vec![], None, list_categories),
+            "post": operation(g, "新建分类", vec![], Some(new_category), category.clone()),
+        },
+        "/categories/{name}": {
+            "patch": operation(
+                g,
+                "修改分类，只修改填了的字段",
+                vec![path_param("name")],
+                Some(category_patch),
+                category,
+            ),
+            "delete": operation(g, "删除分类", vec![path_param("name")], None, ok),
+        },
+        "/summary": {
+            "get": operation(
+                g,
+                "已分类的条目汇总，不填 group_by 时返回 分类 => 链接",
+                summary_params,
+                None,
+                summary,
+            ),
+        },
+        "/kpi": {
+            "get": operation(
+                g,
+                "标记统计，用 t 时返回旧的格式",
+                kpi_params,
+                None,
+                kpi,
+            ),
+        },
+        "/items": {
+            "get": operation(g, "按条件列出条目", item_params, None, item_page),
+        },
+        "/items/{id}/review": {
+            "post": operation(
+                g,
+                "筛选一条，会同步更新飞书里的卡片",
+                vec![path_param("id")],
+                Some(review),
+                item,
+            ),
+        },
+        "/events": { "get": events },
+        "/control": control,
+        "/control/{source}": control_source,
+        "/control/{source}/run": control_run,
+        "/healthz": {
+            "get": health_check("存活检查：数据库能连上，后台任务都在运行"),
+        },
+        "/readyz": {
+            "get": health_check("就绪检查：另外要求飞书 token 有效、后台任务最近报告过"),
+        },
+        "/webhooks/deliveries": {
+            "get": operation(
+                g,
+                "webhook 的投递记录，新的在前",
+                delivery_params,
+                None,
+                deliveries,
+            ),
+        },
+    });
+
+    // 网页用的接口也要带 token，筛选的人用各自的 token
+    // 列出分类和 /summary 一样不用 token，生成专栏时要用
+    paths["/categories"]["post"]["security"] = json!([{ "admin_token": [] }]);
+    for path in [
+        "/categories/{name}",
+        "/items",
+        "/items/{id}/review",
+        "/events",
+        "/webhooks/deliveries",
+    ]
+    .iter()
+    {
+        for op in paths[path].as_object_mut().unwrap().values_mut() {
+            op["security"] = json!([{ "admin_token": [] }]);
+        }
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "A-SOUL 周报",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": gen.take_definitions(),
+            "securitySchemes": {
+                "admin_token": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn refs(value: &Value, ans: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                for (k, v) in map {
+                    match v {
+                        Value::String(s) if k == "$ref" => ans.push(s.clone()),
+                        _ => refs(v, ans),
+                    }
+                }
+            }
+            Value::Array(array) => array.iter().for_each(|v| refs(v, ans)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_openapi() {
+        let doc = openapi();
+        let schemas = &doc["components"]["schemas"];
+        for r in [
+            "Category",
+            "Item",
+            "ItemPage",
+            "ErrorBody",
+            "ReviewerKpi",
+            "WebhookPayload",
+            "Event",
+            "EventReset",
+            "Health",
+        ]
+        .iter()
+        {
+            assert!(schemas.get(r).is_some(), "缺少 {}", r);
+        }
+        // 参数结构体展开了，不出现在 components 里
+        assert!(schemas.get("ItemQuery").is_none());
+
+        let params = doc["paths"]["/items"]["get"]["parameters"]
+            .as_array()
+            .unwrap();
+        let names: Vec<_> = params.iter().map(|p| p["name"].as_str().unwrap()).collect();
+        assert!(names.contains(&"cursor") && names.contains(&"q"));
+        assert!(params.iter().all(|p| p["required"] == false));
+        assert!(doc["paths"]["/items"]["get"]["security"].is_array());
+        assert!(doc["paths"]["/summary"]["get"]["security"].is_null());
+        assert!(doc["paths"]["/categories"]["get"]["security"].is_null());
+        assert!(doc["paths"]["/categories"]["post"]["security"].is_array());
+
+        let mut all = vec![];
+        refs(&doc, &mut all);
+        assert!(!all.is_empty());
+        for r in all {
+            let name = r.trim_start_matches("#/components/schemas/");
+            assert!(schemas.get(name).is_some(), "{} 不存在", r);
+        }
+    }
+}
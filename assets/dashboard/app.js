@@ -0,0 +1,251 @@
+// 筛选网页，接口和飞书卡片用的是同一套
+'use strict';
+
+const $ = (id) => document.getElementById(id);
+
+const state = {
+    tab: 'pending',
+    categories: [],
+    items: [],
+    events: null,
+};
+
+function today() {
+    // 北京时间的今天
+    const now = new Date(Date.now() + 8 * 3600 * 1000);
+    return now.toISOString().slice(0, 10);
+}
+
+function dayRange(date) {
+    const from = new Date(`${date}T00:00:00+08:00`);
+    const to = new Date(from.getTime() + 24 * 3600 * 1000);
+    return [from.toISOString(), to.toISOString()];
+}
+
+function setStatus(text) {
+    $('status').textContent = text;
+}
+
+async function api(method, url, body) {
+    const headers = { Authorization: `Bearer ${$('token').value.trim()}` };
+    if (body) headers['Content-Type'] = 'application/json';
+    const r = await fetch(url, {
+        method,
+        headers,
+        body: body ? JSON.stringify(body) : undefined,
+    });
+    const data = await r.json();
+    if (!r.ok) {
+        throw new Error(data.error || r.statusText);
+    }
+    return data;
+}
+
+function query(params) {
+    return Object.entries(params)
+        .filter(([, v]) => v !== undefined && v !== null)
+        .map(([k, v]) => `${k}=${encodeURIComponent(v)}`)
+        .join('&');
+}
+
+function link(item) {
+    return item.kind === 'video'
+        ? `https://www.bilibili.com/video/${item.id}`
+        : `https://t.bilibili.com/${item.id}`;
+}
+
+function el(tag, attrs = {}, children = []) {
+    const e = document.createElement(tag);
+    for (const [k, v] of Object.entries(attrs)) {
+        if (k === 'onclick') {
+            e.addEventListener('click', v);
+        } else {
+            e.setAttribute(k, v);
+        }
+    }
+    for (const child of [].concat(children)) {
+        e.append(child);
+    }
+    return e;
+}
+
+async function loadCategories() {
+    state.categories = await api('GET', '/categories');
+}
+
+async function loadItems(date) {
+    const [from, to] = dayRange(date);
+    const items = [];
+    let cursor = null;
+    do {
+        const page = await api('GET', '/items?' + query({
+            published_from: from,
+            published_to: to,
+            sort: 'published_at',
+            order: 'asc',
+            limit: 100,
+            cursor,
+        }));
+        items.push(...page.items);
+        cursor = page.next_cursor;
+    } while (cursor);
+    state.items = items;
+}
+
+function itemState(item) {
+    if (item.category) return 'accepted';
+    if (item.marker) return 'rejected';
+    return 'pending';
+}
+
+async function review(item, body) {
+    try {
+        const updated = await api('POST', `/items/${item.id}/review`, body);
+        Object.assign(item, updated);
+        renderItems();
+    } catch (e) {
+        alert(`操作失败：${e.message}`);
+    }
+}
+
+function actions(item) {
+    if (itemState(item) !== 'pending') {
+        const text = item.category ? `已选入：${item.category}` : '已拒绝';
+        return [
+            el('span', {}, text),
+            el('button', { onclick: () => review(item, { action: 'undo' }) }, '撤销'),
+        ];
+    }
+    const options = state.categories.filter((c) => c.active && c.kind === item.kind);
+    const select = el('select', {}, options.map((c) => el('option', { value: c.name }, c.name)));
+    return [
+        select,
+        el('button', {
+            onclick: () => review(item, { action: 'categorize', category: select.value }),
+        }, '选入'),
+        el('button', { onclick: () => review(item, { action: 'reject' }) }, '拒绝'),
+    ];
+}
+
+function renderItems() {
+    const items = state.items.filter((i) => itemState(i) === state.tab);
+    const container = $('items');
+    container.replaceChildren(...items.map((item) => {
+        const time = new Date(item.published_at).toLocaleString('zh-CN', { timeZone: 'Asia/Shanghai' });
+        const views = item.view_count == null ? '' : ` · 播放 ${item.view_count}`;
+        const meta = item.kind === 'video'
+            ? `${item.uploader_name}${views} · ${time}`
+            : `${item.uploader_name} · ${item.picture_count || 0} 张图 · ${time}`;
+        return el('div', { class: 'item' }, [
+            el('img', { src: item.cover_url, loading: 'lazy', alt: '' }),
+            el('div', { class: 'body' }, [
+                el('a', { class: 'title', href: link(item), target: '_blank' }, item.title || item.id),
+                el('div', { class: 'meta' }, meta),
+            ]),
+            el('div', { class: 'actions' }, actions(item)),
+        ]);
+    }));
+    setStatus(`${items.length} 条`);
+}
+
+async function renderKpi(date) {
+    const t = new Date(`${date}T12:00:00+08:00`).toISOString();
+    const kpi = await api('GET', '/kpi?' + query({ t }));
+    const max = Math.max(1, ...kpi.map((k) => k.times));
+    $('kpi').replaceChildren(...kpi.map((k) => el('div', { class: 'bar' }, [
+        el('span', { class: 'name' }, k.name),
+        el('span', { class: 'fill', style: `width: ${(k.times / max) * 60}%` }),
+        el('span', {}, String(k.times)),
+    ])));
+    setStatus(kpi.length ? '' : '还没有人标记');
+}
+
+async function renderReport(date) {
+    const summary = await api('GET', '/summary?' + query({ from: date, group_by: 'category' }));
+    const title = (name) => {
+        const c = state.categories.find((c) => c.name === name);
+        return (c && c.section_title) || name;
+    };
+    $('report').replaceChildren(...summary.groups.map((g) => el('div', {}, [
+        el('h3', {}, `${title(g.key)}（${g.items.length}）`),
+        el('ul', {}, g.items.map((item) => el('li', {}, [
+            el('a', { href: link(item), target: '_blank' }, item.title || item.id),
+            ` — ${item.uploader_name}`,
+        ]))),
+    ])));
+    setStatus(summary.groups.length ? '' : '还没有分类的内容');
+}
+
+async function refresh() {
+    const date = $('date').value;
+    const tab = state.tab;
+    $('items').hidden = !['pending', 'accepted', 'rejected'].includes(tab);
+    $('kpi').hidden = tab !== 'kpi';
+    $('report').hidden = tab !== 'report';
+    setStatus('加载中…');
+    try {
+        if (tab === 'kpi') {
+            await renderKpi(date);
+        } else if (tab === 'report') {
+            await renderReport(date);
+        } else {
+            await loadItems(date);
+            renderItems();
+        }
+    } catch (e) {
+        setStatus(`加载失败：${e.message}`);
+    }
+}
+
+// 新推送的和别处（比如飞书卡片）筛选的，实时更新到列表里
+function onEvent(e) {
+    const item = JSON.parse(e.data).item;
+    const existing = state.items.find((i) => i.id === item.id);
+    if (existing) {
+        Object.assign(existing, item);
+    } else {
+        const [from, to] = dayRange($('date').value).map((t) => new Date(t));
+        const published = new Date(item.published_at);
+        if (published < from || published >= to) return;
+        state.items.push(item);
+        state.items.sort((a, b) => new Date(a.published_at) - new Date(b.published_at));
+    }
+    if (!$('items').hidden) renderItems();
+}
+
+// EventSource 不能设置请求头，token 放在参数里；断线后浏览器会带着 Last-Event-ID 自动重连
+function connectEvents() {
+    if (state.events) state.events.close();
+    const token = encodeURIComponent($('token').value.trim());
+    const events = new EventSource(`/events?token=${token}`);
+    for (const kind of ['candidate.sent', 'item.categorized', 'item.rejected', 'item.reset']) {
+        events.addEventListener(kind, onEvent);
+    }
+    // 错过的太多，补不全，重新拉取
+    events.addEventListener('reset', refresh);
+    state.events = events;
+}
+
+async function main() {
+    $('date').value = today();
+    $('token').value = localStorage.getItem('token') || '';
+    $('token').addEventListener('change', (e) => {
+        localStorage.setItem('token', e.target.value.trim());
+        connectEvents();
+        refresh();
+    });
+    $('date').addEventListener('change', refresh);
+    for (const button of document.querySelectorAll('nav button')) {
+        button.addEventListener('click', () => {
+            document.querySelectorAll('nav button').forEach((b) => b.classList.remove('active'));
+            button.classList.add('active');
+            state.tab = button.dataset.tab;
+            refresh();
+        });
+    }
+    await loadCategories();
+    await refresh();
+    connectEvents();
+}
+
+main();
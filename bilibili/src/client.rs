@@ -0,0 +1,411 @@
+//! 带限流和熔断的客户端，请求头和 cookie 可以配置
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, COOKIE, USER_AGENT};
+use reqwest::{StatusCode, Url};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, OnceCell};
+use tokio::time::Instant;
+
+use crate::error::RISK_CONTROL_CODES;
+use crate::limiter::{LimitConfig, Limiter};
+use crate::wbi::{self, WbiKeys};
+use crate::Error;
+
+/// 没有配置 buvid3 的时候从这里拿一个
+const SPI_URL: &str = "https://api.bilibili.com/x/frontend/finger/spi";
+/// WBI 的 key 每天换，缓存一个小时
+const WBI_KEYS_TTL: Duration = Duration::from_secs(60 * 60);
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/94.0.4606.81 Safari/537.36";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientConfig {
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    /// buvid3 cookie，不填的话普通接口不带，要 WBI 签名的接口会自动拿一个
+    #[serde(default)]
+    pub buvid3: Option<String>,
+    /// 其他的 cookie，cookie 名 => 值
+    #[serde(default)]
+    pub cookies: BTreeMap<String, String>,
+    /// 额外的请求头，比如 Referer
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// 限流和熔断
+    #[serde(default)]
+    pub limit: LimitConfig,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: default_user_agent(),
+            buvid3: None,
+            cookies: BTreeMap::new(),
+            headers: BTreeMap::new(),
+            limit: LimitConfig::default(),
+        }
+    }
+}
+
+fn default_user_agent() -> String {
+    DEFAULT_USER_AGENT.to_string()
+}
+
+impl ClientConfig {
+    fn cookie(&self, buvid3: Option<&str>) -> Option<String> {
+        let cookies: Vec<_> = buvid3
+            .iter()
+            .map(|v| ("buvid3", *v))
+            .chain(self.cookies.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        Some(cookies.join("; ")).filter(|c| !c.is_empty())
+    }
+
+    fn header_map(&self) -> Result<HeaderMap, Error> {
+        let value = |v: &str| {
+            HeaderValue::from_str(v).map_err(|_| Error::Config(format!("请求头的值不合法：{}", v)))
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, value(&self.user_agent)?);
+        if let Some(cookie) = self.cookie(self.buvid3.as_deref()) {
+            headers.insert(COOKIE, value(&cookie)?);
+        }
+        for (name, v) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::Config(format!("请求头的名字不合法：{}", name)))?;
+            headers.insert(name, value(v)?);
+        }
+        Ok(headers)
+    }
+}
+
+/// 哔哩哔哩接口的返回
+#[derive(Debug, Deserialize)]
+pub(crate) struct Response<T> {
+    pub code: i64,
+    #[serde(default)]
+    pub message: String,
+    pub data: Option<T>,
+}
+
+impl<T> Response<T> {
+    fn into_data(self) -> Result<T, Error> {
+        if self.code != 0 {
+            return Err(Error::Api {
+                code: self.code,
+                message: self.message,
+            });
+        }
+        self.data.ok_or_else(|| Error::Api {
+            code: 0,
+            message: "返回里没有 data".to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Spi {
+    b_3: String,
+}
+
+/// 所有请求共用一个限流器和 WBI 的 key，clone 出来的也是同一个
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    config: Arc<ClientConfig>,
+    limiter: Arc<Limiter>,
+    buvid3: Arc<OnceCell<String>>,
+    wbi_keys: Arc<Mutex<Option<(WbiKeys, Instant)>>>,
+}
+
+fn parse_url(url: &str) -> Result<Url, Error> {
+    Url::parse(url).map_err(|e| Error::Config(format!("url {}: {}", url, e)))
+}
+
+impl Client {
+    pub fn new(config: &ClientConfig) -> Result<Self, Error> {
+        let http = reqwest::Client::builder()
+            .default_headers(config.header_map()?)
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        let buvid3 = OnceCell::new();
+        if let Some(v) = &config.buvid3 {
+            let _ = buvid3.set(v.clone());
+        }
+        Ok(Self {
+            http,
+            config: Arc::new(config.clone()),
+            limiter: Arc::new(Limiter::new(config.limit.clone())),
+            buvid3: Arc::new(buvid3),
+            wbi_keys: Default::default(),
+        })
+    }
+
+    /// 请求返回 `{code, message, data}` 的接口，触发风控之后这个域名会熔断一段时间
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        query: &impl Serialize,
+    ) -> Result<T, Error> {
+        self.request(parse_url(url)?, query, None)
+            .await?
+            .into_data()
+    }
+
+    /// 请求要 WBI 签名的接口，自动带上 `wts`、`w_rid` 和 buvid3 cookie，`url` 里不要带 query
+    pub async fn get_wbi<T, K, V>(&self, url: &str, params: &[(K, V)]) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut url = parse_url(url)?;
+        let keys = self.wbi_keys().await?;
+        let cookie = self.config.cookie(Some(self.buvid3().await?));
+        url.set_query(Some(&keys.sign(params, chrono::Utc::now().timestamp())));
+        let r = self.request(url, &(), cookie.as_deref()).await;
+        if matches!(r, Err(Error::Throttled { code: Some(_), .. })) {
+            // -352 有可能是 key 换了，下次重新拿
+            *self.wbi_keys.lock().await = None;
+        }
+        r?.into_data()
+    }
+
+    /// 配置的 buvid3，没有配置的话从 spi 接口拿一个
+    async fn buvid3(&self) -> Result<&str, Error> {
+        let buvid3 = self
+            .buvid3
+            .get_or_try_init(|| async {
+                let spi: Spi = self.get(SPI_URL, &()).await?;
+                debug!("获取到 buvid3: {}", spi.b_3);
+                Ok::<_, Error>(spi.b_3)
+            })
+            .await?;
+        Ok(buvid3.as_str())
+    }
+
+    async fn wbi_keys(&self) -> Result<WbiKeys, Error> {
+        let mut cache = self.wbi_keys.lock().await;
+        if let Some((keys, fetched_at)) = &*cache {
+            if fetched_at.elapsed() < WBI_KEYS_TTL {
+                return Ok(keys.clone());
+            }
+        }
+        let nav = self
+            .request::<wbi::Nav>(parse_url(wbi::NAV_URL)?, &(), None)
+            .await?;
+        // 没登录的时候 code 是 -101，但是 data 里照样有 key
+        let keys = match &nav.data {
+            Some(data) => WbiKeys::from_nav(data),
+            None => {
+                return Err(Error::Api {
+                    code: nav.code,
+                    message: nav.message,
+                })
+            }
+        };
+        debug!("获取到 WBI key: {:?}", keys);
+        *cache = Some((keys.clone(), Instant::now()));
+        Ok(keys)
+    }
+
+    /// 经过限流和熔断，不检查 code，只把风控的 code 当成错误
+    async fn request<T: DeserializeOwned>(
+        &self,
+        url: Url,
+        query: &impl Serialize,
+        cookie: Option<&str>,
+    ) -> Result<Response<T>, Error> {
+        let host = url.host_str().unwrap_or_default().to_string();
+        self.limiter.acquire(&host).await?;
+        match self.request_once(url, &host, query, cookie).await {
+            Ok(response) => {
+                self.limiter.reset(&host);
+                Ok(response)
+            }
+            Err(Error::Throttled { host, code, .. }) => {
+                let retry_after = self.limiter.trip(&host);
+                Err(Error::Throttled {
+                    host,
+                    code,
+                    retry_after,
+                })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn request_once<T: DeserializeOwned>(
+        &self,
+        url: Url,
+        host: &str,
+        query: &impl Serialize,
+        cookie: Option<&str>,
+    ) -> Result<Response<T>, Error> {
+        let mut request = self.http.get(url).query(query);
+        if let Some(cookie) = cookie {
+            request = request.header(COOKIE, cookie);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        // 风控的时候有时直接返回 412，body 是一个 html
+        if status == StatusCode::PRECONDITION_FAILED {
+            return Err(Error::throttled(host, -412));
+        }
+        if !status.is_success() {
+            return Err(Error::Status { status, body });
+        }
+        let response: Response<T> = serde_json::from_str(&body)?;
+        if RISK_CONTROL_CODES.contains(&response.code) {
+            return Err(Error::throttled(host, response.code));
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{json, Value};
+    use wiremock::matchers::{header, header_exists, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn client() -> Client {
+        let config: ClientConfig = serde_json::from_value(json!({
+            "buvid3": "abc",
+            "cookies": { "SESSDATA": "xyz" },
+            "headers": { "Referer": "https://www.bilibili.com/" },
+            "limit": { "backoff_min_secs": 60 },
+        }))
+        .unwrap();
+        Client::new(&config).unwrap()
+    }
+
+    fn body(code: i64) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "code": code,
+            "message": "",
+            "data": { "n": 1 },
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/x/ok"))
+            .and(query_param("tag_id", "1"))
+            .and(header("cookie", "buvid3=abc; SESSDATA=xyz"))
+            .and(header("referer", "https://www.bilibili.com/"))
+            // UA 里有逗号，wiremock 会按逗号拆开，只检查有没有
+            .and(header_exists("user-agent"))
+            .respond_with(body(0))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let data: Value = client()
+            .get(&format!("{}/x/ok", server.uri()), &[("tag_id", 1)])
+            .await
+            .unwrap();
+        assert_eq!(data["n"], 1);
+        let headers = ClientConfig::default().header_map().unwrap();
+        assert_eq!(headers[USER_AGENT], DEFAULT_USER_AGENT);
+    }
+
+    #[tokio::test]
+    async fn test_throttled() {
+        let server = MockServer::start().await;
+        Mock::given(path("/x/not_found"))
+            .respond_with(body(-404))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(path("/x/risk"))
+            .respond_with(body(-412))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = client();
+        let not_found = format!("{}/x/not_found", server.uri());
+
+        // 其他错误不会熔断
+        let e = client.get::<Value>(&not_found, &()).await.unwrap_err();
+        assert_eq!(e.kind(), "api");
+        assert!(client.get::<Value>(&not_found, &()).await.is_err());
+
+        let e = client
+            .get::<Value>(&format!("{}/x/risk", server.uri()), &())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e,
+            Error::Throttled {
+                code: Some(-412),
+                ..
+            }
+        ));
+        assert_eq!(e.retry_after(), Some(Duration::from_secs(60)));
+
+        // 熔断中，同一个域名的请求直接失败
+        let e = client.get::<Value>(&not_found, &()).await.unwrap_err();
+        assert!(matches!(e, Error::Throttled { code: None, .. }));
+        assert_eq!(e.kind(), "throttled");
+    }
+
+    #[tokio::test]
+    async fn test_wbi() {
+        let server = MockServer::start().await;
+        let signed = |req: &wiremock::Request| req.url.query_pairs().any(|(k, _)| k == "w_rid");
+        Mock::given(path("/x/space/wbi/ok"))
+            .and(query_param("mid", "1"))
+            .and(signed)
+            .and(header("cookie", "buvid3=abc; SESSDATA=xyz"))
+            .respond_with(body(0))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/x/space/wbi/risk"))
+            .respond_with(body(-352))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = client();
+        let keys = WbiKeys {
+            img_key: "7cd084941338484aae1ad9425b84077c".to_string(),
+            sub_key: "4932caff0ff746eab6f01bf08b70ac45".to_string(),
+        };
+        *client.wbi_keys.lock().await = Some((keys, Instant::now()));
+
+        let data: Value = client
+            .get_wbi(&format!("{}/x/space/wbi/ok", server.uri()), &[("mid", "1")])
+            .await
+            .unwrap();
+        assert_eq!(data["n"], 1);
+        assert!(client.wbi_keys.lock().await.is_some());
+
+        // 风控之后 key 要重新拿
+        let e = client
+            .get_wbi::<Value, _, _>(
+                &format!("{}/x/space/wbi/risk", server.uri()),
+                &[("mid", "1")],
+            )
+            .await
+            .unwrap_err();
+        assert!(e.is_throttled());
+        assert!(client.wbi_keys.lock().await.is_none());
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let config: ClientConfig = serde_json::from_value(json!({
+            "headers": { "bad header": "x" },
+        }))
+        .unwrap();
+        assert_eq!(Client::new(&config).err().unwrap().kind(), "config");
+    }
+}
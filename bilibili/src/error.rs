@@ -0,0 +1,112 @@
+//! 哔哩哔哩接口的错误类型
+use reqwest::StatusCode;
+use std::{fmt, time::Duration};
+
+/// 触发了风控：-412 请求被拦截，-352 风控校验失败
+pub const RISK_CONTROL_CODES: [i64; 2] = [-412, -352];
+
+#[derive(Debug)]
+pub enum Error {
+    /// 被风控了，或者熔断还没有结束，这段时间内请求这个域名会直接失败
+    Throttled {
+        host: String,
+        /// 触发风控的错误码，熔断中直接失败的时候为 None
+        code: Option<i64>,
+        retry_after: Duration,
+    },
+    /// 哔哩哔哩返回了非 0 的 code
+    Api { code: i64, message: String },
+    /// 非 2xx 的状态码
+    Status { status: StatusCode, body: String },
+    /// 网络错误
+    Transport(reqwest::Error),
+    /// 返回的 body 无法解析
+    Decode(serde_json::Error),
+    /// 配置的请求头或者 cookie 不合法
+    Config(String),
+}
+
+impl Error {
+    /// 触发了风控，`retry_after` 由熔断填上
+    pub(crate) fn throttled(host: &str, code: i64) -> Self {
+        Self::Throttled {
+            host: host.to_string(),
+            code: Some(code),
+            retry_after: Duration::ZERO,
+        }
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        matches!(self, Self::Throttled { .. })
+    }
+
+    /// 被风控时还要等多久才能再请求
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Throttled { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// 指标里的错误类型
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Throttled { .. } => "throttled",
+            Self::Api { .. } => "api",
+            Self::Status { .. } => "status",
+            Self::Transport(_) => "transport",
+            Self::Decode(_) => "decode",
+            Self::Config(_) => "config",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Throttled {
+                host,
+                code: Some(code),
+                retry_after,
+            } => write!(
+                f,
+                "Bilibili risk control ({}) on {}, retry after {:?}",
+                code, host, retry_after
+            ),
+            Self::Throttled {
+                host, retry_after, ..
+            } => write!(
+                f,
+                "Bilibili requests to {} are throttled, retry after {:?}",
+                host, retry_after
+            ),
+            Self::Api { code, message } => write!(f, "Bilibili error ({}) {}", code, message),
+            Self::Status { status, body } => write!(f, "Bilibili HTTP error {}: {}", status, body),
+            Self::Transport(e) => write!(f, "Bilibili request failed: {}", e),
+            Self::Decode(e) => write!(f, "Bilibili response decode failed: {}", e),
+            Self::Config(e) => write!(f, "Invalid bilibili client config: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(e) => Some(e),
+            Self::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Transport(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Decode(e)
+    }
+}
@@ -2,8 +2,15 @@
 
 #[macro_use]
 extern crate serde;
-// #[macro_use]
-// extern crate log;
+#[macro_use]
+extern crate log;
 
+pub mod client;
+pub mod error;
+pub mod limiter;
 pub mod tag_feed;
 pub mod tag_videos;
+pub mod wbi;
+
+pub use client::{Client, ClientConfig};
+pub use error::Error;
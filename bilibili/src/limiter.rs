@@ -0,0 +1,220 @@
+//! 按域名限流：令牌桶控制请求频率，触发风控之后熔断一段时间，熔断时间按次数翻倍
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::Error;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LimitConfig {
+    /// 每个域名每秒最多请求几次
+    #[serde(default = "default_rate")]
+    pub rate_per_sec: f64,
+    /// 允许连续请求的次数
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+    /// 第一次触发风控之后熔断多久，之后每次翻倍
+    #[serde(default = "default_backoff_min_secs")]
+    pub backoff_min_secs: u64,
+    #[serde(default = "default_backoff_max_secs")]
+    pub backoff_max_secs: u64,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        Self {
+            rate_per_sec: default_rate(),
+            burst: default_burst(),
+            backoff_min_secs: default_backoff_min_secs(),
+            backoff_max_secs: default_backoff_max_secs(),
+        }
+    }
+}
+
+fn default_rate() -> f64 {
+    1.
+}
+
+fn default_burst() -> u32 {
+    3
+}
+
+fn default_backoff_min_secs() -> u64 {
+    5 * 60
+}
+
+fn default_backoff_max_secs() -> u64 {
+    2 * 60 * 60
+}
+
+struct TokenBucket {
+    /// 可以是负数，表示已经预约了之后的令牌
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// 取一个令牌，返回要等多久才能用
+    fn reserve(&mut self, rate: f64, burst: u32, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst as f64);
+        self.updated = now;
+        self.tokens -= 1.;
+        if self.tokens >= 0. {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+#[derive(Default)]
+struct Breaker {
+    /// 连续触发风控的次数，请求成功一次就清零
+    trips: u32,
+    open_until: Option<Instant>,
+}
+
+impl Breaker {
+    /// 熔断中的话返回还剩多久
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.open_until
+            .filter(|t| *t > now)
+            .map(|t| t.duration_since(now))
+    }
+
+    fn trip(&mut self, min: Duration, max: Duration, now: Instant) -> Duration {
+        self.trips += 1;
+        let backoff = min
+            .checked_mul(1 << (self.trips - 1).min(16))
+            .unwrap_or(max)
+            .min(max);
+        self.open_until = Some(now + backoff);
+        backoff
+    }
+
+    fn reset(&mut self) {
+        self.trips = 0;
+        self.open_until = None;
+    }
+}
+
+struct Host {
+    bucket: TokenBucket,
+    breaker: Breaker,
+}
+
+pub struct Limiter {
+    config: LimitConfig,
+    hosts: Mutex<HashMap<String, Host>>,
+}
+
+impl Limiter {
+    pub fn new(config: LimitConfig) -> Self {
+        Self {
+            config,
+            hosts: Default::default(),
+        }
+    }
+
+    fn with_host<T>(&self, host: &str, f: impl FnOnce(&mut Host, Instant) -> T) -> T {
+        let now = Instant::now();
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts.entry(host.to_string()).or_insert_with(|| Host {
+            bucket: TokenBucket {
+                tokens: self.config.burst as f64,
+                updated: now,
+            },
+            breaker: Breaker::default(),
+        });
+        f(host, now)
+    }
+
+    /// 等到可以请求这个域名，熔断中直接返回 [`Error::Throttled`]
+    pub async fn acquire(&self, host: &str) -> Result<(), Error> {
+        // 配成 0 的时候当成很慢，不要除以 0
+        let rate = self.config.rate_per_sec.max(0.001);
+        let burst = self.config.burst.max(1);
+        let wait = self.with_host(host, |h, now| match h.breaker.remaining(now) {
+            Some(retry_after) => Err(retry_after),
+            None => Ok(h.bucket.reserve(rate, burst, now)),
+        });
+        match wait {
+            Ok(wait) => {
+                if wait > Duration::ZERO {
+                    tokio::time::sleep(wait).await;
+                }
+                Ok(())
+            }
+            Err(retry_after) => Err(Error::Throttled {
+                host: host.to_string(),
+                code: None,
+                retry_after,
+            }),
+        }
+    }
+
+    /// 触发了风控，返回熔断多久
+    pub fn trip(&self, host: &str) -> Duration {
+        let min = Duration::from_secs(self.config.backoff_min_secs);
+        let max = Duration::from_secs(self.config.backoff_max_secs);
+        let backoff = self.with_host(host, |h, now| h.breaker.trip(min, max, now));
+        warn!("{} 触发了风控，熔断 {:?}", host, backoff);
+        backoff
+    }
+
+    pub fn reset(&self, host: &str) {
+        self.with_host(host, |h, _| h.breaker.reset());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> LimitConfig {
+        LimitConfig {
+            rate_per_sec: 2.,
+            burst: 2,
+            backoff_min_secs: 60,
+            backoff_max_secs: 150,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bucket() {
+        let limiter = Limiter::new(config());
+        let start = Instant::now();
+        limiter.acquire("a.com").await.unwrap();
+        limiter.acquire("a.com").await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        // 桶空了，每秒两个
+        limiter.acquire("a.com").await.unwrap();
+        limiter.acquire("a.com").await.unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_secs(1) && elapsed < Duration::from_millis(1100));
+        // 不同的域名分开算
+        limiter.acquire("b.com").await.unwrap();
+        assert_eq!(start.elapsed(), elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker() {
+        let limiter = Limiter::new(config());
+        assert_eq!(limiter.trip("a.com"), Duration::from_secs(60));
+        let e = limiter.acquire("a.com").await.unwrap_err();
+        assert!(e.is_throttled());
+        assert_eq!(e.retry_after(), Some(Duration::from_secs(60)));
+        assert!(limiter.acquire("b.com").await.is_ok());
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(limiter.acquire("a.com").await.is_ok());
+        // 连续触发翻倍，最多 150 秒
+        assert_eq!(limiter.trip("a.com"), Duration::from_secs(120));
+        assert_eq!(limiter.trip("a.com"), Duration::from_secs(150));
+        limiter.reset("a.com");
+        assert!(limiter.acquire("a.com").await.is_ok());
+        assert_eq!(limiter.trip("a.com"), Duration::from_secs(60));
+    }
+}
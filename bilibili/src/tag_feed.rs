@@ -3,6 +3,11 @@ use biliapi::{requests::BiliResponseExt, Request};
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
+use crate::{Client, Error};
+
+const NEW_URL: &str = "https://api.vc.bilibili.com/topic_svr/v1/topic_svr/topic_new";
+const HISTORY_URL: &str = "https://api.vc.bilibili.com/topic_svr/v1/topic_svr/topic_history";
+
 /// 一个 tag 下的综合消息，这个会拉取“热门消息”，最后一条动态会是最新的一条消息，
 /// 可以拿这个 id 去拉取按时间排序的消息（[`TagFeedHistory`] 接口）
 ///
@@ -146,10 +151,7 @@ impl Request for TagFeedNew {
         client: &reqwest::Client,
         args: Self::Args,
     ) -> biliapi::requests::RequestResponse<Self> {
-        let req = client
-            .get("https://api.vc.bilibili.com/topic_svr/v1/topic_svr/topic_new")
-            .query(&[("topic_name", args)])
-            .send();
+        let req = client.get(NEW_URL).query(&[("topic_name", args)]).send();
         Box::pin(async move { req.await?.bili_data().await })
     }
 }
@@ -161,7 +163,7 @@ impl Request for TagFeedHistory {
         args: Self::Args,
     ) -> biliapi::requests::RequestResponse<Self> {
         let req = client
-            .get("https://api.vc.bilibili.com/topic_svr/v1/topic_svr/topic_history")
+            .get(HISTORY_URL)
             .query(&[
                 ("topic_name", args.topic_name),
                 ("offset_dynamic_id", args.offset_dynamic_id),
@@ -171,6 +173,28 @@ impl Request for TagFeedHistory {
     }
 }
 
+impl Client {
+    /// 经过限流的 [`TagFeedNew`]
+    pub async fn tag_feed_new(&self, topic_name: &str) -> Result<TagFeedNew, Error> {
+        self.get(NEW_URL, &[("topic_name", topic_name)]).await
+    }
+
+    /// 经过限流的 [`TagFeedHistory`]
+    pub async fn tag_feed_history(
+        &self,
+        args: TagFeedHistoryArgs,
+    ) -> Result<TagFeedHistory, Error> {
+        self.get(
+            HISTORY_URL,
+            &[
+                ("topic_name", args.topic_name),
+                ("offset_dynamic_id", args.offset_dynamic_id),
+            ],
+        )
+        .await
+    }
+}
+
 #[cfg(test)]
 #[tokio::test]
 async fn test_tag_feed() {
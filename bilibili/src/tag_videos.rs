@@ -1,5 +1,9 @@
 use biliapi::requests::{self, BiliResponseExt, Request, VideoInfo};
 
+use crate::{Client, Error};
+
+const URL: &str = "https://api.bilibili.com/x/tag/detail";
+
 /// 一个 tag 下的视频
 #[derive(Debug, Deserialize, Clone)]
 pub struct TagVideos {
@@ -18,13 +22,21 @@ impl Request for TagVideos {
     type Args = u64;
     fn request(client: &reqwest::Client, args: Self::Args) -> requests::RequestResponse<Self> {
         let req = client
-            .get("https://api.bilibili.com/x/tag/detail")
+            .get(URL)
             .query(&[("pn", 1), ("ps", 20), ("tag_id", args)])
             .send();
         Box::pin(async move { req.await?.bili_data().await })
     }
 }
 
+impl Client {
+    /// 经过限流的 [`TagVideos`]
+    pub async fn tag_videos(&self, tag_id: u64) -> Result<TagVideos, Error> {
+        self.get(URL, &[("pn", 1), ("ps", 20), ("tag_id", tag_id)])
+            .await
+    }
+}
+
 #[cfg(test)]
 #[tokio::test]
 async fn test_tag() {
@@ -0,0 +1,150 @@
+//! WBI 签名：新一些的接口（用户空间、搜索）要在 query 里带上 `wts` 和 `w_rid`
+//!
+//! 签名用的 img_key 和 sub_key 从 nav 接口拿，每天会换，见
+//! https://github.com/SocialSisterYi/bilibili-API-collect/blob/master/docs/misc/sign/wbi.md
+use md5::{Digest, Md5};
+
+pub(crate) const NAV_URL: &str = "https://api.bilibili.com/x/web-interface/nav";
+
+/// 打乱 img_key + sub_key 的顺序，取前 32 位
+const MIXIN_KEY_ENC_TAB: [usize; 64] = [
+    46, 47, 18, 2, 53, 8, 23, 32, 15, 50, 10, 31, 58, 3, 45, 35, 27, 43, 5, 49, 33, 9, 42, 19, 29,
+    28, 14, 39, 12, 38, 41, 13, 37, 48, 7, 16, 24, 55, 40, 61, 26, 17, 0, 1, 60, 51, 30, 4, 22, 25,
+    54, 21, 56, 59, 6, 63, 57, 62, 11, 36, 20, 34, 44, 52,
+];
+
+/// 签名前要从值里去掉的字符
+const FILTERED_CHARS: &str = "!'()*";
+
+/// nav 接口的 data，只关心 wbi_img
+#[derive(Debug, Deserialize)]
+pub(crate) struct Nav {
+    wbi_img: WbiImg,
+}
+
+#[derive(Debug, Deserialize)]
+struct WbiImg {
+    img_url: String,
+    sub_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WbiKeys {
+    pub img_key: String,
+    pub sub_key: String,
+}
+
+/// url 里的文件名，不带扩展名
+fn file_stem(url: &str) -> &str {
+    let name = url.rsplit('/').next().unwrap_or(url);
+    name.split('.').next().unwrap_or(name)
+}
+
+/// 和 js 的 `encodeURIComponent` 一样，空格编码成 `%20`
+fn encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+impl WbiKeys {
+    pub(crate) fn from_nav(nav: &Nav) -> Self {
+        Self {
+            img_key: file_stem(&nav.wbi_img.img_url).to_string(),
+            sub_key: file_stem(&nav.wbi_img.sub_url).to_string(),
+        }
+    }
+
+    pub fn mixin_key(&self) -> String {
+        let raw = format!("{}{}", self.img_key, self.sub_key).into_bytes();
+        MIXIN_KEY_ENC_TAB
+            .iter()
+            .filter_map(|&i| raw.get(i))
+            .take(32)
+            .map(|&b| b as char)
+            .collect()
+    }
+
+    /// 给参数签名，返回带上 `wts` 和 `w_rid` 的 query string，`wts` 是当前的秒级时间戳
+    pub fn sign<K, V>(&self, params: &[(K, V)], wts: i64) -> String
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut params: Vec<(&str, String)> = params
+            .iter()
+            .map(|(k, v)| {
+                let v = v.as_ref().replace(|c: char| FILTERED_CHARS.contains(c), "");
+                (k.as_ref(), v)
+            })
+            .collect();
+        params.push(("wts", wts.to_string()));
+        params.sort_by(|a, b| a.0.cmp(b.0));
+        let query = params
+            .iter()
+            .map(|(k, v)| format!("{}={}", encode(k), encode(v)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let w_rid = Md5::digest(format!("{}{}", query, self.mixin_key()).as_bytes());
+        format!("{}&w_rid={:x}", query, w_rid)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys() -> WbiKeys {
+        WbiKeys {
+            img_key: "7cd084941338484aae1ad9425b84077c".to_string(),
+            sub_key: "4932caff0ff746eab6f01bf08b70ac45".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_keys_from_nav() {
+        // 没登录的时候 nav 返回 -101，data 里照样有 wbi_img
+        let body = r#"{
+            "code": -101,
+            "message": "账号未登录",
+            "data": {
+                "isLogin": false,
+                "wbi_img": {
+                    "img_url": "https://i0.hdslb.com/bfs/wbi/7cd084941338484aae1ad9425b84077c.png",
+                    "sub_url": "https://i0.hdslb.com/bfs/wbi/4932caff0ff746eab6f01bf08b70ac45.png"
+                }
+            }
+        }"#;
+        let nav: crate::client::Response<Nav> = serde_json::from_str(body).unwrap();
+        assert_eq!(WbiKeys::from_nav(&nav.data.unwrap()), keys());
+    }
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(keys().mixin_key(), "ea1db124af3c7062474693fa704f4ff8");
+        // 文档里的例子
+        let params = [("foo", "114"), ("bar", "514"), ("zab", "1919810")];
+        assert_eq!(
+            keys().sign(&params, 1702204169),
+            "bar=514&foo=114&wts=1702204169&zab=1919810&w_rid=8f6f2b5b3d485fe1886cec6a0be8c5d4"
+        );
+        // 中文和空格编码，去掉 !'()*
+        let params = [
+            ("mid", "672346917".to_string()),
+            ("keyword", "向晚 (大魔王)!".to_string()),
+            ("ps", "30".to_string()),
+        ];
+        assert_eq!(
+            keys().sign(&params, 1702204169),
+            "keyword=%E5%90%91%E6%99%9A%20%E5%A4%A7%E9%AD%94%E7%8E%8B&mid=672346917&ps=30\
+             &wts=1702204169&w_rid=2aa8862a680a5c683b048be56b5e9097"
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! 导出一段时间内已经分类的条目
+//!
+//! 用法：export <from> <to> <csv|jsonl|xlsx> [输出文件]
+use anyhow::*;
+use asoul_weekly as pkg;
+use chrono::NaiveDate;
+use pkg::biz::export::{self, Format};
+use pkg::feishu::{self, FeishuClient};
+use pkg::{biz, config, db};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 3 {
+        bail!("用法：export <from> <to> <csv|jsonl|xlsx> [输出文件]");
+    }
+    let from: NaiveDate = args[0].parse().context("from 不是日期")?;
+    let to: NaiveDate = args[1].parse().context("to 不是日期")?;
+    let format: Format = args[2].parse()?;
+    let output = args
+        .get(3)
+        .cloned()
+        .unwrap_or_else(|| format!("export-{}-{}.{}", from, to, format.extension()));
+
+    let config = config::Config::from_file("./config.toml").context("Config file not found")?;
+    let db_pool = db::init(&config.sqlite_url).await?;
+    let token_manager =
+        feishu::TokenManager::new(config.feishu.app_id, config.feishu.app_secret).await?;
+    let feishu_client = FeishuClient::new(token_manager);
+
+    let names = biz::directory::names(&feishu_client).await?;
+    let rows = export::rows(from, to, &names, &db_pool).await?;
+    std::fs::write(&output, export::render(&rows, format)?)?;
+    println!("导出 {} 条到 {}", rows.len(), output);
+    Ok(())
+}
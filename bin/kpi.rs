@@ -0,0 +1,36 @@
+//! 从服务端拉一段时间的标记统计
+//!
+//! 用法：ASOUL_WEEKLY_URL=http://... kpi <from> [to]
+use anyhow::*;
+use chrono::NaiveDate;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let from: NaiveDate = args
+        .get(0)
+        .context("用法：kpi <from> [to]")?
+        .parse()
+        .context("from 不是日期")?;
+    let to: NaiveDate = match args.get(1) {
+        Some(to) => to.parse().context("to 不是日期")?,
+        None => from,
+    };
+    let base_url = std::env::var("ASOUL_WEEKLY_URL").context("Missing `ASOUL_WEEKLY_URL`")?;
+
+    let kpi = api::Client::new(base_url).kpi(from, to).await?;
+    println!("{} ~ {}", kpi.from, kpi.to);
+    for (i, k) in kpi.reviewers.iter().enumerate() {
+        println!(
+            "{:>2}. {}\t{} 条（选入 {}，拒绝 {}），平均 {} 分钟，中位数 {} 分钟",
+            i + 1,
+            k.name,
+            k.total,
+            k.accepted,
+            k.rejected,
+            k.avg_latency_secs / 60,
+            k.median_latency_secs / 60
+        );
+    }
+    Ok(())
+}
@@ -8,9 +8,8 @@ async fn main() -> Result<()> {
     let config = config::Config::from_file("./config.toml").context("Config file not found")?;
     let token_manager =
         feishu::TokenManager::new(config.feishu.app_id, config.feishu.app_secret).await?;
-    let token = token_manager.token();
-    // println!("{}", token.read());
-    let feishu_client = FeishuClient::new(token);
+    // println!("{}", token_manager.current());
+    let feishu_client = FeishuClient::new(token_manager);
 
     let groups = feishu_client.get_groups().await?;
     for g in groups {
@@ -15,31 +15,11 @@ use biliapi::Request;
 use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
 use chrono_tz::Asia::Shanghai;
 use log::*;
-use std::{
-    collections::{BTreeMap, HashMap},
-    time::Duration,
-};
+use std::time::Duration;
 
 pub use login::*;
 use zhuanlan::{cards::Cards, items::Element, save_draft::*};
 
-lazy_static::lazy_static! {
-    static ref WEIGHT: HashMap<&'static str, i32> = maplit::hashmap!{
-        "A-SOUL一周年庆祝" => -2,
-        "乃琳50万粉祝贺" => -1,
-        "音乐" => 0,
-        "舞蹈" => 1,
-        "手书" => 2,
-        "手书/动画" => 2,
-        "精剪混剪" => 3,
-        "MMD" => 4,
-        "发病" => 5,
-        "鬼畜/整活" => 6,
-        "炸厨房"=>7,
-        "其他" => 8
-    };
-}
-
 const MAX_SIZE: usize = 800;
 
 fn strip(s: &str) -> String {
@@ -47,21 +27,13 @@ fn strip(s: &str) -> String {
     r.replace_all(s, "").to_string()
 }
 
-async fn get_data(t: DateTime<Utc>) -> Result<BTreeMap<String, Vec<String>>> {
-    let base_url = std::option_env!("ASOUL_WEEKLY_URL")
+fn base_url() -> String {
+    std::option_env!("ASOUL_WEEKLY_URL")
         .map(|s| s.to_string())
         .unwrap_or_else(|| {
             std::env::var("ASOUL_WEEKLY_URL")
                 .expect("Missing environment variable `ASOUL_WEEKLY_URL`")
-        });
-    let url = format!(
-        "{}/summary?t={}",
-        base_url,
-        t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
-    );
-    debug!("获取归档 {}", url);
-    let r = reqwest::get(url).await?.json().await?;
-    Ok(r)
+        })
 }
 
 /// 返回版头，引言等
@@ -242,30 +214,39 @@ async fn generate_dynamic_thumbnail(images: Vec<bytes::Bytes>, date: DateTime<Ut
 async fn gen_article_elements(
     client: &reqwest::Client,
     date: DateTime<Utc>,
-    mut summary: BTreeMap<String, Vec<String>>,
+    categories: &[api::Category],
+    mut summary: api::LegacySummary,
 ) -> Result<Vec<Element>> {
     let mut interval = tokio::time::interval(Duration::from_secs(1));
 
     // 分类
     let dynamics = summary.remove("动态").unwrap_or_default();
     let mut videos = summary.into_iter().collect::<Vec<_>>();
-    videos.sort_unstable_by_key(|(name, _)| WEIGHT.get(name.as_str()).unwrap_or(&99999));
+    // 不认识的分类排在最后
+    let category = |name: &str| categories.iter().find(|c| c.name == name);
+    videos.sort_by_key(|(name, _)| {
+        category(name.as_str())
+            .map(|c| c.sort_order)
+            .unwrap_or(i64::MAX)
+    });
 
     // 导言
     let mut elements = header(date);
 
     // 视频
     elements.extend(video_header());
-    for (category, bvids) in videos {
-        info!("处理分类 {} 视频", category);
-        if category == "动态" {
+    for (name, bvids) in videos {
+        info!("处理分类 {} 视频", name);
+        if name == "动态" {
             continue;
         }
         elements.push(Element::Text {
             center: false,
             strong: true,
             classes: vec!["color-blue-02".to_string(), "font-size-20".to_string()],
-            text: category.to_string(),
+            text: category(name.as_str())
+                .and_then(|c| c.section_title.clone())
+                .unwrap_or_else(|| name.clone()),
         });
         for bvids in bvids.chunks(2) {
             info!("获取 {:?} 的 aid", bvids);
@@ -338,8 +319,15 @@ fn date_string(t: DateTime<Utc>) -> String {
 pub async fn generate(client: reqwest::Client, csrf: String) -> Result<SaveDraft> {
     let date = Utc::now();
 
-    let summary = get_data(date - chrono::Duration::days(1)).await?;
-    let elements = gen_article_elements(&client, date, summary).await?;
+    // 分类决定日报里视频分区的顺序和小标题
+    let server = api::Client::new(base_url());
+    let categories = server.categories().await?;
+    let yesterday = (date - chrono::Duration::days(1))
+        .with_timezone(&Shanghai)
+        .date()
+        .naive_local();
+    let summary = server.summary(yesterday).await?;
+    let elements = gen_article_elements(&client, date, &categories, summary).await?;
 
     // 发送草稿
     let draft = Draft {
@@ -0,0 +1,8 @@
+-- Add up migration script here
+
+-- 上传到飞书的图片，source 为图片的 url 或者图片内容的 hash
+CREATE TABLE `image_cache` (
+    `source`        TEXT    NOT NULL    PRIMARY KEY,
+    `image_key`     TEXT    NOT NULL,
+    `create_time`   TEXT    NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Add up migration script here
+
+-- 被屏蔽的 UP 主，不再推送他们的视频和动态
+CREATE TABLE `blocked_user` (
+    `uid`           INTEGER NOT NULL    PRIMARY KEY,
+    `operator`      TEXT    NOT NULL,
+    `create_time`   TEXT    NOT NULL
+);
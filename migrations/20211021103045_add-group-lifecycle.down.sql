@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE `group` DROP COLUMN `archive_time`;
+ALTER TABLE `group` DROP COLUMN `last_active_time`;
+ALTER TABLE `group` DROP COLUMN `create_time`;
+ALTER TABLE `group` DROP COLUMN `state`;
@@ -0,0 +1,13 @@
+-- Add up migration script here
+
+-- 群的生命周期：active 使用中，members_removed 已经把人移出群，dissolved 已经解散
+ALTER TABLE `group` ADD COLUMN `state` TEXT NOT NULL DEFAULT 'active';
+ALTER TABLE `group` ADD COLUMN `create_time` TEXT;
+-- 最后一次往群里推送的时间，超过一定天数没用的群会被归档
+ALTER TABLE `group` ADD COLUMN `last_active_time` TEXT;
+ALTER TABLE `group` ADD COLUMN `archive_time` TEXT;
+
+-- 以前的群不知道创建时间，从现在开始算
+UPDATE `group`
+SET `create_time` = datetime('now'),
+    `last_active_time` = datetime('now');
@@ -0,0 +1,10 @@
+-- Add up migration script here
+
+-- 发到归档群的记录，保证同一个条目只归档一次
+CREATE TABLE `archive` (
+    `item_id`       TEXT    NOT NULL    PRIMARY KEY,
+    `chat_id`       TEXT    NOT NULL,
+    -- 为 NULL 时表示正在发送
+    `message_id`    TEXT,
+    `create_time`   TEXT    NOT NULL
+);
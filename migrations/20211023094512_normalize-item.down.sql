@@ -0,0 +1,28 @@
+-- Add down migration script here
+CREATE TABLE `item_old` (
+    `id`            TEXT    NOT NULL    PRIMARY KEY,
+    `json`          TEXT    NOT NULL,
+    `message_id`    TEXT    NOT NULL,
+    `create_time`   TEXT    NOT NULL,
+    `category`      TEXT,
+    `mark_time`     TEXT,
+    `author`        TEXT    NOT NULL    DEFAULT '<unknown>',
+    `marker`        TEXT    DEFAULT NULL
+);
+
+-- 拆出来的字段无法还原成卡片，只能保留旧数据的 json
+INSERT INTO `item_old`
+(`id`, `json`, `message_id`, `create_time`, `category`, `mark_time`, `author`, `marker`)
+SELECT
+    `id`,
+    COALESCE(`legacy_json`, '[]'),
+    `message_id`,
+    datetime(`published_at`, 'unixepoch'),
+    `category`,
+    datetime(`marked_at`, 'unixepoch'),
+    CASE WHEN `uploader_name` = '' THEN '<unknown>' ELSE `uploader_name` END,
+    `marker`
+FROM `item`;
+
+DROP TABLE `item`;
+ALTER TABLE `item_old` RENAME TO `item`;
@@ -0,0 +1,63 @@
+-- Add up migration script here
+
+-- 把卡片 json 拆成字段，时间都改成 unix 时间戳（秒）
+CREATE TABLE `item_new` (
+    `id`            TEXT    NOT NULL    PRIMARY KEY, -- BV 号或者动态 id
+    `kind`          TEXT    NOT NULL, -- video / dynamic
+    `title`         TEXT    NOT NULL    DEFAULT '', -- 视频标题，动态的正文
+    `uploader_uid`  INTEGER, -- 旧数据没有
+    `uploader_name` TEXT    NOT NULL    DEFAULT '',
+    `cover_url`     TEXT    NOT NULL    DEFAULT '', -- 视频封面，动态的第一张图
+    `image_key`     TEXT    NOT NULL    DEFAULT '', -- 卡片上的图片在飞书的 image_key
+    `duration`      INTEGER, -- 视频长度，秒
+    `picture_count` INTEGER, -- 动态的图片数
+    `source_tags`   TEXT    NOT NULL    DEFAULT '[]', -- 从哪些监控的 tag 拉到的，json 数组
+    -- 推送时的播放、点赞、评论、弹幕数，旧数据从卡片里解析
+    `view_count`    INTEGER,
+    `like_count`    INTEGER,
+    `reply_count`   INTEGER,
+    `danmaku_count` INTEGER,
+    `message_id`    TEXT    NOT NULL,
+    `published_at`  INTEGER NOT NULL, -- 视频、动态的发布时间
+    `pushed_at`     INTEGER NOT NULL, -- 推送到飞书的时间
+    -- 标记
+    `category`      TEXT,
+    `marker`        TEXT,
+    `marked_at`     INTEGER,
+    -- 旧数据的卡片 json，启动时解析到上面的字段之后清空
+    `legacy_json`   TEXT
+);
+
+-- 时间解析不了的行不能悄悄变成 1970 年，让迁移失败，手动修正之后再迁移
+CREATE TEMP TABLE `unparseable_time` (`id` TEXT NOT NULL);
+CREATE TEMP TRIGGER `reject_unparseable_time` BEFORE INSERT ON `unparseable_time`
+BEGIN
+    SELECT RAISE(ABORT, 'item 里有解析不了的 create_time 或 mark_time，先手动修正');
+END;
+INSERT INTO `unparseable_time`
+SELECT `id`
+FROM `item`
+WHERE
+    strftime('%s', `create_time`) IS NULL
+    OR (`mark_time` IS NOT NULL AND strftime('%s', `mark_time`) IS NULL);
+DROP TABLE `unparseable_time`;
+
+INSERT INTO `item_new`
+(`id`, `kind`, `uploader_name`, `message_id`, `published_at`, `pushed_at`,
+ `category`, `marker`, `marked_at`, `legacy_json`)
+SELECT
+    `id`,
+    CASE WHEN `id` LIKE 'BV%' THEN 'video' ELSE 'dynamic' END,
+    CASE WHEN `author` IN ('<unknown>', 'unknown') THEN '' ELSE `author` END,
+    `message_id`,
+    CAST(strftime('%s', `create_time`) AS INTEGER),
+    -- 以前没有记录推送时间，用发布时间代替
+    CAST(strftime('%s', `create_time`) AS INTEGER),
+    `category`,
+    `marker`,
+    CAST(strftime('%s', `mark_time`) AS INTEGER),
+    NULLIF(`json`, '')
+FROM `item`;
+
+DROP TABLE `item`;
+ALTER TABLE `item_new` RENAME TO `item`;
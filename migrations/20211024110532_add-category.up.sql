@@ -0,0 +1,16 @@
+-- Add up migration script here
+
+-- 分类，卡片的选项、汇总和日报的顺序都从这里读
+CREATE TABLE `category` (
+    `name`          TEXT    NOT NULL    PRIMARY KEY,
+    -- video 或者 dynamic，决定出现在哪种卡片上
+    `kind`          TEXT    NOT NULL    DEFAULT 'video',
+    `sort_order`    INTEGER NOT NULL    DEFAULT 0,
+    -- json 数组，旧的分类名，汇总的时候归到这个分类下
+    `aliases`       TEXT    NOT NULL    DEFAULT '[]',
+    -- 停用的分类不再出现在卡片上，但是已经分类的条目不受影响
+    `active`        INTEGER NOT NULL    DEFAULT 1,
+    -- 日报里的小标题，为空时用分类名
+    `section_title` TEXT    NULL,
+    `create_time`   TEXT    NOT NULL
+);
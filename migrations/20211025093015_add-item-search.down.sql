@@ -0,0 +1,42 @@
+-- Add down migration script here
+DROP TRIGGER `item_fts_update`;
+DROP TRIGGER `item_fts_delete`;
+DROP TRIGGER `item_fts_insert`;
+DROP TABLE `item_fts`;
+
+-- 去掉 seq 和 description，id 改回主键，索引随表一起删掉
+CREATE TABLE `item_old` (
+    `id`            TEXT    NOT NULL    PRIMARY KEY,
+    `kind`          TEXT    NOT NULL,
+    `title`         TEXT    NOT NULL    DEFAULT '',
+    `uploader_uid`  INTEGER,
+    `uploader_name` TEXT    NOT NULL    DEFAULT '',
+    `cover_url`     TEXT    NOT NULL    DEFAULT '',
+    `image_key`     TEXT    NOT NULL    DEFAULT '',
+    `duration`      INTEGER,
+    `picture_count` INTEGER,
+    `source_tags`   TEXT    NOT NULL    DEFAULT '[]',
+    `view_count`    INTEGER,
+    `like_count`    INTEGER,
+    `reply_count`   INTEGER,
+    `danmaku_count` INTEGER,
+    `message_id`    TEXT    NOT NULL,
+    `published_at`  INTEGER NOT NULL,
+    `pushed_at`     INTEGER NOT NULL,
+    `category`      TEXT,
+    `marker`        TEXT,
+    `marked_at`     INTEGER,
+    `legacy_json`   TEXT
+);
+
+INSERT INTO `item_old`
+SELECT
+    `id`, `kind`, `title`, `uploader_uid`, `uploader_name`, `cover_url`, `image_key`,
+    `duration`, `picture_count`, `source_tags`, `view_count`, `like_count`, `reply_count`,
+    `danmaku_count`, `message_id`, `published_at`, `pushed_at`, `category`, `marker`,
+    `marked_at`, `legacy_json`
+FROM `item`
+ORDER BY `seq`;
+
+DROP TABLE `item`;
+ALTER TABLE `item_old` RENAME TO `item`;
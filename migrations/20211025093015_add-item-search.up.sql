@@ -0,0 +1,86 @@
+-- Add up migration script here
+
+-- 加上视频简介，动态的正文在 title 里
+-- 另外加一个 INTEGER PRIMARY KEY 的 seq，id 是 TEXT 主键时隐含的 rowid 在 VACUUM 时可能重新编号，
+-- 全文索引和列表的游标都用 seq
+CREATE TABLE `item_new` (
+    `seq`           INTEGER NOT NULL    PRIMARY KEY,
+    `id`            TEXT    NOT NULL    UNIQUE, -- BV 号或者动态 id
+    `kind`          TEXT    NOT NULL, -- video / dynamic
+    `title`         TEXT    NOT NULL    DEFAULT '', -- 视频标题，动态的正文
+    `description`   TEXT    NOT NULL    DEFAULT '', -- 视频简介
+    `uploader_uid`  INTEGER, -- 旧数据没有
+    `uploader_name` TEXT    NOT NULL    DEFAULT '',
+    `cover_url`     TEXT    NOT NULL    DEFAULT '', -- 视频封面，动态的第一张图
+    `image_key`     TEXT    NOT NULL    DEFAULT '', -- 卡片上的图片在飞书的 image_key
+    `duration`      INTEGER, -- 视频长度，秒
+    `picture_count` INTEGER, -- 动态的图片数
+    `source_tags`   TEXT    NOT NULL    DEFAULT '[]', -- 从哪些监控的 tag 拉到的，json 数组
+    -- 推送时的播放、点赞、评论、弹幕数，旧数据从卡片里解析
+    `view_count`    INTEGER,
+    `like_count`    INTEGER,
+    `reply_count`   INTEGER,
+    `danmaku_count` INTEGER,
+    `message_id`    TEXT    NOT NULL,
+    `published_at`  INTEGER NOT NULL, -- 视频、动态的发布时间
+    `pushed_at`     INTEGER NOT NULL, -- 推送到飞书的时间
+    -- 标记
+    `category`      TEXT,
+    `marker`        TEXT,
+    `marked_at`     INTEGER,
+    -- 旧数据的卡片 json，启动时解析到上面的字段之后清空
+    `legacy_json`   TEXT
+);
+
+-- 保持原来的插入顺序
+INSERT INTO `item_new`
+(`seq`, `id`, `kind`, `title`, `uploader_uid`, `uploader_name`, `cover_url`, `image_key`,
+ `duration`, `picture_count`, `source_tags`, `view_count`, `like_count`, `reply_count`,
+ `danmaku_count`, `message_id`, `published_at`, `pushed_at`, `category`, `marker`,
+ `marked_at`, `legacy_json`)
+SELECT
+    `rowid`, `id`, `kind`, `title`, `uploader_uid`, `uploader_name`, `cover_url`, `image_key`,
+    `duration`, `picture_count`, `source_tags`, `view_count`, `like_count`, `reply_count`,
+    `danmaku_count`, `message_id`, `published_at`, `pushed_at`, `category`, `marker`,
+    `marked_at`, `legacy_json`
+FROM `item`
+ORDER BY `rowid`;
+
+DROP TABLE `item`;
+ALTER TABLE `item_new` RENAME TO `item`;
+
+-- 列表接口常用的筛选和排序
+CREATE INDEX `idx_item_published_at` ON `item` (`published_at`);
+CREATE INDEX `idx_item_marked_at` ON `item` (`marked_at`);
+CREATE INDEX `idx_item_pushed_at` ON `item` (`pushed_at`);
+CREATE INDEX `idx_item_category` ON `item` (`category`);
+CREATE INDEX `idx_item_uploader_uid` ON `item` (`uploader_uid`);
+CREATE INDEX `idx_item_marker` ON `item` (`marker`);
+
+-- 标题（视频标题、动态正文）和简介的全文索引，中文没有分词，用 trigram
+CREATE VIRTUAL TABLE `item_fts` USING fts5(
+    `title`,
+    `description`,
+    content = 'item',
+    content_rowid = 'seq',
+    tokenize = 'trigram'
+);
+
+CREATE TRIGGER `item_fts_insert` AFTER INSERT ON `item` BEGIN
+    INSERT INTO `item_fts` (`rowid`, `title`, `description`)
+    VALUES (new.`seq`, new.`title`, new.`description`);
+END;
+
+CREATE TRIGGER `item_fts_delete` AFTER DELETE ON `item` BEGIN
+    INSERT INTO `item_fts` (`item_fts`, `rowid`, `title`, `description`)
+    VALUES ('delete', old.`seq`, old.`title`, old.`description`);
+END;
+
+CREATE TRIGGER `item_fts_update` AFTER UPDATE OF `title`, `description` ON `item` BEGIN
+    INSERT INTO `item_fts` (`item_fts`, `rowid`, `title`, `description`)
+    VALUES ('delete', old.`seq`, old.`title`, old.`description`);
+    INSERT INTO `item_fts` (`rowid`, `title`, `description`)
+    VALUES (new.`seq`, new.`title`, new.`description`);
+END;
+
+INSERT INTO `item_fts` (`item_fts`) VALUES ('rebuild');
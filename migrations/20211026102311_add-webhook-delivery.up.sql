@@ -0,0 +1,23 @@
+-- Add up migration script here
+
+-- webhook 的投递队列，也是投递记录
+CREATE TABLE `webhook_delivery` (
+    `id`                INTEGER NOT NULL    PRIMARY KEY AUTOINCREMENT,
+    -- 配置里的 webhook 名字
+    `webhook`           TEXT    NOT NULL,
+    `event`             TEXT    NOT NULL,
+    `item_id`           TEXT    NOT NULL,
+    -- 入队时就生成好的 body，重试时原样发送
+    `payload`           TEXT    NOT NULL,
+    -- pending、delivered 或者 failed
+    `status`            TEXT    NOT NULL    DEFAULT 'pending',
+    `attempts`          INTEGER NOT NULL    DEFAULT 0,
+    `last_status_code`  INTEGER NULL,
+    `last_error`        TEXT    NULL,
+    `created_at`        INTEGER NOT NULL,
+    `next_attempt_at`   INTEGER NOT NULL,
+    `delivered_at`      INTEGER NULL
+);
+
+CREATE INDEX `idx_webhook_delivery_due` ON `webhook_delivery` (`status`, `next_attempt_at`);
+CREATE INDEX `idx_webhook_delivery_webhook` ON `webhook_delivery` (`webhook`);
@@ -0,0 +1,13 @@
+-- Add up migration script here
+
+-- 推给 /events 的事件，断线重连时从这里补
+CREATE TABLE `event` (
+    `id`            INTEGER NOT NULL    PRIMARY KEY AUTOINCREMENT,
+    `kind`          TEXT    NOT NULL,
+    `item_id`       TEXT    NOT NULL,
+    -- 事件发生之后的条目，json
+    `item`          TEXT    NOT NULL,
+    `created_at`    INTEGER NOT NULL
+);
+
+CREATE INDEX `idx_event_created_at` ON `event` (`created_at`);
@@ -0,0 +1,222 @@
+//! 分类之后发到归档群
+use anyhow::Result;
+use chrono::Utc;
+use serde_json::Value;
+
+use crate::biz;
+use crate::biz::route::{Candidate, Target};
+use crate::config::CONFIG;
+use crate::db::{self, ItemKind};
+use crate::feishu::{FeishuClient, Receiver};
+
+/// 占住超过这么久还没发出去的归档可以重发
+const CLAIM_TIMEOUT_MINUTES: i64 = 10;
+
+/// 把通过的条目发到对应的归档群，没有开启归档时什么都不做。
+///
+/// 按分类匹配路由规则，都不匹配时发到默认的归档群
+pub async fn send_archive(item: db::Item, pool: db::Pool, client: FeishuClient) -> Result<()> {
+    // 只有分类了的才归档，没有分类时也不能当成候选去匹配路由规则
+    let category = match item.category.as_deref() {
+        Some(category) => category,
+        None => {
+            warn!("{} 没有分类，不归档", item.id);
+            return Ok(());
+        }
+    };
+    let config = &CONFIG.archive;
+    if !config.enabled {
+        return Ok(());
+    }
+    let group_name = match item.kind {
+        ItemKind::Video => &config.video_group,
+        ItemKind::Dynamic => &config.dynamic_group,
+    };
+    let candidate = Candidate {
+        kind: item.kind,
+        tags: &item.source_tags,
+        uploader: item.uploader_uid,
+        category: Some(category),
+    };
+    let target = biz::route::route(&candidate, || Target::default_group(group_name));
+    let receiver = biz::route::resolve(&target, item.published_at, &pool, &client).await?;
+    let card = biz::cards::wrap_card_body(biz::cards::item_card(&item));
+    post_once(&item.id, &receiver, card, &pool, &client).await
+}
+
+/// 同一个条目只发一次。飞书重复回调、重新分类都不会再发
+async fn post_once(
+    item_id: &str,
+    receiver: &Receiver,
+    card: Value,
+    pool: &db::Pool,
+    client: &FeishuClient,
+) -> Result<()> {
+    let stale_before = Utc::now() - chrono::Duration::minutes(CLAIM_TIMEOUT_MINUTES);
+    if !db::Archive::claim(item_id, receiver.id(), stale_before, pool).await? {
+        info!("{} 已经归档过了，不再发送", item_id);
+        return Ok(());
+    }
+    // 请求失败重试的时候由飞书根据 uuid 去重
+    let uuid = format!("archive-{}", item_id);
+    match client.send_card_once(receiver, card, &uuid).await {
+        Ok(message) => {
+            info!("{} 已经发到 {:?}", item_id, receiver);
+            db::Archive::set_message_id(item_id, &message.message_id, pool).await
+        }
+        Err(e) => {
+            db::Archive::release(item_id, pool).await?;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::feishu::TokenManager;
+    use wiremock::matchers::{body_partial_json, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn setup() -> (MockServer, FeishuClient, db::Pool) {
+        let server = MockServer::start().await;
+        let client = FeishuClient::with_base_url(
+            TokenManager::with_token("t-test"),
+            format!("{}/open-apis", server.uri()),
+        );
+        let pool = db::init("sqlite://:memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        (server, client, pool)
+    }
+
+    fn receiver() -> Receiver {
+        Receiver::Chat("oc_archive".to_string())
+    }
+
+    fn sent(message_id: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "code": 0,
+            "msg": "success",
+            "data": { "message_id": message_id }
+        }))
+    }
+
+    async fn archived_message_id(item_id: &str, pool: &db::Pool) -> Option<String> {
+        sqlx::query_scalar("SELECT `message_id` FROM `archive` WHERE `item_id` = ?")
+            .bind(item_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_post_once() {
+        let (server, client, pool) = setup().await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .and(query_param("receive_id_type", "chat_id"))
+            .and(body_partial_json(json!({
+                "receive_id": "oc_archive",
+                "uuid": "archive-BV1xx411c7mD"
+            })))
+            .respond_with(sent("om_1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        for _ in 0..3 {
+            post_once("BV1xx411c7mD", &receiver(), json!({}), &pool, &client)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            archived_message_id("BV1xx411c7mD", &pool).await,
+            Some("om_1".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_uncategorized_not_archived() {
+        let (server, client, pool) = setup().await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .respond_with(sent("om_1"))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let item = db::Item::sample("BV1xx411c7mD", Utc::now());
+        assert!(item.category.is_none());
+        send_archive(item, pool.clone(), client).await.unwrap();
+        assert!(
+            sqlx::query_scalar::<_, String>("SELECT `item_id` FROM `archive`")
+                .fetch_optional(&pool)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_once_retry() {
+        let (server, client, pool) = setup().await;
+        // 第一次 5xx，重试成功，只算一次归档
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .respond_with(ResponseTemplate::new(502))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .respond_with(sent("om_1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        post_once("123456", &receiver(), json!({}), &pool, &client)
+            .await
+            .unwrap();
+        post_once("123456", &receiver(), json!({}), &pool, &client)
+            .await
+            .unwrap();
+        assert_eq!(
+            archived_message_id("123456", &pool).await,
+            Some("om_1".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_once_failed() {
+        let (server, client, pool) = setup().await;
+        // 不可重试的错误，放弃这次归档，下次还能再发
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 230002,
+                "msg": "Bot is not in the chat."
+            })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .respond_with(sent("om_2"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert!(post_once("123456", &receiver(), json!({}), &pool, &client)
+            .await
+            .is_err());
+        post_once("123456", &receiver(), json!({}), &pool, &client)
+            .await
+            .unwrap();
+        assert_eq!(
+            archived_message_id("123456", &pool).await,
+            Some("om_2".to_string())
+        );
+    }
+}
@@ -1,26 +1,36 @@
 use anyhow::*;
-use biliapi::Request;
 use bilibili::tag_feed::*;
-use chrono::{Timelike, Utc};
-use chrono_tz::Asia::Shanghai;
-use std::{collections::HashMap, time::Duration};
-use tokio::time;
+use chrono::Utc;
+use std::collections::HashMap;
 
+use crate::biz::schedule::Schedule;
 use crate::config::CONFIG;
-use crate::{biz, db, feishu::FeishuClient};
+use crate::supervisor::Task;
+use crate::{biz, db, feishu::FeishuClient, metrics};
 
-pub async fn fetch_forever(client: FeishuClient, pool: db::Pool) -> ! {
+pub async fn fetch_forever(
+    client: FeishuClient,
+    bili: bilibili::Client,
+    pool: db::Pool,
+    task: Task,
+) -> ! {
+    let schedule = Schedule::new(CONFIG.schedules.dynamic.clone());
     loop {
-        info!("开始拉取动态");
-        if let Err(e) = run_once(&client, pool.clone()).await {
-            error!("拉取动态失败: {:?}", e);
+        let mut retry_after = None;
+        if biz::control::is_source_paused(db::ItemKind::Dynamic) {
+            task.alive();
+            info!("已暂停，跳过拉取动态");
+        } else {
+            let r = run_once(&client, &bili, pool.clone(), &schedule).await;
+            retry_after = super::report("dynamic", r, task);
         }
 
-        // 1~8点五分钟刷一次，其他时间3分钟一次
-        if matches!(Utc::now().with_timezone(&Shanghai).hour(), 1..=8) {
-            time::sleep(Duration::from_secs(5 * 60)).await;
-        } else {
-            time::sleep(Duration::from_secs(3 * 60)).await;
+        // 被风控的话至少等到熔断结束
+        let wait = schedule
+            .next_wait(&mut rand::thread_rng())
+            .max(retry_after.unwrap_or_default());
+        if biz::control::wait(db::ItemKind::Dynamic, wait).await {
+            info!("手动触发拉取动态");
         }
     }
 }
@@ -46,10 +56,14 @@ fn filter_map(card: Dynamic<String>) -> Option<Dynamic<PictureDynamic>> {
     Some(picture_dynamic)
 }
 
-async fn get_all_tags(client: &FeishuClient) -> Result<Vec<Dynamic<PictureDynamic>>> {
-    let mut dynamics = HashMap::new();
+/// 返回动态和拉到它的 tag 名
+async fn get_all_tags(
+    bili: &bilibili::Client,
+    schedule: &Schedule,
+) -> Result<Vec<(Dynamic<PictureDynamic>, Vec<String>)>> {
+    let mut dynamics: HashMap<u64, (Dynamic<PictureDynamic>, Vec<String>)> = HashMap::new();
 
-    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    let mut tick = tokio::time::interval(schedule.tag_spacing());
     for (tag_name, _tag_id) in CONFIG.watch_tags.iter() {
         info!("获取 tag {} 下动态", tag_name);
 
@@ -61,17 +75,22 @@ async fn get_all_tags(client: &FeishuClient) -> Result<Vec<Dynamic<PictureDynami
 
             info!("获取 tag {} 第 {} 页", tag_name, times + 1);
             tick.tick().await;
-            let tag_dynamics = TagFeedHistory::request(
-                &client.client,
-                TagFeedHistoryArgs {
+            let r = bili
+                .tag_feed_history(TagFeedHistoryArgs {
                     topic_name: tag_name.to_string(),
                     offset_dynamic_id: offset,
-                },
-            )
-            .await?;
+                })
+                .await;
+            metrics::bilibili_request("tag_feed_history", &r);
+            let tag_dynamics = r?;
 
             for card in tag_dynamics.cards.iter().cloned().filter_map(filter_map) {
-                dynamics.insert(card.desc.dynamic_id, card);
+                let (_, tags) = dynamics
+                    .entry(card.desc.dynamic_id)
+                    .or_insert_with(|| (card, vec![]));
+                if !tags.contains(tag_name) {
+                    tags.push(tag_name.clone());
+                }
             }
 
             if let Some(last) = tag_dynamics.cards.last() {
@@ -89,44 +108,79 @@ async fn get_all_tags(client: &FeishuClient) -> Result<Vec<Dynamic<PictureDynami
     }
     let mut dynamics: Vec<_> = dynamics.into_values().collect();
     info!("所有tag中获取的总动态数量： {}", dynamics.len());
-    dynamics.sort_unstable_by_key(|d| d.desc.timestamp);
+    dynamics.sort_unstable_by_key(|(d, _)| d.desc.timestamp);
 
     Ok(dynamics)
 }
 
 async fn filter_new_dynamics(
     pool: &db::Pool,
-    dynamics: Vec<Dynamic<PictureDynamic>>,
-) -> Vec<Dynamic<PictureDynamic>> {
+    dynamics: Vec<(Dynamic<PictureDynamic>, Vec<String>)>,
+) -> Vec<(Dynamic<PictureDynamic>, Vec<String>)> {
     let mut ans = vec![];
-    for d in dynamics {
+    for (d, tags) in dynamics {
         let sent: bool = db::Item::is_sent(&d.desc.dynamic_id.to_string(), pool)
             .await
             .unwrap_or(false);
         if !sent {
-            ans.push(d);
+            ans.push((d, tags));
         }
     }
     ans
 }
 
-async fn run_once(client: &FeishuClient, pool: db::Pool) -> Result<()> {
-    let group = biz::group::create_group("动态筛选", client, &pool).await?;
-
+async fn run_once(
+    client: &FeishuClient,
+    bili: &bilibili::Client,
+    pool: db::Pool,
+    schedule: &Schedule,
+) -> Result<()> {
+    info!("开始拉取动态");
     // 拉动态
-    let dynamics = get_all_tags(client).await?;
+    let dynamics = get_all_tags(bili, schedule).await?;
     info!("获取全部tag下的动态有 {} 条", dynamics.len());
+    let fetched = dynamics.len();
+    let blocked = db::BlockedUser::all(&pool).await?;
+    let dynamics = dynamics
+        .into_iter()
+        .filter(|(d, _)| !blocked.contains(&(d.desc.uid as i64)))
+        .collect();
     let dynamics = filter_new_dynamics(&pool, dynamics).await;
     info!("没推送过的新动态: {} 条", dynamics.len());
+    metrics::candidates("dynamic", "fetched", fetched);
+    metrics::candidates("dynamic", "filtered", fetched - dynamics.len());
+
+    let routed = biz::route::split(dynamics, |(d, tags)| {
+        let candidate = biz::route::Candidate {
+            kind: db::ItemKind::Dynamic,
+            tags,
+            uploader: Some(d.desc.uid as i64),
+            category: None,
+        };
+        biz::route::route(&candidate, || biz::route::Target::default_group("动态筛选"))
+    });
+    for (target, dynamics) in routed {
+        send_dynamics(&target, &dynamics, client, &pool).await?;
+    }
+
+    Ok(())
+}
+
+async fn send_dynamics(
+    target: &biz::route::Target,
+    dynamics: &[(Dynamic<PictureDynamic>, Vec<String>)],
+    client: &FeishuClient,
+    pool: &db::Pool,
+) -> Result<()> {
+    let receiver = biz::route::resolve(target, Utc::now(), pool, client).await?;
 
     // 发送到飞书
     for dynamics in dynamics.chunks(10) {
         // 按批发送
         let mut items = vec![];
-        for dynamic in dynamics {
+        for (dynamic, tags) in dynamics {
             info!("新动态 id= {}", dynamic.desc.dynamic_id);
-            let body = biz::cards::dynamic_card(dynamic, client).await?;
-            items.push((dynamic, body));
+            items.push(to_item(dynamic, tags, client, pool).await?);
         }
 
         if items.is_empty() {
@@ -134,28 +188,71 @@ async fn run_once(client: &FeishuClient, pool: db::Pool) -> Result<()> {
             return Ok(());
         }
         // 发送
-        let bodies = items.iter().map(|(_, b)| b.clone()).collect();
+        let bodies = items.iter().map(biz::cards::item_card).collect();
         let card = biz::cards::wrap_card_body(biz::cards::merge_body(bodies));
 
-        let sent = client.send_card(&group.chat_id, card.clone()).await?;
+        let sent = client.send_card_to(&receiver, card.clone()).await?;
         let message_id = sent.message_id;
         info!("message id = {}", message_id);
 
-        info!("发送批动态完毕，本批 {} 动态", items.len());
-
-        for (dynamic, body) in items {
-            let item = db::Item {
-                id: dynamic.desc.dynamic_id.to_string(),
-                json: serde_json::to_string(&body)?,
-                message_id: message_id.clone(),
-                create_time: dynamic.desc.timestamp,
-                category: None,
-                author: dynamic.desc.user_profile.info.uname.clone(),
-            };
-            item.insert(&pool).await?;
+        info!(
+            "发送批动态完毕，本批 {} 动态，发到 {:?}",
+            items.len(),
+            target
+        );
+
+        metrics::candidates("dynamic", "sent", items.len());
+
+        let pushed_at = Utc::now();
+        for mut item in items {
+            item.message_id = message_id.clone();
+            item.pushed_at = pushed_at;
+            item.clone().insert(pool).await?;
+            biz::events::publish(db::EventKind::CandidateSent, &item, pool).await;
         }
         info!("保存动态信息到 DB 完成");
     }
 
     Ok(())
 }
+
+/// 上传拼好的图，生成还没有推送的条目
+async fn to_item(
+    dynamic: &Dynamic<PictureDynamic>,
+    tags: &[String],
+    client: &FeishuClient,
+    pool: &db::Pool,
+) -> Result<db::Item> {
+    let image_key = biz::cards::dynamic_thumbnail_image_key(dynamic, client, pool).await?;
+    let cover_url = dynamic
+        .inner
+        .pictures
+        .first()
+        .map(|p| p.src.clone())
+        .unwrap_or_default();
+    Ok(db::Item {
+        id: dynamic.desc.dynamic_id.to_string(),
+        kind: db::ItemKind::Dynamic,
+        title: dynamic.inner.description.clone(),
+        description: String::new(),
+        uploader_uid: Some(dynamic.desc.uid as i64),
+        uploader_name: dynamic.desc.user_profile.info.uname.clone(),
+        cover_url,
+        image_key,
+        duration: None,
+        picture_count: Some(dynamic.inner.pictures.len() as u32),
+        source_tags: tags.to_vec(),
+        // 动态的 desc 里没有评论数
+        stat: db::Stat {
+            view: Some(dynamic.desc.view as u32),
+            like: Some(dynamic.desc.like as u32),
+            ..Default::default()
+        },
+        message_id: String::new(),
+        published_at: dynamic.desc.timestamp,
+        pushed_at: Utc::now(),
+        category: None,
+        marker: None,
+        marked_at: None,
+    })
+}
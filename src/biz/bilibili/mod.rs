@@ -1,2 +1,32 @@
+use anyhow::Result;
+use std::time::Duration;
+
+use crate::metrics;
+use crate::supervisor::Task;
+
 pub mod dynamic;
 pub mod video;
+
+/// 报告一轮拉取的结果，被风控的时候返回还要等多久才能再拉
+fn report(source: &str, r: Result<()>, task: Task) -> Option<Duration> {
+    let e = match r {
+        Ok(()) => {
+            metrics::fetch_succeeded(source);
+            task.succeeded();
+            return None;
+        }
+        Err(e) => e,
+    };
+    task.failed(&e);
+    let retry_after = e
+        .downcast_ref::<bilibili::Error>()
+        .and_then(|e| e.retry_after());
+    match retry_after {
+        Some(retry_after) => {
+            warn!("拉取 {} 被风控，{:?} 后再拉：{}", source, retry_after, e);
+            metrics::fetch_throttled(source);
+        }
+        None => error!("拉取 {} 失败: {:?}", source, e),
+    }
+    retry_after
+}
@@ -1,88 +1,145 @@
 use anyhow::Result;
-use biliapi::{requests::Request, requests::VideoInfo};
-use chrono::{Timelike, Utc};
-use chrono_tz::Asia::Shanghai;
-use std::{collections::HashMap, time::Duration};
-use tokio::time;
-
-use bilibili::tag_videos::TagVideos;
+use biliapi::requests::VideoInfo;
+use chrono::Utc;
+use std::collections::HashMap;
 
+use crate::biz::schedule::Schedule;
 use crate::config::CONFIG;
-use crate::{biz, db, feishu::FeishuClient};
+use crate::supervisor::Task;
+use crate::{biz, db, feishu::FeishuClient, metrics};
 
-pub async fn fetch_forever(client: FeishuClient, db: db::Pool) -> ! {
+pub async fn fetch_forever(
+    client: FeishuClient,
+    bili: bilibili::Client,
+    db: db::Pool,
+    task: Task,
+) -> ! {
+    let schedule = Schedule::new(CONFIG.schedules.video.clone());
     loop {
-        info!("开始拉取视频");
-        if let Err(e) = run_once(&client, db.clone()).await {
-            error!("failed to fetch feed videos: {:?}", e);
+        let mut retry_after = None;
+        if biz::control::is_source_paused(db::ItemKind::Video) {
+            task.alive();
+            info!("已暂停，跳过拉取视频");
+        } else {
+            let r = run_once(&client, &bili, db.clone(), &schedule).await;
+            retry_after = super::report("video", r, task);
         }
 
-        // 1~8点五分钟刷一次，其他时间3分钟一次
-        if matches!(Utc::now().with_timezone(&Shanghai).hour(), 1..=8) {
-            time::sleep(Duration::from_secs(5 * 60)).await;
-        } else {
-            time::sleep(Duration::from_secs(3 * 60)).await;
+        // 被风控的话至少等到熔断结束
+        let wait = schedule
+            .next_wait(&mut rand::thread_rng())
+            .max(retry_after.unwrap_or_default());
+        if biz::control::wait(db::ItemKind::Video, wait).await {
+            info!("手动触发拉取视频");
         }
     }
 }
 
-async fn get_all_tags(client: &FeishuClient) -> Result<Vec<VideoInfo>> {
-    let mut videos = HashMap::new();
-    let mut tick = tokio::time::interval(Duration::from_secs(2));
+/// 返回视频和拉到它的 tag 名
+async fn get_all_tags(
+    bili: &bilibili::Client,
+    schedule: &Schedule,
+) -> Result<Vec<(VideoInfo, Vec<String>)>> {
+    let mut videos: HashMap<String, (VideoInfo, Vec<String>)> = HashMap::new();
+    let mut tick = tokio::time::interval(schedule.tag_spacing());
     for (tag_name, tag_id) in CONFIG.watch_tags.iter() {
         tick.tick().await;
         info!("getting videos for tag {}", tag_name);
-        let tag_videos = TagVideos::request(&client.client, *tag_id).await?;
+        let r = bili.tag_videos(*tag_id).await;
+        metrics::bilibili_request("tag_detail", &r);
+        let tag_videos = r?;
         debug!(
             "tag {} videos: {}",
             tag_name,
             tag_videos.news.archives.len()
         );
         let l = videos.len();
-        videos.extend(
-            tag_videos
-                .news
-                .archives
-                .into_iter()
-                // 直接筛掉转载
-                .filter(|v| v.copyright != 2)
-                // 筛选时长
-                .filter(|v| v.duration.as_secs() >= 10)
-                .map(|v| (v.bvid.clone(), v)),
-        );
+        let archives = tag_videos
+            .news
+            .archives
+            .into_iter()
+            // 直接筛掉转载
+            .filter(|v| v.copyright != 2)
+            // 筛选时长
+            .filter(|v| v.duration.as_secs() >= 10);
+        for v in archives {
+            videos
+                .entry(v.bvid.clone())
+                .or_insert_with(|| (v, vec![]))
+                .1
+                .push(tag_name.clone());
+        }
         info!("{} new videos got for tag {}", videos.len() - l, tag_name);
     }
     let mut videos: Vec<_> = videos.into_values().collect();
-    videos.sort_unstable_by_key(|v| v.publish_at);
+    videos.sort_unstable_by_key(|(v, _)| v.publish_at);
     Ok(videos)
 }
 
-async fn all_unsent_videos(pool: &db::Pool, videos: Vec<VideoInfo>) -> Vec<VideoInfo> {
+async fn all_unsent_videos(
+    pool: &db::Pool,
+    videos: Vec<(VideoInfo, Vec<String>)>,
+) -> Vec<(VideoInfo, Vec<String>)> {
     let mut ans = vec![];
-    for v in videos {
+    for (v, tags) in videos {
         let sent: bool = db::Item::is_sent(&v.bvid, pool).await.unwrap_or(false);
 
         if !sent {
-            ans.push(v);
+            ans.push((v, tags));
         }
     }
     ans
 }
 
-async fn run_once(client: &FeishuClient, db: db::Pool) -> Result<()> {
-    let group = biz::group::create_group("视频筛选", client, &db).await?;
-
-    let videos = get_all_tags(client).await?;
+async fn run_once(
+    client: &FeishuClient,
+    bili: &bilibili::Client,
+    db: db::Pool,
+    schedule: &Schedule,
+) -> Result<()> {
+    info!("开始拉取视频");
+    let videos = get_all_tags(bili, schedule).await?;
+    let fetched = videos.len();
+    let blocked = db::BlockedUser::all(&db).await?;
+    let videos = videos
+        .into_iter()
+        .filter(|(v, _)| !blocked.contains(&(v.owner.mid as i64)))
+        .collect();
     let videos = all_unsent_videos(&db, videos).await;
     info!("new videos: {}", videos.len());
+    metrics::candidates("video", "fetched", fetched);
+    metrics::candidates("video", "filtered", fetched - videos.len());
+
+    let routed = biz::route::split(videos, |(v, tags)| {
+        let candidate = biz::route::Candidate {
+            kind: db::ItemKind::Video,
+            tags,
+            uploader: Some(v.owner.mid as i64),
+            category: None,
+        };
+        biz::route::route(&candidate, || biz::route::Target::default_group("视频筛选"))
+    });
+    for (target, videos) in routed {
+        send_videos(&target, &videos, client, &db).await?;
+    }
+
+    Ok(())
+}
+
+async fn send_videos(
+    target: &biz::route::Target,
+    videos: &[(VideoInfo, Vec<String>)],
+    client: &FeishuClient,
+    db: &db::Pool,
+) -> Result<()> {
+    let receiver = biz::route::resolve(target, Utc::now(), db, client).await?;
 
     for videos in videos.chunks(10) {
         // 筛选
         let mut items = vec![];
-        for video in videos {
+        for (video, tags) in videos {
             info!("新视频：[{}] {}", video.bvid, video.title);
-            let body = biz::cards::video_info_to_card_body(video, client).await?;
-            items.push((video, body));
+            items.push(to_item(video, tags, client, db).await?);
         }
 
         if items.is_empty() {
@@ -90,28 +147,59 @@ async fn run_once(client: &FeishuClient, db: db::Pool) -> Result<()> {
             return Ok(());
         }
         // 合并发送
-        let bodies = items.iter().map(|(_, b)| b.clone()).collect();
+        let bodies = items.iter().map(biz::cards::item_card).collect();
         let card = biz::cards::wrap_card_body(biz::cards::merge_body(bodies));
 
-        let sent = client.send_card(&group.chat_id, card.clone()).await?;
+        let sent = client.send_card_to(&receiver, card.clone()).await?;
         let message_id = sent.message_id;
         debug!("message id = {}", message_id);
-        info!("发送本批视频完毕，本批 {}", items.len());
+        info!("发送本批视频完毕，本批 {}，发到 {:?}", items.len(), target);
+        metrics::candidates("video", "sent", items.len());
 
         // 保存 message_id => bv 的映射
-        for (video, body) in items {
-            let item = db::Item {
-                id: video.bvid.clone(),
-                json: serde_json::to_string(&body)?,
-                message_id: message_id.clone(),
-                create_time: video.publish_at,
-                category: None,
-                author: video.owner.name.clone(),
-            };
-            item.insert(&db).await?;
+        let pushed_at = Utc::now();
+        for mut item in items {
+            item.message_id = message_id.clone();
+            item.pushed_at = pushed_at;
+            item.clone().insert(db).await?;
+            biz::events::publish(db::EventKind::CandidateSent, &item, db).await;
         }
         info!("保存视频信息到 DB 完成");
     }
-
     Ok(())
 }
+
+/// 上传封面，生成还没有推送的条目
+async fn to_item(
+    video: &VideoInfo,
+    tags: &[String],
+    client: &FeishuClient,
+    db: &db::Pool,
+) -> Result<db::Item> {
+    let image_key = biz::image::upload_url(&video.cover_url, client, db).await?;
+    Ok(db::Item {
+        id: video.bvid.clone(),
+        kind: db::ItemKind::Video,
+        title: video.title.clone(),
+        description: video.desc.clone(),
+        uploader_uid: Some(video.owner.mid as i64),
+        uploader_name: video.owner.name.clone(),
+        cover_url: video.cover_url.clone(),
+        image_key,
+        duration: Some(video.duration.as_secs() as u32),
+        picture_count: None,
+        source_tags: tags.to_vec(),
+        stat: db::Stat {
+            view: Some(video.stat.view as u32),
+            like: Some(video.stat.like as u32),
+            reply: Some(video.stat.reply as u32),
+            danmaku: Some(video.stat.danmaku as u32),
+        },
+        message_id: String::new(),
+        published_at: video.publish_at,
+        pushed_at: Utc::now(),
+        category: None,
+        marker: None,
+        marked_at: None,
+    })
+}
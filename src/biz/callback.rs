@@ -49,105 +49,50 @@ struct ButtonAction {
     value: HashMap<String, String>,
 }
 
+/// 飞书事件订阅推送的数据（2.0 版本的结构）
+#[derive(Debug, Deserialize)]
+pub struct EventData {
+    pub header: EventHeader,
+    /// 不同的 event_type 结构不同
+    pub event: Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventHeader {
+    pub event_id: String,
+    pub event_type: String,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum CallbackData {
     Bind(BindData),
     Action(ActionData),
+    Event(EventData),
 }
 
 pub async fn new_body(
     action: ActionData,
     pool: &db::Pool,
-    _feishu_client: web::Data<crate::FeishuClient>,
+    feishu_client: web::Data<crate::FeishuClient>,
 ) -> Result<Vec<Value>> {
-    let (item_new_body, item, _is_video) = match action.action {
+    let (id, category) = match action.action {
         // 选择类型一定是视频类的
-        Action::Select(s) => {
-            // 标记类型
-            let bvid = s.value["bvid"].to_string();
-            let category = s.option;
-            db::Item::set_category(&bvid, &category, &action.user_id, pool).await?;
-            // 修改
-            info!("getting card body {} from db", bvid);
-            let item = db::Item::from_id(&bvid, pool)
-                .await?
-                .ok_or_else(|| anyhow!("Item {} not exist", bvid))?;
-            let v: Vec<Value> = serde_json::from_str(&item.json)?;
-            info!("card body {} got.", bvid);
-
-            (biz::cards::video_to_accepted(v, category), item, true)
-        }
+        Action::Select(s) => (s.value["bvid"].to_string(), s.option),
         // 按键可能是动态的通过，但是历史遗留，也可能是视频的
         Action::Button(b) => {
             if b.value.get("type").map(|s| s.as_str()) == Some("dynamic") {
-                let dynamic_id = b.value["dynamic_id"].to_string();
-                db::Item::set_category(&dynamic_id, "动态", &action.user_id, pool).await?;
-
-                // 修改
-                let item = db::Item::from_id(&dynamic_id, pool)
-                    .await?
-                    .ok_or_else(|| anyhow!("Item {} not exist", dynamic_id))?;
-                let v: Vec<Value> = serde_json::from_str(&item.json)?;
-                info!("card body of dynamic {} got.", dynamic_id);
-
-                (biz::cards::dynamic_to_ok(v), item, false)
+                (b.value["dynamic_id"].to_string(), "动态".to_string())
             } else {
                 bail!("button value.type != dynamic");
             }
         }
     };
-    let card_body = serde_json::to_string(&item_new_body)?;
-    db::Item::set_json(&item.id, &card_body, pool).await?;
-    info!("new card body set.");
-
-    // 把归档结果发送到归档群
-    #[cfg(feature = "archive")]
-    {
-        let _pool = pool.clone();
-        tokio::spawn(async move {
-            if let Err(e) = send_archive(
-                is_video,
-                _pool,
-                &feishu_client,
-                item_new_body,
-                item.create_time,
-            )
-            .await
-            {
-                error!("发送归档信息失败：{:?}", e);
-            }
-        });
-    }
+    // 标记类型，然后发到归档群
+    biz::review::categorize(&id, &category, &action.user_id, pool, &feishu_client).await?;
 
     // 返回新的卡片
-    let mut bodies = vec![];
-    let all_json = db::Item::all_item_json(&action.open_message_id, pool).await?;
-    for json in all_json {
-        let body: Vec<Value> = serde_json::from_str(&json)?;
-        bodies.push(body);
-    }
-    Ok(biz::cards::merge_body(bodies))
-}
-
-#[cfg(feature = "archive")]
-async fn send_archive(
-    is_video: bool,
-    pool: db::Pool,
-    feishu_client: &crate::FeishuClient,
-    body: Vec<Value>,
-    time: chrono::DateTime<chrono::Utc>,
-) -> Result<()> {
-    let group_name = if is_video {
-        "视频归档"
-    } else {
-        "动态归档"
-    };
-    let group = biz::group::create_group_with_time(group_name, time, &pool, feishu_client).await?;
-    feishu_client
-        .send_card(&group.chat_id, wrap_card_body(body))
-        .await?;
-    Ok(())
+    biz::review::message_body(&action.open_message_id, pool).await
 }
 
 // /// 异步接口
@@ -1,6 +1,5 @@
 //! 生成各种卡片
 use anyhow::Result;
-use biliapi::requests::VideoInfo;
 use chrono_tz::Asia::Shanghai;
 use regex::Regex;
 use serde_json::Value;
@@ -8,7 +7,7 @@ use std::borrow::Cow;
 
 use bilibili::tag_feed::{Dynamic, PictureDynamic};
 
-use crate::{biz, config::CONFIG, feishu::FeishuClient};
+use crate::{biz, db, feishu::FeishuClient, metrics};
 
 type CardBody = Vec<Value>;
 
@@ -34,25 +33,34 @@ fn markdown_escape(s: &str) -> Cow<str> {
     }
 }
 
-/// 基础信息分行，会上传封面
-async fn video_basic_info(info: &VideoInfo, client: &FeishuClient) -> Result<Value> {
-    let url = format!("https://www.bilibili.com/video/{}", info.bvid);
-    let img_key = client.upload_image_url(&info.cover_url).await?;
-    let up = &info.owner.name;
-
-    let intro = format!(
-        "[▷{title}]({url})\n{bvid} UP：{up}\n播放 {play}  评论 {comment}  弹幕 {danmaku}  长度 {m}:{s:02}",
-        title = markdown_escape(&info.title),
-        bvid = info.bvid,
+/// 视频的基础信息分行
+fn video_basic_info(item: &db::Item) -> Value {
+    let url = format!("https://www.bilibili.com/video/{}", item.id);
+    let mut intro = format!(
+        "[▷{title}]({url})\n{bvid} UP：{up}",
+        title = markdown_escape(&item.title),
         url = url,
-        up = up,
-        play = info.stat.view,
-        comment = info.stat.reply,
-        danmaku = info.stat.danmaku,
-        m = info.duration.as_secs() / 60,
-        s = info.duration.as_secs() % 60,
+        bvid = item.id,
+        up = item.uploader_name,
     );
-    Ok(json!({
+    // 播放 1  评论 2  弹幕 3  长度 m:ss，旧数据没有的不显示
+    let stat = &item.stat;
+    let mut numbers: Vec<String> = [
+        ("播放", stat.view),
+        ("评论", stat.reply),
+        ("弹幕", stat.danmaku),
+    ]
+    .iter()
+    .filter_map(|(name, n)| n.map(|n| format!("{} {}", name, n)))
+    .collect();
+    if let Some(duration) = item.duration {
+        numbers.push(format!("长度 {}:{:02}", duration / 60, duration % 60));
+    }
+    if !numbers.is_empty() {
+        intro.push('\n');
+        intro.push_str(&numbers.join("  "));
+    }
+    json!({
         "tag": "div",
         "text": {
             "tag": "lark_md",
@@ -60,19 +68,49 @@ async fn video_basic_info(info: &VideoInfo, client: &FeishuClient) -> Result<Val
         },
         "extra": {
             "tag": "img",
-            "img_key": img_key,
+            "img_key": item.image_key,
             "alt": {
                 "tag": "plain_text",
                 "content": "视频封面"
             }
         }
-    }))
+    })
+}
+
+/// 动态的作者和正文
+fn dynamic_basic_info(item: &db::Item) -> Value {
+    let pictures = item
+        .picture_count
+        .map(|n| format!(" ({} 图)", n))
+        .unwrap_or_default();
+    let content_md = format!(
+        "[{}{}](https://t.bilibili.com/{})\n{}",
+        item.uploader_name,
+        pictures,
+        item.id,
+        markdown_escape(&item.title)
+    );
+    json!({
+        "tag": "div",
+        "text": {
+            "tag": "lark_md",
+            "content": content_md,
+        },
+        "extra": {
+            "tag": "img",
+            "img_key": item.image_key,
+            "alt": {
+                "tag": "plain_text",
+                "content": "第一张图"
+            }
+        }
+    })
 }
 
-/// 视频的页脚，发布于
-fn video_footnote(info: &VideoInfo) -> Value {
-    let t = info
-        .publish_at
+/// 页脚，发布于
+fn footnote(item: &db::Item) -> Value {
+    let t = item
+        .published_at
         .with_timezone(&Shanghai)
         .format("%Y-%m-%d %H:%M:%S");
     json!({
@@ -87,9 +125,8 @@ fn video_footnote(info: &VideoInfo) -> Value {
 }
 
 /// 按钮
-fn video_action(info: &VideoInfo) -> Value {
-    let select_options: Vec<Value> = CONFIG
-        .video_categories
+fn video_action(item: &db::Item) -> Value {
+    let select_options: Vec<Value> = biz::category::video_options()
         .iter()
         .map(|t| {
             json!({
@@ -113,151 +150,143 @@ fn video_action(info: &VideoInfo) -> Value {
                 },
                 "value": {
                     "type": "video",
-                    "bvid": info.bvid
+                    "bvid": item.id
                 },
                 "options": select_options
             },
-            // {
-            //     "tag": "button",
-            //     "text": {
-            //         "tag": "plain_text",
-            //         "content": "不通过筛选"
-            //     },
-            //     "type": "default",
-            //     "value": {
-            //         "bvid": info.bvid,
-            //         "action": "deny"
-            //     }
-            // }
         ]
     })
 }
 
-pub fn video_to_accepted(body: CardBody, category: String) -> CardBody {
-    vec![
-        body[0].clone(),
-        json!({
-          "tag": "markdown",
-          "content": format!("✔️ 已接受，分类：{}", category)
-        }),
-        body[2].clone(),
-    ]
+fn dynamic_action(item: &db::Item) -> Value {
+    json!({
+        "tag": "action",
+        "actions": [
+            {
+                "tag": "button",
+                "text": {
+                    "tag": "plain_text",
+                    "content": "选入今日二创"
+                },
+                "type": "default",
+                "value": {
+                    "type": "dynamic",
+                    "dynamic_id": item.id
+                }
+            },
+        ]
+    })
 }
 
-pub fn dynamic_to_ok(body: CardBody) -> CardBody {
-    // 一共三段，修改中间的
-    vec![
-        body[0].clone(),
-        json!({
-            "tag": "markdown",
-            "content": "✔️ 已接受"
-        }),
-        body[2].clone(),
-    ]
+fn rejected() -> Value {
+    json!({
+        "tag": "markdown",
+        "content": "❌ 已拒绝"
+    })
 }
 
-pub fn wrap_card_body(body: CardBody) -> Value {
+fn accepted(item: &db::Item) -> Value {
+    let content = match (item.kind, &item.category) {
+        (db::ItemKind::Video, Some(category)) => format!("✔️ 已接受，分类：{}", category),
+        _ => "✔️ 已接受".to_string(),
+    };
     json!({
-        "config": { "wide_screen_mode": true },
-        "i18n_elements": { "zh_cn": body }
+        "tag": "markdown",
+        "content": content
     })
 }
 
-/// 动态卡片，会上传封面
-pub async fn dynamic_card(
-    dynamic: &Dynamic<PictureDynamic>,
-    client: &FeishuClient,
-) -> Result<CardBody> {
-    let content_md = format!(
-        "[{} ({} 图)](https://t.bilibili.com/{})\n{}",
-        dynamic.desc.user_profile.info.uname,
-        dynamic.inner.pictures.len(),
-        dynamic.desc.dynamic_id,
-        markdown_escape(&dynamic.inner.description)
-    );
-    debug!("dynamic content card markdown = {}", content_md);
-
-    let img_key = get_dynamic_thumbnail_image_key(dynamic, client).await?;
+/// 按条目的字段和分类状态生成卡片，一共三段：基础信息、按钮或者分类结果、页脚
+pub fn item_card(item: &db::Item) -> CardBody {
+    let (basic_info, action) = match item.kind {
+        db::ItemKind::Video => (video_basic_info(item), video_action(item)),
+        db::ItemKind::Dynamic => (dynamic_basic_info(item), dynamic_action(item)),
+    };
+    // 有人看过但是没有分类的是拒绝了
+    let middle = match (&item.category, &item.marker) {
+        (Some(_), _) => accepted(item),
+        (None, Some(_)) => rejected(),
+        (None, None) => action,
+    };
+    vec![basic_info, middle, footnote(item)]
+}
 
-    let t = dynamic
-        .desc
-        .timestamp
-        .with_timezone(&Shanghai)
-        .format("%Y-%m-%d %H:%M:%S");
+pub fn wrap_card_body(body: CardBody) -> Value {
+    json!({
+        // 之后要更新卡片，新版消息接口的 update_multi 放在卡片里
+        "config": { "wide_screen_mode": true, "update_multi": true },
+        "i18n_elements": { "zh_cn": body }
+    })
+}
 
-    let b = vec![
-        json!({
-          "tag": "div",
-          "text": {
-            "tag": "lark_md",
-            "content": content_md,
-          },
-          "extra": {
-            "tag": "img",
-            "img_key": img_key,
-            "alt": {
-              "tag": "plain_text",
-              "content": "第一张图"
+/// 只有标题和一段 markdown 的卡片，用于回复命令等
+pub fn text_card(title: &str, content: &str) -> Value {
+    json!({
+        "config": { "wide_screen_mode": true },
+        "header": {
+            "title": {
+                "tag": "plain_text",
+                "content": title
             }
-          }
-        }),
-        json!({
-            "tag": "action",
-            "actions": [
+        },
+        "i18n_elements": {
+            "zh_cn": [
                 {
-                    "tag": "button",
-                    "text": {
-                        "tag": "plain_text",
-                        "content": "选入今日二创"
-                    },
-                    "type": "default",
-                    "value": {
-                        "type": "dynamic",
-                        "dynamic_id": dynamic.desc.dynamic_id.to_string()
-                    }
-                },
+                    "tag": "markdown",
+                    "content": content
+                }
             ]
-        }),
-        json!({
-          "tag": "note",
-          "elements": [
-            {
-              "tag": "plain_text",
-              "content":  format!("发布于 {}", t)
-            }
-          ]
-        }),
-    ];
-    debug!("card = {}", serde_json::to_string(&b).unwrap());
-    Ok(b)
+        }
+    })
 }
 
-async fn get_dynamic_thumbnail_image_key(
+/// 把动态的所有图片拼成一张图上传，失败时使用默认图
+pub async fn dynamic_thumbnail_image_key(
     dynamic: &Dynamic<PictureDynamic>,
     client: &FeishuClient,
+    pool: &db::Pool,
 ) -> Result<String> {
+    let urls: Vec<String> = dynamic
+        .inner
+        .pictures
+        .iter()
+        .map(|pic| {
+            let mut url = pic.src.clone();
+            if !url.ends_with("@512w.jpg") {
+                url.push_str("@512w.jpg");
+            }
+            url
+        })
+        .collect();
+    // 合并的图片以所有图片的链接作为 source
+    let source = format!("merge:{}", urls.join(","));
+    if let Some(image_key) = db::ImageCache::get(&source, pool).await? {
+        debug!("动态 {} 的图片已经上传过了", dynamic.desc.dynamic_id);
+        return Ok(image_key);
+    }
+
     // 进行一个贴图的上传
     let mut image_download_futures = vec![];
-    async fn download_image(client: &FeishuClient, mut url: String) -> Result<Vec<u8>> {
-        if !url.ends_with("@512w.jpg") {
-            url.push_str("@512w.jpg");
-        }
+    async fn download_image(client: &FeishuClient, url: String) -> Result<Vec<u8>> {
+        let timer = metrics::IMAGE_SECONDS
+            .with_label_values(&["download"])
+            .start_timer();
         let response = client.client.get(url).send().await?;
         if !response.status().is_success() {
             bail!("下载图片失败：{}", response.status());
         }
         let bytes = response.bytes().await?;
+        timer.observe_duration();
         Ok(bytes.to_vec())
     }
-    for pic in dynamic.inner.pictures.iter() {
-        let url = pic.src.clone();
+    for url in urls {
         image_download_futures.push(download_image(client, url));
     }
     let image_bytes = match futures::future::try_join_all(image_download_futures).await {
         Ok(result) => result,
         Err(e) => {
             error!("动态的某张图片下载失败了：{:?}", e);
-            return Ok("img_v2_1f156161-3ffa-40f7-9d28-9621cc5ed2cg".to_string());
+            return biz::image::fallback(client, pool).await;
         }
     };
     debug!(
@@ -266,37 +295,36 @@ async fn get_dynamic_thumbnail_image_key(
         image_bytes.iter().map(|i| i.len()).sum::<usize>() as f64 / 1024. / 1024.
     );
 
-    let merged_image_bytes = match merge_images::merge(&image_bytes) {
+    let timer = metrics::IMAGE_SECONDS
+        .with_label_values(&["merge"])
+        .start_timer();
+    let merged = merge_images::merge(&image_bytes);
+    timer.observe_duration();
+    let merged_image_bytes = match merged {
         Ok(bytes) => bytes,
         Err(e) => {
             warn!("合并图片失败,使用fallback图片：{:?}", e);
-            return Ok("img_v2_1f156161-3ffa-40f7-9d28-9621cc5ed2cg".to_string());
+            return biz::image::fallback(client, pool).await;
         }
     };
     debug!("图片合并成功");
 
-    let r = client
-        .upload_image_bytes(merged_image_bytes)
-        .await
-        .unwrap_or_else(|e| {
+    let r = match biz::image::upload_bytes(merged_image_bytes, client, pool).await {
+        Ok(image_key) => {
+            db::ImageCache::insert(&source, &image_key, pool).await?;
+            image_key
+        }
+        Err(e) => {
             warn!("上传图片失败，可能是过大：{:?}", e);
             debug!("使用默认图");
-            "img_v2_1f156161-3ffa-40f7-9d28-9621cc5ed2cg".to_string()
-        });
+            biz::image::fallback(client, pool).await?
+        }
+    };
 
     debug!("图片上传完成");
     Ok(r)
 }
 
-pub async fn video_info_to_card_body(info: &VideoInfo, client: &FeishuClient) -> Result<CardBody> {
-    let basic_info_block = biz::cards::video_basic_info(info, client).await?;
-    let footnote_block = biz::cards::video_footnote(info);
-    let action_block = biz::cards::video_action(info);
-
-    let body = vec![basic_info_block, action_block, footnote_block];
-    Ok(body)
-}
-
 pub fn merge_body(bodies: Vec<CardBody>) -> CardBody {
     let mut combined_body = vec![];
     for (idx, body) in bodies.into_iter().enumerate() {
@@ -307,3 +335,194 @@ pub fn merge_body(bodies: Vec<CardBody>) -> CardBody {
     }
     combined_body
 }
+
+/// 解析旧数据存的卡片 json，卡片格式见以前的 `video_basic_info` 和 `dynamic_card`
+pub fn parse_legacy(kind: db::ItemKind, json: &str) -> db::LegacyFields {
+    let mut fields = db::LegacyFields::default();
+    let body: CardBody = match serde_json::from_str(json) {
+        Ok(body) => body,
+        Err(_) => return fields,
+    };
+    let first = match body.first() {
+        Some(first) => first,
+        None => return fields,
+    };
+    if let Some(image_key) = first["extra"]["img_key"].as_str() {
+        fields.image_key = image_key.to_string();
+    }
+    let content = first["text"]["content"].as_str().unwrap_or_default();
+    let mut lines = content.lines();
+    let head = lines.next().unwrap_or_default();
+    // [xxx](url)
+    let link_text = head
+        .strip_prefix('[')
+        .and_then(|s| s.rfind("](").map(|i| &s[..i]));
+    match kind {
+        db::ItemKind::Video => {
+            if let Some(title) = link_text {
+                fields.title = title.trim_start_matches('▷').to_string();
+            }
+            for line in lines {
+                if let Some(i) = line.find("UP：") {
+                    fields.uploader_name = Some(line[i + "UP：".len()..].trim().to_string());
+                }
+                if let Some(i) = line.find("长度 ") {
+                    fields.duration = parse_duration(&line[i + "长度 ".len()..]);
+                }
+                fields.stat.view = fields.stat.view.or_else(|| parse_number(line, "播放 "));
+                fields.stat.reply = fields.stat.reply.or_else(|| parse_number(line, "评论 "));
+                fields.stat.danmaku = fields.stat.danmaku.or_else(|| parse_number(line, "弹幕 "));
+            }
+        }
+        db::ItemKind::Dynamic => {
+            // 作者 (n 图)
+            if let Some(text) = link_text {
+                match text.rfind(" (") {
+                    Some(i) => {
+                        fields.uploader_name = Some(text[..i].to_string());
+                        fields.picture_count = text[i + 2..]
+                            .trim_end_matches(')')
+                            .trim_end_matches('图')
+                            .trim()
+                            .parse()
+                            .ok();
+                    }
+                    None => fields.uploader_name = Some(text.to_string()),
+                }
+            }
+            fields.title = lines.collect::<Vec<_>>().join("\n");
+        }
+    }
+    fields
+}
+
+/// `line` 里 `prefix` 后面的数字
+fn parse_number(line: &str, prefix: &str) -> Option<u32> {
+    let i = line.find(prefix)?;
+    line[i + prefix.len()..]
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// m:ss
+fn parse_duration(s: &str) -> Option<u32> {
+    let s = s.split_whitespace().next()?;
+    let (m, sec) = s.split_once(':')?;
+    Some(m.parse::<u32>().ok()? * 60 + sec.parse::<u32>().ok()?)
+}
+
+/// 把旧数据的卡片 json 解析成字段，只需要跑一次，启动的时候调用
+pub async fn backfill_legacy_items(pool: &db::Pool) -> Result<()> {
+    let items = db::Item::legacy(pool).await?;
+    if items.is_empty() {
+        return Ok(());
+    }
+    info!("解析 {} 条旧数据的卡片", items.len());
+    for item in items {
+        let kind = match item.kind.as_str() {
+            "video" => db::ItemKind::Video,
+            _ => db::ItemKind::Dynamic,
+        };
+        let fields = parse_legacy(kind, &item.legacy_json);
+        db::Item::fill_legacy(&item.id, fields, pool).await?;
+    }
+    info!("旧数据解析完成");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_legacy_video() {
+        let json = json!([{
+            "tag": "div",
+            "text": {
+                "tag": "lark_md",
+                "content": "[▷【A-SOUL】标题](https://www.bilibili.com/video/BV1xx411c7mD)\nBV1xx411c7mD UP：某UP主\n播放 10  评论 2  弹幕 0  长度 3:05"
+            },
+            "extra": { "tag": "img", "img_key": "img_v2_a" }
+        }, {}, {}]);
+        let fields = parse_legacy(db::ItemKind::Video, &json.to_string());
+        assert_eq!(
+            fields,
+            db::LegacyFields {
+                title: "【A-SOUL】标题".to_string(),
+                uploader_name: Some("某UP主".to_string()),
+                image_key: "img_v2_a".to_string(),
+                duration: Some(185),
+                picture_count: None,
+                stat: db::Stat {
+                    view: Some(10),
+                    reply: Some(2),
+                    danmaku: Some(0),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_video_basic_info() {
+        let mut item = db::Item::sample("BV1xx411c7mD", chrono::Utc::now());
+        item.duration = Some(185);
+        item.stat = db::Stat {
+            view: Some(10),
+            like: Some(5),
+            reply: Some(2),
+            danmaku: Some(0),
+        };
+        let info = video_basic_info(&item);
+        let content = info["text"]["content"].as_str().unwrap();
+        assert!(content.ends_with("\n播放 10  评论 2  弹幕 0  长度 3:05"));
+        // 和旧数据的卡片格式一样，能解析回来
+        let fields = parse_legacy(db::ItemKind::Video, &json!([info]).to_string());
+        assert_eq!(fields.duration, Some(185));
+        assert_eq!(
+            fields.stat,
+            db::Stat {
+                like: None,
+                ..item.stat
+            }
+        );
+
+        // 没有数据的时候不显示
+        item.stat = db::Stat::default();
+        item.duration = None;
+        let info = video_basic_info(&item);
+        let content = info["text"]["content"].as_str().unwrap();
+        assert!(content.ends_with("UP：uploader"));
+    }
+
+    #[test]
+    fn test_parse_legacy_dynamic() {
+        let json = json!([{
+            "tag": "div",
+            "text": {
+                "tag": "lark_md",
+                "content": "[某画师 (3 图)](https://t.bilibili.com/123456)\n第一行\n第二行"
+            },
+            "extra": { "tag": "img", "img_key": "img_v2_b" }
+        }, {}, {}]);
+        let fields = parse_legacy(db::ItemKind::Dynamic, &json.to_string());
+        assert_eq!(
+            fields,
+            db::LegacyFields {
+                title: "第一行\n第二行".to_string(),
+                uploader_name: Some("某画师".to_string()),
+                image_key: "img_v2_b".to_string(),
+                duration: None,
+                picture_count: Some(3),
+                stat: db::Stat::default(),
+            }
+        );
+        // 解析不了的保持默认
+        assert_eq!(
+            parse_legacy(db::ItemKind::Dynamic, ""),
+            db::LegacyFields::default()
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! 分类，存在 DB 里，内存里缓存一份给生成卡片用
+use anyhow::Result;
+use parking_lot::RwLock;
+
+use crate::config::CONFIG;
+use crate::db::{self, Category, ItemKind};
+
+/// 以前 gen-article 里写死的分类顺序，初始化时沿用，专栏里分类的顺序不变
+const LEGACY_ORDER: &[&str] = &[
+    "音乐",
+    "舞蹈",
+    "手书",
+    "精剪混剪",
+    "MMD",
+    "发病",
+    "鬼畜/整活",
+    "炸厨房",
+    "其他",
+];
+
+/// 以前用过的分类名
+const LEGACY_ALIASES: &[(&str, &[&str])] = &[("动态", &["ok"]), ("手书", &["手书/动画"])];
+
+lazy_static::lazy_static! {
+    /// 按顺序排好的所有分类
+    static ref CACHE: RwLock<Vec<Category>> = RwLock::new(vec![]);
+}
+
+/// 从配置的 `video_categories` 生成初始的分类，再加上动态。
+/// 以前排过序的分类按原来的顺序，新的排在后面
+fn seed_categories(video_categories: &[String]) -> Vec<Category> {
+    let aliases = |name: &str| -> Vec<String> {
+        LEGACY_ALIASES
+            .iter()
+            .filter(|(n, _)| *n == name)
+            .flat_map(|(_, aliases)| aliases.iter().map(|s| s.to_string()))
+            .collect()
+    };
+    let mut ans: Vec<Category> = video_categories
+        .iter()
+        .enumerate()
+        .map(|(i, name)| Category {
+            name: name.clone(),
+            kind: ItemKind::Video,
+            sort_order: LEGACY_ORDER
+                .iter()
+                .position(|n| n == name)
+                .unwrap_or(LEGACY_ORDER.len() + i) as i64,
+            aliases: aliases(name),
+            active: true,
+            section_title: None,
+        })
+        .collect();
+    ans.sort_by_key(|c| c.sort_order);
+    let next = ans.last().map(|c| c.sort_order + 1).unwrap_or_default();
+    ans.push(Category {
+        name: "动态".to_string(),
+        kind: ItemKind::Dynamic,
+        sort_order: next,
+        aliases: aliases("动态"),
+        active: true,
+        section_title: None,
+    });
+    ans
+}
+
+/// 启动时调用，分类表为空的时候从配置初始化
+pub async fn init(pool: &db::Pool) -> Result<()> {
+    if Category::all(pool).await?.is_empty() {
+        info!("分类表为空，从配置初始化");
+        for category in seed_categories(&CONFIG.video_categories) {
+            category.insert(pool).await?;
+        }
+    }
+    reload(pool).await
+}
+
+/// 分类修改之后重新载入缓存
+pub async fn reload(pool: &db::Pool) -> Result<()> {
+    let categories = Category::all(pool).await?;
+    debug!("载入 {} 个分类", categories.len());
+    *CACHE.write() = categories;
+    Ok(())
+}
+
+/// 视频卡片上可选的分类
+pub fn video_options() -> Vec<String> {
+    CACHE
+        .read()
+        .iter()
+        .filter(|c| c.active && c.kind == ItemKind::Video)
+        .map(|c| c.name.clone())
+        .collect()
+}
+
+/// 所有启用的分类
+pub fn active_names() -> Vec<String> {
+    CACHE
+        .read()
+        .iter()
+        .filter(|c| c.active)
+        .map(|c| c.name.clone())
+        .collect()
+}
+
+/// 按名字或者旧的名字找分类
+fn find<'a>(categories: &'a [Category], name: &str) -> Option<&'a Category> {
+    categories.iter().find(|c| c.name == name).or_else(|| {
+        categories
+            .iter()
+            .find(|c| c.aliases.iter().any(|a| a == name))
+    })
+}
+
+/// 把旧的分类名换成现在的，不认识的原样返回
+pub fn canonical_name(categories: &[Category], name: &str) -> String {
+    find(categories, name)
+        .map(|c| c.name.clone())
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// 分类名和它所有的别名，用来查询数据库里用旧名字存的条目
+pub fn with_aliases(name: &str) -> Vec<String> {
+    match find(&CACHE.read(), name) {
+        Some(c) => std::iter::once(c.name.clone())
+            .chain(c.aliases.iter().cloned())
+            .collect(),
+        None => vec![name.to_string()],
+    }
+}
+
+/// 启用的分类里找，可以用旧的名字
+pub fn resolve_active(name: &str) -> Option<String> {
+    find(&CACHE.read(), name)
+        .filter(|c| c.active)
+        .map(|c| c.name.clone())
+}
+
+/// 分类的修改不合法，接口按类型返回 400、404 或 409
+#[derive(Debug, PartialEq)]
+pub enum CategoryError {
+    /// 名字是空的
+    EmptyName,
+    NotFound(String),
+    /// 已经有同名的分类
+    Exists(String),
+    /// 名字或别名和其他分类的名字、别名重复
+    Duplicate {
+        name: String,
+        other: String,
+    },
+}
+
+impl std::fmt::Display for CategoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptyName => write!(f, "分类名不能为空"),
+            Self::NotFound(name) => write!(f, "不存在分类 {}", name),
+            Self::Exists(name) => write!(f, "分类 {} 已经存在", name),
+            Self::Duplicate { name, other } => write!(f, "{} 和分类 {} 重复", name, other),
+        }
+    }
+}
+
+impl std::error::Error for CategoryError {}
+
+/// 名字和别名不能和其他分类重复
+pub fn check_conflict(
+    categories: &[Category],
+    category: &Category,
+) -> std::result::Result<(), CategoryError> {
+    let names = std::iter::once(&category.name).chain(category.aliases.iter());
+    for name in names {
+        if let Some(other) = find(categories, name).filter(|c| c.name != category.name) {
+            return Err(CategoryError::Duplicate {
+                name: name.clone(),
+                other: other.name.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// 新建分类，不填顺序时排在最后
+pub async fn create(data: api::NewCategory, pool: &db::Pool) -> Result<Category> {
+    if data.name.trim().is_empty() {
+        return Err(CategoryError::EmptyName.into());
+    }
+    let categories = Category::all(pool).await?;
+    if categories.iter().any(|c| c.name == data.name) {
+        return Err(CategoryError::Exists(data.name).into());
+    }
+    let sort_order = data.sort_order.unwrap_or_else(|| {
+        categories
+            .iter()
+            .map(|c| c.sort_order + 1)
+            .max()
+            .unwrap_or_default()
+    });
+    let category = Category {
+        name: data.name,
+        kind: data.kind,
+        sort_order,
+        aliases: data.aliases,
+        active: data.active,
+        section_title: data.section_title,
+    };
+    check_conflict(&categories, &category)?;
+    category.insert(pool).await?;
+    reload(pool).await?;
+    Ok(category)
+}
+
+/// 只修改填了的字段
+pub async fn update(name: &str, patch: api::CategoryPatch, pool: &db::Pool) -> Result<Category> {
+    let mut category = Category::from_name(name, pool)
+        .await?
+        .ok_or_else(|| CategoryError::NotFound(name.to_string()))?;
+    if let Some(kind) = patch.kind {
+        category.kind = kind;
+    }
+    if let Some(sort_order) = patch.sort_order {
+        category.sort_order = sort_order;
+    }
+    if let Some(aliases) = patch.aliases {
+        category.aliases = aliases;
+    }
+    if let Some(active) = patch.active {
+        category.active = active;
+    }
+    if let Some(section_title) = patch.section_title {
+        // 空字符串表示清空
+        category.section_title = Some(section_title).filter(|s| !s.is_empty());
+    }
+    check_conflict(&Category::all(pool).await?, &category)?;
+    category.update(pool).await?;
+    reload(pool).await?;
+    Ok(category)
+}
+
+/// 返回是否确实删除了
+pub async fn remove(name: &str, pool: &db::Pool) -> Result<bool> {
+    let removed = Category::remove(name, pool).await?;
+    reload(pool).await?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_seed_and_alias() {
+        let config = vec!["音乐".to_string(), "手书".to_string()];
+        let categories = seed_categories(&config);
+        let names: Vec<_> = categories.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["音乐", "手书", "动态"]);
+        assert_eq!(categories[2].kind, ItemKind::Dynamic);
+        assert_eq!(categories[2].sort_order, 3);
+
+        assert_eq!(canonical_name(&categories, "ok"), "动态");
+        assert_eq!(canonical_name(&categories, "手书/动画"), "手书");
+        assert_eq!(canonical_name(&categories, "音乐"), "音乐");
+        assert_eq!(canonical_name(&categories, "炸厨房"), "炸厨房");
+
+        let mut new = categories[0].clone();
+        new.name = "音乐区".to_string();
+        new.aliases = vec!["ok".to_string()];
+        assert_eq!(
+            check_conflict(&categories, &new),
+            Err(CategoryError::Duplicate {
+                name: "ok".to_string(),
+                other: "动态".to_string()
+            })
+        );
+        new.aliases = vec!["音乐".to_string()];
+        assert!(check_conflict(&categories, &new).is_err());
+        new.aliases = vec![];
+        assert!(check_conflict(&categories, &new).is_ok());
+        // 改自己不算重复
+        assert!(check_conflict(&categories, &categories[1]).is_ok());
+    }
+
+    #[test]
+    fn test_seed_keeps_article_order() {
+        // 配置里的顺序和以前专栏的顺序不一样
+        let config: Vec<String> = vec![
+            "其他",
+            "新分类",
+            "炸厨房",
+            "鬼畜/整活",
+            "发病",
+            "MMD",
+            "精剪混剪",
+            "手书",
+            "舞蹈",
+            "音乐",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        let categories = seed_categories(&config);
+        let mut sorted = categories.clone();
+        sorted.sort_by_key(|c| c.sort_order);
+        let names: Vec<_> = sorted.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "音乐",
+                "舞蹈",
+                "手书",
+                "精剪混剪",
+                "MMD",
+                "发病",
+                "鬼畜/整活",
+                "炸厨房",
+                "其他",
+                "新分类",
+                "动态"
+            ]
+        );
+        assert_eq!(sorted[0].sort_order, 0);
+        assert_eq!(sorted[8].sort_order, 8);
+    }
+}
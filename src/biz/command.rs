@@ -0,0 +1,445 @@
+//! 群聊里的机器人命令
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use chrono_tz::Asia::Shanghai;
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::collections::VecDeque;
+
+use crate::biz::callback::EventData;
+use crate::config::CONFIG;
+use crate::{biz, db, feishu::FeishuClient};
+
+const HELP: &str = "\
+/summary [月-日]：当天的分类汇总
+/kpi [月-日]：当天每个人的标记数
+/recat <BV号或动态id> <分类>：修改分类
+/block <uid>：屏蔽 UP 主，不再推送
+/unblock <uid>：取消屏蔽
+/pause [视频|动态]：暂停推送，不填则全部暂停
+/resume [视频|动态]：恢复推送，不填则全部恢复
+/run [视频|动态]：马上拉取一轮，不填则都拉
+/status：运行状态";
+
+lazy_static::lazy_static! {
+    /// 最近处理过的 event_id，飞书没有及时收到响应的时候会重复推送
+    static ref RECENT_EVENTS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Summary(DateTime<Utc>),
+    Kpi(DateTime<Utc>),
+    Recat {
+        id: String,
+        category: String,
+    },
+    Block(i64),
+    Unblock(i64),
+    /// 没有来源时是全部
+    Pause(Option<db::ItemKind>),
+    Resume(Option<db::ItemKind>),
+    Run(Option<db::ItemKind>),
+    Status,
+    Help,
+}
+
+impl Command {
+    /// 不是命令的消息返回 None
+    pub fn parse(text: &str, now: DateTime<Utc>) -> Result<Option<Self>> {
+        // @机器人 会变成 @_user_1 这种
+        let mut words = text.split_whitespace().filter(|w| !w.starts_with("@_"));
+        let command = match words.next() {
+            Some(command) if command.starts_with('/') => command,
+            _ => return Ok(None),
+        };
+        let args: Vec<&str> = words.collect();
+        let command = match (command, args.as_slice()) {
+            ("/summary", []) => Self::Summary(now),
+            ("/summary", [date]) => Self::Summary(parse_date(date, now)?),
+            ("/kpi", []) => Self::Kpi(now),
+            ("/kpi", [date]) => Self::Kpi(parse_date(date, now)?),
+            ("/recat", [id, category]) => Self::Recat {
+                id: id.to_string(),
+                category: category.to_string(),
+            },
+            ("/block", [uid]) => Self::Block(parse_uid(uid)?),
+            ("/unblock", [uid]) => Self::Unblock(parse_uid(uid)?),
+            ("/pause", []) => Self::Pause(None),
+            ("/pause", [source]) => Self::Pause(Some(parse_source(source)?)),
+            ("/resume", []) => Self::Resume(None),
+            ("/resume", [source]) => Self::Resume(Some(parse_source(source)?)),
+            ("/run", []) => Self::Run(None),
+            ("/run", [source]) => Self::Run(Some(parse_source(source)?)),
+            ("/status", []) => Self::Status,
+            ("/help", _) => Self::Help,
+            _ => bail!("无法识别的命令：{}", text.trim()),
+        };
+        Ok(Some(command))
+    }
+}
+
+fn parse_source(s: &str) -> Result<db::ItemKind> {
+    match s {
+        "视频" | "video" => Ok(db::ItemKind::Video),
+        "动态" | "dynamic" => Ok(db::ItemKind::Dynamic),
+        _ => bail!("无法识别的来源：{}，可选 视频 动态", s),
+    }
+}
+
+fn source_name(kind: db::ItemKind) -> &'static str {
+    match kind {
+        db::ItemKind::Video => "视频",
+        db::ItemKind::Dynamic => "动态",
+    }
+}
+
+fn parse_uid(s: &str) -> Result<i64> {
+    s.parse().map_err(|_| anyhow!("uid 应该是数字：{}", s))
+}
+
+/// 支持 `月-日` 和 `年-月-日`，返回那一天中午（UTC+8）的时间
+fn parse_date(s: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let today = now.with_timezone(&Shanghai).date().naive_local();
+    let date = match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        Ok(date) => date,
+        Err(_) => {
+            let invalid = || anyhow!("无法识别的日期：{}，格式为 月-日", s);
+            let (month, day) = s.split_once('-').ok_or_else(invalid)?;
+            let month: u32 = month.parse().map_err(|_| invalid())?;
+            let day: u32 = day.parse().map_err(|_| invalid())?;
+            // 先定年份再构造日期，还没到的日期是去年的，比如跨年的时候 12-31；02-29 也要看那一年是不是闰年
+            let year = if (month, day) > (today.month(), today.day()) {
+                today.year() - 1
+            } else {
+                today.year()
+            };
+            NaiveDate::from_ymd_opt(year, month, day).ok_or_else(invalid)?
+        }
+    };
+    let t = Shanghai
+        .from_local_date(&date)
+        .single()
+        .ok_or_else(|| anyhow!("无法识别的日期：{}", s))?
+        .and_hms(12, 0, 0);
+    Ok(t.with_timezone(&Utc))
+}
+
+/// 飞书的 im.message.receive_v1 事件
+#[derive(Debug, Deserialize)]
+struct MessageEvent {
+    sender: Sender,
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+struct Sender {
+    sender_id: SenderId,
+}
+
+#[derive(Debug, Deserialize)]
+struct SenderId {
+    user_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    chat_id: String,
+    message_type: String,
+    /// json 字符串，文本消息是 {"text": "..."}
+    content: String,
+}
+
+fn is_duplicate(event_id: &str) -> bool {
+    let mut recent = RECENT_EVENTS.lock();
+    if recent.iter().any(|id| id == event_id) {
+        return true;
+    }
+    recent.push_back(event_id.to_string());
+    if recent.len() > 128 {
+        recent.pop_front();
+    }
+    false
+}
+
+/// 处理飞书推送的事件，目前只处理收到消息
+pub async fn handle_event(event: EventData, pool: db::Pool, client: FeishuClient) {
+    if is_duplicate(&event.header.event_id) {
+        info!("重复的事件 {}，忽略", event.header.event_id);
+        return;
+    }
+    if event.header.event_type != "im.message.receive_v1" {
+        debug!("忽略事件 {}", event.header.event_type);
+        return;
+    }
+    if let Err(e) = handle_message(event.event, &pool, &client).await {
+        error!("处理消息失败：{:?}", e);
+    }
+}
+
+async fn handle_message(event: Value, pool: &db::Pool, client: &FeishuClient) -> Result<()> {
+    let event: MessageEvent = serde_json::from_value(event)?;
+    // 不在审核人员名单里的消息直接忽略，不回复，也不暴露命令列表
+    let user_id = event.sender.sender_id.user_id.unwrap_or_default();
+    if !CONFIG.feishu.is_reviewer(&user_id) {
+        debug!("{} 不在审核人员名单中，忽略消息", user_id);
+        return Ok(());
+    }
+    if event.message.message_type != "text" {
+        return Ok(());
+    }
+    #[derive(Debug, Deserialize)]
+    struct TextContent {
+        text: String,
+    }
+    let text = serde_json::from_str::<TextContent>(&event.message.content)?.text;
+    let chat_id = &event.message.chat_id;
+
+    let command = match Command::parse(&text, Utc::now()) {
+        Ok(Some(command)) => command,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            let content = format!("{}\n\n{}", e, HELP);
+            client
+                .send_card(chat_id, biz::cards::text_card("命令错误", &content))
+                .await?;
+            return Ok(());
+        }
+    };
+    info!("{} 执行命令 {:?}", user_id, command);
+
+    let card = match execute(command, &user_id, pool, client).await {
+        Ok((title, content)) => biz::cards::text_card(&title, &content),
+        Err(e) => {
+            error!("执行命令失败：{:?}", e);
+            biz::cards::text_card("执行失败", &e.to_string())
+        }
+    };
+    client.send_card(chat_id, card).await?;
+    Ok(())
+}
+
+/// 返回 (标题, markdown 内容)
+async fn execute(
+    command: Command,
+    operator: &str,
+    pool: &db::Pool,
+    client: &FeishuClient,
+) -> Result<(String, String)> {
+    let date_s = |t: DateTime<Utc>| t.with_timezone(&Shanghai).format("%m-%d").to_string();
+    match command {
+        Command::Summary(t) => {
+            let map = biz::summary::categorized(t, pool).await?;
+            let mut lines = vec![];
+            for (category, links) in map {
+                lines.push(format!("**{}**（{}）", category, links.len()));
+                for link in links {
+                    if link.starts_with("https://") {
+                        lines.push(link);
+                    } else {
+                        lines.push(format!("https://www.bilibili.com/video/{}", link));
+                    }
+                }
+            }
+            if lines.is_empty() {
+                lines.push("还没有分类的内容".to_string());
+            }
+            Ok((format!("{} 汇总", date_s(t)), lines.join("\n")))
+        }
+        Command::Kpi(t) => {
+            let kpi = biz::kpi::daily(t, pool, client).await?;
+            let mut lines: Vec<String> = kpi
+                .into_iter()
+                .map(|(name, times)| format!("{}：{}", name, times))
+                .collect();
+            if lines.is_empty() {
+                lines.push("还没有人标记".to_string());
+            }
+            Ok((format!("{} KPI", date_s(t)), lines.join("\n")))
+        }
+        Command::Recat { id, category } => {
+            if db::Item::from_id(&id, pool).await?.is_none() {
+                bail!("数据库不存在 {} 的条目", id);
+            }
+            let category = match biz::category::resolve_active(&category) {
+                Some(category) => category,
+                None => bail!(
+                    "不存在的分类：{}，可选的分类：{}",
+                    category,
+                    biz::category::active_names().join(" ")
+                ),
+            };
+            // 和卡片、网页上的操作一样，会发到归档群，也要更新飞书里的卡片
+            let item = biz::review::categorize(&id, &category, operator, pool, client).await?;
+            if let Err(e) = biz::review::refresh_card(&item, pool, client).await {
+                warn!("更新 {} 的飞书卡片失败：{:?}", id, e);
+            }
+            Ok(("修改分类".to_string(), format!("{} => {}", id, category)))
+        }
+        Command::Block(uid) => {
+            db::BlockedUser::insert(uid, operator, pool).await?;
+            Ok((
+                "屏蔽 UP 主".to_string(),
+                format!("已屏蔽 {}，不再推送 TA 的视频和动态", uid),
+            ))
+        }
+        Command::Unblock(uid) => {
+            let content = if db::BlockedUser::remove(uid, pool).await? {
+                format!("已取消屏蔽 {}", uid)
+            } else {
+                format!("{} 本来就没有被屏蔽", uid)
+            };
+            Ok(("取消屏蔽".to_string(), content))
+        }
+        Command::Pause(None) => {
+            biz::control::set_paused(true);
+            Ok(("暂停".to_string(), "已暂停推送，/resume 恢复".to_string()))
+        }
+        Command::Pause(Some(kind)) => {
+            biz::control::set_source_paused(kind, true);
+            let content = format!(
+                "已暂停推送{}，/resume {} 恢复",
+                source_name(kind),
+                source_name(kind)
+            );
+            Ok(("暂停".to_string(), content))
+        }
+        Command::Resume(None) => {
+            biz::control::set_paused(false);
+            for kind in db::ItemKind::ALL.iter() {
+                biz::control::set_source_paused(*kind, false);
+            }
+            Ok(("恢复".to_string(), "已恢复推送".to_string()))
+        }
+        Command::Resume(Some(kind)) => {
+            biz::control::set_source_paused(kind, false);
+            let mut content = format!("已恢复推送{}", source_name(kind));
+            if biz::control::is_paused() {
+                content.push_str("，但是现在全部暂停了，/resume 全部恢复");
+            }
+            Ok(("恢复".to_string(), content))
+        }
+        Command::Run(kind) => {
+            let kinds = match kind {
+                Some(kind) => vec![kind],
+                None => db::ItemKind::ALL.to_vec(),
+            };
+            let mut lines = vec![];
+            for kind in kinds {
+                if biz::control::is_source_paused(kind) {
+                    lines.push(format!("{}已暂停，先 /resume 再拉取", source_name(kind)));
+                } else {
+                    biz::control::run_now(kind);
+                    lines.push(format!("马上拉取{}", source_name(kind)));
+                }
+            }
+            Ok(("立即拉取".to_string(), lines.join("\n")))
+        }
+        Command::Status => {
+            let now = Utc::now();
+            let (total, categorized) = db::Item::count_in_date(now, pool).await?;
+            let blocked = db::BlockedUser::all(pool).await?;
+            let state = if biz::control::is_paused() {
+                "已暂停".to_string()
+            } else {
+                db::ItemKind::ALL
+                    .iter()
+                    .map(|&kind| {
+                        let state = if biz::control::is_source_paused(kind) {
+                            "已暂停"
+                        } else {
+                            "运行中"
+                        };
+                        format!("{}{}", source_name(kind), state)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("，")
+            };
+            let content = format!(
+                "推送：{}\n今日推送 {} 条，已分类 {} 条\n屏蔽 UP 主 {} 个",
+                state,
+                total,
+                categorized,
+                blocked.len()
+            );
+            Ok(("运行状态".to_string(), content))
+        }
+        Command::Help => Ok(("命令列表".to_string(), HELP.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        "2021-10-18T04:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let parse = |s: &str| Command::parse(s, now()).unwrap();
+        assert_eq!(parse("随便聊聊"), None);
+        assert_eq!(parse("@_user_1 /status"), Some(Command::Status));
+        assert_eq!(parse("/kpi"), Some(Command::Kpi(now())));
+        assert_eq!(
+            parse("/summary 10-17"),
+            Some(Command::Summary("2021-10-17T04:00:00Z".parse().unwrap()))
+        );
+        assert_eq!(
+            parse("/recat BV1xx411c7mD 音乐"),
+            Some(Command::Recat {
+                id: "BV1xx411c7mD".to_string(),
+                category: "音乐".to_string()
+            })
+        );
+        assert_eq!(parse("/block 114514"), Some(Command::Block(114514)));
+        assert_eq!(parse("/pause"), Some(Command::Pause(None)));
+        assert_eq!(
+            parse("/pause 视频"),
+            Some(Command::Pause(Some(db::ItemKind::Video)))
+        );
+        assert_eq!(
+            parse("/run dynamic"),
+            Some(Command::Run(Some(db::ItemKind::Dynamic)))
+        );
+        assert!(Command::parse("/run 专栏", now()).is_err());
+        assert!(Command::parse("/block abc", now()).is_err());
+        assert!(Command::parse("/recat BV1xx411c7mD", now()).is_err());
+        assert!(Command::parse("/unknown", now()).is_err());
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(
+            parse_date("10-18", now()).unwrap(),
+            "2021-10-18T04:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        // 还没到的日期是去年的
+        assert_eq!(
+            parse_date("12-31", now()).unwrap(),
+            "2020-12-31T04:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            parse_date("2021-01-02", now()).unwrap(),
+            "2021-01-02T04:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert!(parse_date("13-01", now()).is_err());
+        assert!(parse_date("10", now()).is_err());
+
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        // 今年不是闰年
+        assert!(parse_date("02-29", now()).is_err());
+        // 闰年 2 月 29 日之前，指的是去年的，去年不是闰年
+        assert!(parse_date("02-29", at("2024-02-10T04:00:00Z")).is_err());
+        assert_eq!(
+            parse_date("02-29", at("2024-03-01T04:00:00Z")).unwrap(),
+            at("2024-02-29T04:00:00Z")
+        );
+        // 去年是闰年
+        assert_eq!(
+            parse_date("02-29", at("2025-01-10T04:00:00Z")).unwrap(),
+            at("2024-02-29T04:00:00Z")
+        );
+    }
+}
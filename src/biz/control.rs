@@ -0,0 +1,107 @@
+//! 运行时的控制开关：全部暂停、按来源暂停，以及立即拉取一轮
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::db::ItemKind;
+
+/// 暂停之后不再拉取和推送新的视频、动态
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// 一个来源（视频或者动态）的开关
+struct Source {
+    paused: AtomicBool,
+    /// 唤醒拉取的循环，马上拉一轮
+    run_now: Notify,
+}
+
+impl Source {
+    fn new() -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            run_now: Notify::new(),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref VIDEO: Source = Source::new();
+    static ref DYNAMIC: Source = Source::new();
+}
+
+fn source(kind: ItemKind) -> &'static Source {
+    match kind {
+        ItemKind::Video => &VIDEO,
+        ItemKind::Dynamic => &DYNAMIC,
+    }
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+pub fn set_paused(paused: bool) {
+    info!("设置暂停状态：{}", paused);
+    PAUSED.store(paused, Ordering::SeqCst);
+}
+
+/// 全部暂停或者这个来源暂停了
+pub fn is_source_paused(kind: ItemKind) -> bool {
+    is_paused() || source(kind).paused.load(Ordering::SeqCst)
+}
+
+pub fn set_source_paused(kind: ItemKind, paused: bool) {
+    info!("设置 {} 的暂停状态：{}", kind.as_str(), paused);
+    source(kind).paused.store(paused, Ordering::SeqCst);
+}
+
+/// 让拉取的循环马上拉一轮，正在拉的话这一轮结束之后马上再拉一轮
+pub fn run_now(kind: ItemKind) {
+    info!("手动触发拉取 {}", kind.as_str());
+    source(kind).run_now.notify_one();
+}
+
+/// 等到下一轮拉取，被 [`run_now`] 提前唤醒时返回 true
+pub async fn wait(kind: ItemKind, duration: Duration) -> bool {
+    tokio::select! {
+        _ = source(kind).run_now.notified() => true,
+        _ = tokio::time::sleep(duration) => false,
+    }
+}
+
+pub fn state() -> api::ControlState {
+    api::ControlState {
+        paused: is_paused(),
+        sources: ItemKind::ALL
+            .iter()
+            .map(|&kind| api::SourceState {
+                source: kind,
+                paused: source(kind).paused.load(Ordering::SeqCst),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_now() {
+        let kind = ItemKind::Dynamic;
+        assert!(!wait(kind, Duration::from_millis(10)).await);
+        // 先触发再等，也能马上返回
+        run_now(kind);
+        assert!(wait(kind, Duration::from_secs(60)).await);
+        assert!(!wait(kind, Duration::from_millis(10)).await);
+
+        set_source_paused(kind, true);
+        assert!(is_source_paused(kind));
+        assert!(!is_source_paused(ItemKind::Video));
+        let state = state();
+        assert_eq!(state.sources.len(), 2);
+        assert!(state.sources.iter().any(|s| s.source == kind && s.paused));
+        set_source_paused(kind, false);
+        assert!(!is_source_paused(kind));
+    }
+}
@@ -0,0 +1,71 @@
+//! 飞书通讯录，user_id => 名字
+use anyhow::Result;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::feishu::FeishuClient;
+
+/// 通讯录缓存多久
+const TTL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static::lazy_static! {
+    /// user_id => 名字，以及获取的时间
+    static ref CACHE: RwLock<Option<(Instant, HashMap<String, String>)>> = RwLock::new(None);
+}
+
+/// user_id => 名字，缓存一段时间，刷新失败时继续用旧的
+pub async fn names(client: &FeishuClient) -> Result<HashMap<String, String>> {
+    if let Some((t, map)) = &*CACHE.read() {
+        if t.elapsed() < TTL {
+            return Ok(map.clone());
+        }
+    }
+    match client.get_users_in_tenant().await {
+        Ok(users) => {
+            let map: HashMap<String, String> =
+                users.into_iter().map(|u| (u.user_id, u.name)).collect();
+            *CACHE.write() = Some((Instant::now(), map.clone()));
+            Ok(map)
+        }
+        Err(e) => match &*CACHE.read() {
+            Some((_, map)) => {
+                warn!("刷新通讯录失败，使用缓存：{:?}", e);
+                Ok(map.clone())
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// 通过 HTTP 接口标记的记录的标记人
+const HTTP_API: &str = "HTTP API";
+
+/// 标记人的名字，找不到的显示为 ？？？
+pub fn display_name(names: &HashMap<String, String>, user_id: &str) -> String {
+    if user_id == HTTP_API {
+        return HTTP_API.to_string();
+    }
+    names
+        .get(user_id)
+        .cloned()
+        // 网页上标记的记成 web:名字
+        .or_else(|| user_id.strip_prefix("web:").map(|s| s.to_string()))
+        .unwrap_or_else(|| "？？？".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_name() {
+        let names: HashMap<String, String> = vec![("u1".to_string(), "张三".to_string())]
+            .into_iter()
+            .collect();
+        assert_eq!(display_name(&names, "u1"), "张三");
+        assert_eq!(display_name(&names, "web:李四"), "李四");
+        assert_eq!(display_name(&names, "HTTP API"), "HTTP API");
+        assert_eq!(display_name(&names, "u2"), "？？？");
+    }
+}
@@ -0,0 +1,159 @@
+//! 内部的事件总线：新推送的候选和筛选结果。先记进数据库再广播，`/events` 断线重连时从数据库补
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use tokio::sync::{broadcast, Mutex};
+
+use crate::db::{self, EventKind, WebhookEvent};
+use crate::{biz, metrics};
+
+/// 订阅者跟不上时最多攒多少条，超过了订阅者会收到 `Lagged`
+const CHANNEL_CAPACITY: usize = 256;
+/// 重连时最多补多少条
+const REPLAY_LIMIT: u32 = 1000;
+/// 数据库里的事件留多久
+const RETENTION_DAYS: i64 = 7;
+/// 每记多少条清理一次旧事件
+const PRUNE_EVERY: i64 = 1000;
+
+lazy_static::lazy_static! {
+    static ref CHANNEL: broadcast::Sender<api::Event> = broadcast::channel(CHANNEL_CAPACITY).0;
+    /// 记录和广播在一起做，保证广播的顺序和数据库分配的 id 一致，
+    /// 否则订阅者可能先收到大的 id，带着它重连时会漏掉小的
+    static ref PUBLISH_LOCK: Mutex<()> = Mutex::new(());
+}
+
+pub fn subscribe() -> broadcast::Receiver<api::Event> {
+    CHANNEL.subscribe()
+}
+
+/// 重连时要补的
+#[derive(Debug)]
+pub enum Replay {
+    Events(Vec<api::Event>),
+    /// 补不全，客户端要重新拉取
+    Reset(api::EventReset),
+}
+
+async fn replay_at_most(id: i64, limit: u32, pool: &db::Pool) -> Result<Replay> {
+    let events = db::Event::after(id, limit + 1, pool).await?;
+    // id 是自增的，对不上说明中间的已经清理掉了
+    let pruned = events.first().map_or(false, |e| e.id > id + 1);
+    if events.len() > limit as usize || pruned {
+        let last_event_id = db::Event::last_id(pool).await?.unwrap_or(id);
+        return Ok(Replay::Reset(api::EventReset { last_event_id }));
+    }
+    Ok(Replay::Events(events))
+}
+
+/// `id` 之后错过的事件，超过 [`REPLAY_LIMIT`] 条时让客户端重新拉取
+pub async fn replay(id: i64, pool: &db::Pool) -> Result<Replay> {
+    replay_at_most(id, REPLAY_LIMIT, pool).await
+}
+
+/// 指标里的 `reviewer` 只区分在哪里筛选的，不放具体的人，免得标签越来越多
+fn reviewer_label(marker: Option<&str>) -> &'static str {
+    match marker {
+        Some(m) if m.starts_with("web:") => "web",
+        Some("HTTP API") => "api",
+        _ => "feishu",
+    }
+}
+
+fn webhook_event(kind: EventKind) -> Option<WebhookEvent> {
+    match kind {
+        EventKind::CandidateSent => None,
+        EventKind::ItemCategorized => Some(WebhookEvent::ItemCategorized),
+        EventKind::ItemRejected => Some(WebhookEvent::ItemRejected),
+        EventKind::ItemReset => Some(WebhookEvent::ItemReset),
+    }
+}
+
+async fn record(kind: EventKind, item: &db::Item, pool: &db::Pool) -> Result<api::Event> {
+    let item: api::Item = item.clone().into();
+    // 数据库里只存到秒，广播出去的和重连补的要一样
+    let created_at = Utc.timestamp(Utc::now().timestamp(), 0);
+    let id = db::Event::insert(kind, &item, created_at, pool).await?;
+    if id % PRUNE_EVERY == 0 {
+        let before = created_at - chrono::Duration::days(RETENTION_DAYS);
+        let n = db::Event::prune(before, pool).await?;
+        info!("清理了 {} 条旧事件", n);
+    }
+    Ok(api::Event {
+        id,
+        kind,
+        created_at,
+        item,
+    })
+}
+
+/// 记下并广播，筛选结果同时发给 webhook。失败只记日志，不影响推送和筛选
+pub async fn publish(kind: EventKind, item: &db::Item, pool: &db::Pool) {
+    if let (EventKind::ItemCategorized, Some(category)) = (kind, &item.category) {
+        metrics::ITEMS_CATEGORIZED
+            .with_label_values(&[category, reviewer_label(item.marker.as_deref())])
+            .inc();
+    }
+    {
+        let _guard = PUBLISH_LOCK.lock().await;
+        match record(kind, item, pool).await {
+            // 没有订阅者时会返回错误，不用管
+            Ok(event) => drop(CHANNEL.send(event)),
+            Err(e) => error!("记录 {} 的 {} 事件失败：{:?}", item.id, kind.as_str(), e),
+        }
+    }
+    if let Some(event) = webhook_event(kind) {
+        biz::webhook::publish(event, item, pool).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn test_reviewer_label() {
+        assert_eq!(reviewer_label(Some("web:阿草")), "web");
+        assert_eq!(reviewer_label(Some("HTTP API")), "api");
+        assert_eq!(reviewer_label(Some("ou_xxxxxxxx")), "feishu");
+        assert_eq!(reviewer_label(None), "feishu");
+    }
+
+    #[tokio::test]
+    async fn test_publish() -> Result<()> {
+        let pool = db::init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+        let t: DateTime<Utc> = "2021-10-18T04:00:00Z".parse().unwrap();
+
+        // 候选推送不发 webhook，测试里不需要配置文件
+        let mut rx = subscribe();
+        publish(EventKind::CandidateSent, &db::Item::sample("BV1", t), &pool).await;
+        publish(EventKind::CandidateSent, &db::Item::sample("BV2", t), &pool).await;
+        let first = rx.recv().await?;
+        let second = rx.recv().await?;
+        assert_eq!(first.kind, EventKind::CandidateSent);
+        assert_eq!(second.item.id, "BV2");
+
+        // 重连时补的和广播的一样
+        let missed = match replay(first.id, &pool).await? {
+            Replay::Events(events) => events,
+            r => panic!("{:?}", r),
+        };
+        assert_eq!(missed.len(), 1);
+        assert_eq!(missed[0].id, second.id);
+        assert_eq!(missed[0].created_at, second.created_at);
+
+        // 错过的太多时不补，从最新的接着发
+        publish(EventKind::CandidateSent, &db::Item::sample("BV3", t), &pool).await;
+        let third = rx.recv().await?;
+        match replay_at_most(first.id, 1, &pool).await? {
+            Replay::Reset(reset) => assert_eq!(reset.last_event_id, third.id),
+            r => panic!("{:?}", r),
+        }
+        match replay_at_most(second.id, 1, &pool).await? {
+            Replay::Events(events) => assert_eq!(events[0].id, third.id),
+            r => panic!("{:?}", r),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+//! 导出一段时间内已经分类的条目，给编辑做表格用
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Asia::Shanghai;
+use std::collections::HashMap;
+
+use crate::{biz, db};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    Csv,
+    Jsonl,
+    Xlsx,
+}
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "csv" => Format::Csv,
+            "jsonl" => Format::Jsonl,
+            "xlsx" => Format::Xlsx,
+            _ => bail!("不支持的格式：{}，可选 csv jsonl xlsx", s),
+        })
+    }
+}
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Jsonl => "jsonl",
+            Format::Xlsx => "xlsx",
+        }
+    }
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Jsonl => "application/x-ndjson",
+            Format::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        }
+    }
+}
+
+/// 导出的一行
+#[derive(Debug, Serialize)]
+pub struct Row {
+    pub link: String,
+    pub id: String,
+    pub kind: db::ItemKind,
+    pub title: String,
+    pub uploader_uid: Option<i64>,
+    pub uploader_name: String,
+    pub category: String,
+    pub marker: Option<String>,
+    pub marker_name: Option<String>,
+    /// 北京时间
+    pub published_at: String,
+    pub marked_at: Option<String>,
+    /// 视频长度，秒
+    pub duration: Option<u32>,
+    pub picture_count: Option<u32>,
+    pub source_tags: Vec<String>,
+    /// 推送时的播放、点赞、评论、弹幕数
+    pub view_count: Option<u32>,
+    pub like_count: Option<u32>,
+    pub reply_count: Option<u32>,
+    pub danmaku_count: Option<u32>,
+}
+
+/// csv 和 xlsx 的表头，和 [`Row::cells`] 一一对应
+const HEADERS: [&str; 18] = [
+    "链接",
+    "id",
+    "类型",
+    "标题",
+    "UP 主 uid",
+    "UP 主",
+    "分类",
+    "标记人 id",
+    "标记人",
+    "发布时间",
+    "标记时间",
+    "视频长度（秒）",
+    "图片数",
+    "来源 tag",
+    "播放",
+    "点赞",
+    "评论",
+    "弹幕",
+];
+
+fn format_time(t: DateTime<Utc>) -> String {
+    t.with_timezone(&Shanghai)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+impl Row {
+    fn new(item: db::Item, categories: &[db::Category], names: &HashMap<String, String>) -> Self {
+        let link = match item.kind {
+            db::ItemKind::Video => format!("https://www.bilibili.com/video/{}", item.id),
+            db::ItemKind::Dynamic => format!("https://t.bilibili.com/{}", item.id),
+        };
+        let category =
+            biz::category::canonical_name(categories, item.category.as_deref().unwrap_or_default());
+        Self {
+            link,
+            id: item.id,
+            kind: item.kind,
+            title: item.title,
+            uploader_uid: item.uploader_uid,
+            uploader_name: item.uploader_name,
+            category,
+            marker_name: item
+                .marker
+                .as_deref()
+                .map(|m| biz::directory::display_name(names, m)),
+            marker: item.marker,
+            published_at: format_time(item.published_at),
+            marked_at: item.marked_at.map(format_time),
+            duration: item.duration,
+            picture_count: item.picture_count,
+            source_tags: item.source_tags,
+            view_count: item.stat.view,
+            like_count: item.stat.like,
+            reply_count: item.stat.reply,
+            danmaku_count: item.stat.danmaku,
+        }
+    }
+
+    fn cells(&self) -> Vec<String> {
+        fn opt<T: ToString>(v: &Option<T>) -> String {
+            v.as_ref().map(|v| v.to_string()).unwrap_or_default()
+        }
+        vec![
+            self.link.clone(),
+            self.id.clone(),
+            self.kind.as_str().to_string(),
+            self.title.clone(),
+            opt(&self.uploader_uid),
+            self.uploader_name.clone(),
+            self.category.clone(),
+            opt(&self.marker),
+            opt(&self.marker_name),
+            self.published_at.clone(),
+            opt(&self.marked_at),
+            opt(&self.duration),
+            opt(&self.picture_count),
+            self.source_tags.join(","),
+            opt(&self.view_count),
+            opt(&self.like_count),
+            opt(&self.reply_count),
+            opt(&self.danmaku_count),
+        ]
+    }
+}
+
+/// 北京时间 `from` 到 `to` 已经分类的条目，两头都包含。`names` 为 user_id => 名字
+pub async fn rows(
+    from: NaiveDate,
+    to: NaiveDate,
+    names: &HashMap<String, String>,
+    pool: &db::Pool,
+) -> Result<Vec<Row>> {
+    biz::summary::check_range(from, to)?;
+    let items = db::Item::all_categorized_in_dates(from, to, pool).await?;
+    let categories = db::Category::all(pool).await?;
+    Ok(items
+        .into_iter()
+        .map(|item| Row::new(item, &categories, names))
+        .collect())
+}
+
+pub fn render(rows: &[Row], format: Format) -> Result<Vec<u8>> {
+    match format {
+        Format::Csv => {
+            // 带上 BOM，不然 Excel 打开中文会乱码
+            let mut writer = csv::Writer::from_writer(b"\xEF\xBB\xBF".to_vec());
+            writer.write_record(&HEADERS)?;
+            for row in rows {
+                writer.write_record(row.cells())?;
+            }
+            writer.into_inner().context("写入 csv 失败")
+        }
+        Format::Jsonl => {
+            let mut buf = vec![];
+            for row in rows {
+                serde_json::to_writer(&mut buf, row)?;
+                buf.push(b'\n');
+            }
+            Ok(buf)
+        }
+        Format::Xlsx => {
+            use simple_excel_writer::{Row as XlsxRow, Workbook};
+            let mut workbook = Workbook::create_in_memory();
+            let mut sheet = workbook.create_sheet("导出");
+            workbook.write_sheet(&mut sheet, |writer| {
+                let mut header = XlsxRow::new();
+                for h in HEADERS.iter() {
+                    header.add_cell(*h);
+                }
+                writer.append_row(header)?;
+                for row in rows {
+                    let mut r = XlsxRow::new();
+                    for cell in row.cells() {
+                        r.add_cell(cell);
+                    }
+                    writer.append_row(r)?;
+                }
+                Ok(())
+            })?;
+            workbook.close()?.context("写入 xlsx 失败")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_rows() -> Vec<Row> {
+        let t: DateTime<Utc> = "2021-10-18T04:00:00Z".parse().unwrap();
+        let mut video = db::Item::sample("BV1xx411c7mD", t);
+        video.title = "标题, 带 \"引号\"".to_string();
+        video.category = Some("音乐".to_string());
+        video.marker = Some("u1".to_string());
+        video.marked_at = Some(t);
+        video.duration = Some(75);
+        video.stat = db::Stat {
+            view: Some(1000),
+            like: Some(100),
+            reply: Some(10),
+            danmaku: None,
+        };
+        let mut dynamic = db::Item::sample("123", t);
+        dynamic.category = Some("ok".to_string());
+        let categories = vec![db::Category {
+            name: "动态".to_string(),
+            kind: db::ItemKind::Dynamic,
+            sort_order: 0,
+            aliases: vec!["ok".to_string()],
+            active: true,
+            section_title: None,
+        }];
+        let names = vec![("u1".to_string(), "张三".to_string())]
+            .into_iter()
+            .collect();
+        vec![video, dynamic]
+            .into_iter()
+            .map(|item| Row::new(item, &categories, &names))
+            .collect()
+    }
+
+    #[test]
+    fn test_render_csv() {
+        let csv = String::from_utf8(render(&sample_rows(), Format::Csv).unwrap()).unwrap();
+        let lines: Vec<&str> = csv.trim_start_matches('\u{feff}').lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("链接,id,类型"));
+        assert_eq!(
+            lines[1],
+            "https://www.bilibili.com/video/BV1xx411c7mD,BV1xx411c7mD,video,\
+             \"标题, 带 \"\"引号\"\"\",1,uploader,音乐,u1,张三,\
+             2021-10-18 12:00:00,2021-10-18 12:00:00,75,,,1000,100,10,"
+        );
+        assert!(
+            lines[2].starts_with("https://t.bilibili.com/123,123,dynamic,title,1,uploader,动态,,,")
+        );
+    }
+
+    #[test]
+    fn test_render_jsonl() {
+        let jsonl = render(&sample_rows(), Format::Jsonl).unwrap();
+        let rows: Vec<serde_json::Value> = jsonl
+            .split(|b| *b == b'\n')
+            .filter(|l| !l.is_empty())
+            .map(|l| serde_json::from_slice(l).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["marker_name"], "张三");
+        assert_eq!(rows[0]["view_count"], 1000);
+        assert_eq!(rows[0]["danmaku_count"], serde_json::Value::Null);
+        assert_eq!(rows[1]["category"], "动态");
+        assert_eq!(rows[1]["kind"], "dynamic");
+    }
+
+    #[test]
+    fn test_render_xlsx() {
+        let xlsx = render(&sample_rows(), Format::Xlsx).unwrap();
+        // xlsx 是 zip，表格在 sheet1.xml 里
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(xlsx)).unwrap();
+        let mut sheet = String::new();
+        std::io::Read::read_to_string(
+            &mut zip.by_name("xl/worksheets/sheet1.xml").unwrap(),
+            &mut sheet,
+        )
+        .unwrap();
+        for cell in ["播放", "点赞", "评论", "弹幕", "张三"].iter() {
+            assert!(sheet.contains(cell), "缺少 {}", cell);
+        }
+        assert!(sheet.contains(">1000<") && sheet.contains(">100<") && sheet.contains(">10<"));
+    }
+}
@@ -1,10 +1,14 @@
 //! 拉群
+use std::time::Duration;
+
 use anyhow::{Context, Result};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, Utc};
 use chrono_tz::Asia::Shanghai;
 use tokio::sync::Mutex;
 
-use crate::config::CONFIG;
+use crate::config::{ArchiveAction, Rotation, CONFIG};
+use crate::db::GroupState;
+use crate::supervisor::Task;
 use crate::{db, feishu::FeishuClient};
 
 lazy_static::lazy_static! {
@@ -12,45 +16,163 @@ lazy_static::lazy_static! {
     static ref LOCK: Mutex<()> = Mutex::new(());
 }
 
-pub async fn create_group_with_time(
+/// 按轮换方式给群名加上日期，如 `视频筛选 10-18`、`视频筛选 10-18~10-24`
+fn group_name(name: &str, time: DateTime<Utc>, rotation: Rotation, dev_mode: bool) -> String {
+    let name = if dev_mode {
+        format!("{} dev", name)
+    } else {
+        name.to_string()
+    };
+    let date = time.with_timezone(&Shanghai).date();
+    match rotation {
+        Rotation::Persistent => name,
+        Rotation::Weekly => {
+            let monday =
+                date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64);
+            let sunday = monday + chrono::Duration::days(6);
+            format!(
+                "{} {}~{}",
+                name,
+                monday.format("%m-%d"),
+                sunday.format("%m-%d")
+            )
+        }
+        Rotation::Daily => format!("{} {}", name, date.format("%m-%d")),
+    }
+}
+
+/// 建群的时候把 `user_ids` 拉进群
+pub async fn create_group_with_members(
     name: &str,
     time: DateTime<Utc>,
+    user_ids: &[String],
     pool: &db::Pool,
     client: &FeishuClient,
 ) -> Result<db::Group> {
     let dev_mode = std::option_env!("DEV").is_some();
+    let group_name = group_name(name, time, CONFIG.group.rotation, dev_mode);
 
-    let date_s = time.with_timezone(&Shanghai).format("%m-%d");
-    let group_name = if dev_mode {
-        format!("{} dev {}", name, date_s)
-    } else {
-        format!("{} {}", name, date_s)
-    };
-
-    // 不加锁也可以直接读
-    if let Some(group) = db::Group::from_name(&group_name, pool).await? {
-        info!("从 DB 中查到群 {} 的信息，不再新拉群", group_name);
-        return Ok(group);
+    // 查询和拉人都在锁里，归档不会在中间把群归档掉
+    let _guard = LOCK.lock().await;
+    match db::Group::from_name(&group_name, pool).await? {
+        Some(group) if group.state == GroupState::Active => {
+            info!("从 DB 中查到群 {} 的信息，不再新拉群", group_name);
+            db::Group::touch(&group_name, pool).await?;
+            return Ok(group);
+        }
+        Some(group) => info!("群 {} 已经归档（{:?}），重新启用", group_name, group.state),
+        None => {}
     }
     // 不存在，创建
-    let _guard = LOCK.lock().await;
-    // 现在有唯一的对 group 表的访问权限了
-    // TODO: 把 get_or_create_group 替换成 create_group，前者总会失败
+    // 只移出了人的群机器人还在里面，会被找到；解散了的会新建
     let feishu_group = client.get_or_create_group(&group_name).await?;
     let chat_id = feishu_group.chat_id;
-    let user_ids = CONFIG.feishu.init_user_ids.clone();
     client
-        .ensure_users_in_group(user_ids, &chat_id)
+        .ensure_users_in_group(user_ids.to_vec(), &chat_id)
         .await
         .context("确保人在群里失败")?;
     // 现在插入表
-    let group = db::Group::insert(&group_name, &chat_id, pool).await?;
+    let group = db::Group::upsert(&group_name, &chat_id, pool).await?;
 
     debug!("群聊：{:?}", group);
     Ok(group)
 }
 
-/// 拉一个确保我在其中的群，如 动态筛选
-pub async fn create_group(name: &str, client: &FeishuClient, pool: &db::Pool) -> Result<db::Group> {
-    create_group_with_time(name, Utc::now(), pool, client).await
+/// 定期归档很久没有推送的群，没有配置 `archive_after_days` 时什么都不做
+pub async fn archive_forever(client: FeishuClient, pool: db::Pool, task: Task) -> ! {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match archive_stale_groups(&client, &pool).await {
+            Ok(()) => task.succeeded(),
+            Err(e) => {
+                error!("归档群失败：{:?}", e);
+                task.failed(&e);
+            }
+        }
+    }
+}
+
+async fn archive_stale_groups(client: &FeishuClient, pool: &db::Pool) -> Result<()> {
+    let days = match CONFIG.group.archive_after_days {
+        Some(days) => days,
+        None => return Ok(()),
+    };
+    let before = Utc::now() - chrono::Duration::days(days as i64);
+    let groups = db::Group::inactive_since(before, pool).await?;
+    for group in groups {
+        // 和建群互斥，避免刚归档的群又被用上
+        let _guard = LOCK.lock().await;
+        // 等锁的时候群可能刚被用过，重新确认
+        if !db::Group::inactive_since(before, pool)
+            .await?
+            .iter()
+            .any(|g| g.name == group.name)
+        {
+            debug!("群 {} 刚刚用过，不归档", group.name);
+            continue;
+        }
+        info!("群 {} 已经 {} 天没有推送，开始归档", group.name, days);
+        match archive_group(&group, client).await {
+            Ok(state) => db::Group::set_state(&group.name, state, pool).await?,
+            // 单个群失败不影响其他群，下次再试
+            Err(e) => error!("归档群 {} 失败：{:?}", group.name, e),
+        }
+    }
+    Ok(())
+}
+
+async fn archive_group(group: &db::Group, client: &FeishuClient) -> Result<GroupState> {
+    match CONFIG.group.archive_action {
+        ArchiveAction::Dissolve => {
+            client.dissolve_group(&group.chat_id).await?;
+            Ok(GroupState::Dissolved)
+        }
+        ArchiveAction::RemoveMembers => {
+            let user_ids: Vec<String> = client
+                .get_group_users(&group.chat_id)
+                .await?
+                .into_iter()
+                .map(|u| u.member_id)
+                .collect();
+            // 一次最多移出 50 人
+            for ids in user_ids.chunks(50) {
+                client.remove_users_from_group(ids, &group.chat_id).await?;
+            }
+            Ok(GroupState::MembersRemoved)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_group_name() {
+        // 北京时间 2021-10-20 周三
+        let t: DateTime<Utc> = "2021-10-20T04:00:00Z".parse().unwrap();
+        assert_eq!(
+            group_name("视频筛选", t, Rotation::Daily, false),
+            "视频筛选 10-20"
+        );
+        assert_eq!(
+            group_name("视频筛选", t, Rotation::Daily, true),
+            "视频筛选 dev 10-20"
+        );
+        assert_eq!(
+            group_name("视频筛选", t, Rotation::Weekly, false),
+            "视频筛选 10-18~10-24"
+        );
+        assert_eq!(
+            group_name("视频筛选", t, Rotation::Persistent, false),
+            "视频筛选"
+        );
+        // UTC 周日晚上已经是北京时间周一
+        let t: DateTime<Utc> = "2021-10-24T17:00:00Z".parse().unwrap();
+        assert_eq!(
+            group_name("动态筛选", t, Rotation::Weekly, false),
+            "动态筛选 10-25~10-31"
+        );
+    }
 }
@@ -0,0 +1,227 @@
+//! 上传图片到飞书，按图片的来源 url 和内容 hash 缓存 image_key
+use anyhow::{Context, Result};
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, io::Reader, ColorType, GenericImageView,
+    ImageFormat,
+};
+use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+
+use crate::config::CONFIG;
+use crate::{db, feishu::FeishuClient, metrics};
+
+/// 内置的占位图，没有配置 fallback 图片的时候启动时上传
+const PLACEHOLDER: &[u8] = include_bytes!("../../assets/placeholder.png");
+
+/// 飞书上传图片限制 10 MiB，留一点余量
+const MAX_UPLOAD_SIZE: usize = 9 * 1024 * 1024;
+/// 压缩时最长边的像素数
+const MAX_DIMENSION: u32 = 2048;
+/// 怎么缩都超过大小的时候，最长边最小缩到多少
+const MIN_DIMENSION: u32 = 256;
+/// 依次尝试的 jpeg 质量
+const JPEG_QUALITIES: [u8; 5] = [90, 80, 70, 60, 50];
+
+lazy_static::lazy_static! {
+    static ref FALLBACK_IMAGE_KEY: RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// 图片内容对应的 source
+fn content_source(bytes: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(bytes))
+}
+
+/// 上传图片，同样内容的图片上传过的话直接返回缓存的 image_key
+pub async fn upload_bytes(
+    bytes: Vec<u8>,
+    client: &FeishuClient,
+    pool: &db::Pool,
+) -> Result<String> {
+    let source = content_source(&bytes);
+    if let Some(image_key) = db::ImageCache::get(&source, pool).await? {
+        debug!("图片 {} 已经上传过了：{}", source, image_key);
+        return Ok(image_key);
+    }
+    let bytes = match preprocess(bytes.clone()).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("压缩图片失败，上传原图：{:?}", e);
+            bytes
+        }
+    };
+    let timer = metrics::IMAGE_SECONDS
+        .with_label_values(&["upload"])
+        .start_timer();
+    let image_key = client.upload_image_bytes(bytes).await?;
+    timer.observe_duration();
+    db::ImageCache::insert(&source, &image_key, pool).await?;
+    Ok(image_key)
+}
+
+/// 把图片压缩到飞书的大小限制以内，在 blocking 线程池里进行，不会卡住拉取的循环
+pub async fn preprocess(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    tokio::task::spawn_blocking(move || compress(&bytes, MAX_DIMENSION, MAX_UPLOAD_SIZE)).await?
+}
+
+/// 缩放到最长边不超过 `max_dimension`，然后逐步降低 jpeg 质量直到不超过 `max_size`。
+///
+/// 本身就足够小的图片原样返回；gif 只取第一帧。
+fn compress(bytes: &[u8], max_dimension: u32, max_size: usize) -> Result<Vec<u8>> {
+    let reader = Reader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader.format().context("无法识别图片格式")?;
+    let (w, h) = reader.into_dimensions().context("读取图片大小失败")?;
+    if format != ImageFormat::Gif && bytes.len() <= max_size && w.max(h) <= max_dimension {
+        return Ok(bytes.to_vec());
+    }
+
+    // gif 解码出来就是第一帧
+    let img = image::load_from_memory_with_format(bytes, format).context("解码图片失败")?;
+    let mut dimension = max_dimension.min(w.max(h));
+    loop {
+        let resized = if img.width().max(img.height()) > dimension {
+            img.resize(dimension, dimension, FilterType::Triangle)
+        } else {
+            img.clone()
+        };
+        let rgb = resized.to_rgb8();
+        for &quality in JPEG_QUALITIES.iter() {
+            let mut buf = vec![];
+            JpegEncoder::new_with_quality(&mut buf, quality).encode(
+                rgb.as_raw(),
+                rgb.width(),
+                rgb.height(),
+                ColorType::Rgb8,
+            )?;
+            debug!(
+                "图片压缩：{}x{} => {}x{}, quality = {}, {:.2} MiB",
+                w,
+                h,
+                rgb.width(),
+                rgb.height(),
+                quality,
+                buf.len() as f64 / 1024. / 1024.
+            );
+            if buf.len() <= max_size {
+                return Ok(buf);
+            }
+        }
+        if dimension <= MIN_DIMENSION {
+            bail!("图片压缩到 {} 像素仍然超过大小限制", dimension);
+        }
+        dimension = (dimension / 2).max(MIN_DIMENSION);
+    }
+}
+
+/// 下载并上传 url 对应的图片，url 或者图片内容上传过的话都不会重复上传
+pub async fn upload_url(url: &str, client: &FeishuClient, pool: &db::Pool) -> Result<String> {
+    if let Some(image_key) = db::ImageCache::get(url, pool).await? {
+        debug!("图片 {} 已经上传过了：{}", url, image_key);
+        return Ok(image_key);
+    }
+    let timer = metrics::IMAGE_SECONDS
+        .with_label_values(&["download"])
+        .start_timer();
+    let bytes = client
+        .client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    timer.observe_duration();
+    debug!(
+        "image downloaded, size = {:.2} MiB",
+        bytes.len() as f64 / 1024. / 1024.
+    );
+    let image_key = upload_bytes(bytes.to_vec(), client, pool).await?;
+    db::ImageCache::insert(url, &image_key, pool).await?;
+    Ok(image_key)
+}
+
+/// 图片下载、合并或者上传失败的时候使用的图片。
+/// 优先用配置里的 image_key，否则上传内置的占位图，确定之后缓存起来
+pub async fn fallback(client: &FeishuClient, pool: &db::Pool) -> Result<String> {
+    let cached = FALLBACK_IMAGE_KEY.read().clone();
+    if let Some(image_key) = cached {
+        return Ok(image_key);
+    }
+    let image_key = match &CONFIG.feishu.fallback_image_key {
+        Some(image_key) => image_key.clone(),
+        None => upload_bytes(PLACEHOLDER.to_vec(), client, pool)
+            .await
+            .context("上传内置占位图失败")?,
+    };
+    info!("fallback 图片：{}", image_key);
+    *FALLBACK_IMAGE_KEY.write() = Some(image_key.clone());
+    Ok(image_key)
+}
+
+/// 启动时先确定 fallback 图片，有问题的话尽早发现
+pub async fn init_fallback(client: &FeishuClient, pool: &db::Pool) -> Result<()> {
+    fallback(client, pool).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{DynamicImage, ImageOutputFormat, RgbImage};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// 一张随机噪点图，png 和 jpeg 都压不小
+    fn noise(w: u32, h: u32) -> DynamicImage {
+        let mut rng = StdRng::seed_from_u64(0);
+        let img = RgbImage::from_fn(w, h, |_, _| image::Rgb(rng.gen()));
+        DynamicImage::ImageRgb8(img)
+    }
+
+    fn encode(img: &DynamicImage, format: ImageOutputFormat) -> Vec<u8> {
+        let mut buf = vec![];
+        img.write_to(&mut buf, format).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_compress_small_image_unchanged() {
+        let bytes = encode(&noise(64, 64), ImageOutputFormat::Png);
+        let compressed = compress(&bytes, 2048, 1024 * 1024).unwrap();
+        assert_eq!(compressed, bytes);
+    }
+
+    #[test]
+    fn test_compress_resize() {
+        let bytes = encode(&noise(1600, 400), ImageOutputFormat::Png);
+        let compressed = compress(&bytes, 800, 10 * 1024 * 1024).unwrap();
+        assert_eq!(image::guess_format(&compressed).unwrap(), ImageFormat::Jpeg);
+        let img = image::load_from_memory(&compressed).unwrap();
+        assert_eq!(img.dimensions(), (800, 200));
+    }
+
+    #[test]
+    fn test_compress_size_limit() {
+        let bytes = encode(&noise(1024, 1024), ImageOutputFormat::Png);
+        let max_size = 300 * 1024;
+        assert!(bytes.len() > max_size);
+        let compressed = compress(&bytes, 2048, max_size).unwrap();
+        assert!(compressed.len() <= max_size);
+    }
+
+    #[test]
+    fn test_compress_gif() {
+        let bytes = encode(&noise(100, 50), ImageOutputFormat::Gif);
+        let compressed = compress(&bytes, 2048, 1024 * 1024).unwrap();
+        assert_eq!(image::guess_format(&compressed).unwrap(), ImageFormat::Jpeg);
+        let img = image::load_from_memory(&compressed).unwrap();
+        assert_eq!(img.dimensions(), (100, 50));
+    }
+
+    #[test]
+    fn test_content_source() {
+        assert_eq!(
+            content_source(b"abc"),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
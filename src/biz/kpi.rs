@@ -0,0 +1,244 @@
+//! 每个人的标记数量
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use chrono_tz::Asia::Shanghai;
+use std::collections::BTreeMap;
+
+use crate::feishu::{FeishuClient, Receiver};
+use crate::supervisor::Task;
+use crate::{biz, db};
+
+/// 每周一几点发排行榜（北京时间）
+const LEADERBOARD_HOUR: u32 = 10;
+
+/// 返回 (名字, 标记次数)
+pub async fn daily(
+    date: DateTime<Utc>,
+    pool: &db::Pool,
+    client: &FeishuClient,
+) -> Result<Vec<(String, u32)>> {
+    let kpi = db::Item::get_kpi(date, pool).await?;
+    let names = biz::directory::names(client).await?;
+    let result = kpi
+        .into_iter()
+        .map(|(user_id, times)| (biz::directory::display_name(&names, &user_id), times))
+        .collect();
+    Ok(result)
+}
+
+pub use api::ReviewerKpi;
+
+/// 按人汇总，标记多的在前
+fn aggregate(marks: Vec<db::Mark>, categories: &[db::Category]) -> Vec<ReviewerKpi> {
+    let mut by_marker: BTreeMap<String, Vec<db::Mark>> = BTreeMap::new();
+    for mark in marks {
+        by_marker.entry(mark.marker.clone()).or_default().push(mark);
+    }
+    let mut ans: Vec<ReviewerKpi> = by_marker
+        .into_iter()
+        .map(|(user_id, marks)| {
+            let mut by_category: BTreeMap<String, u32> = BTreeMap::new();
+            let mut by_kind: BTreeMap<String, u32> = BTreeMap::new();
+            let mut latencies = vec![];
+            for mark in &marks {
+                if let Some(category) = &mark.category {
+                    let category = biz::category::canonical_name(categories, category);
+                    *by_category.entry(category).or_default() += 1;
+                }
+                *by_kind.entry(mark.kind.as_str().to_string()).or_default() += 1;
+                latencies.push((mark.marked_at - mark.pushed_at).num_seconds().max(0));
+            }
+            latencies.sort_unstable();
+            let total = marks.len() as u32;
+            let accepted = by_category.values().sum();
+            ReviewerKpi {
+                name: user_id.clone(),
+                user_id,
+                total,
+                accepted,
+                rejected: total - accepted,
+                by_category,
+                by_kind,
+                avg_latency_secs: latencies.iter().sum::<i64>() / latencies.len() as i64,
+                median_latency_secs: latencies[latencies.len() / 2],
+            }
+        })
+        .collect();
+    ans.sort_by(|a, b| b.total.cmp(&a.total));
+    ans
+}
+
+/// 北京时间 `from` 到 `to` 每个人的标记，两头都包含
+pub async fn range(
+    from: NaiveDate,
+    to: NaiveDate,
+    pool: &db::Pool,
+    client: &FeishuClient,
+) -> Result<Vec<ReviewerKpi>> {
+    biz::summary::check_range(from, to)?;
+    let marks = db::Item::marks_in_dates(from, to, pool).await?;
+    let categories = db::Category::all(pool).await?;
+    let mut kpis = aggregate(marks, &categories);
+    let names = biz::directory::names(client).await?;
+    for kpi in kpis.iter_mut() {
+        kpi.name = biz::directory::display_name(&names, &kpi.user_id);
+    }
+    Ok(kpis)
+}
+
+/// `now` 之后的第一个周一上午
+fn next_leaderboard_time(now: DateTime<Utc>) -> DateTime<Utc> {
+    let today = now.with_timezone(&Shanghai).date();
+    let monday = today - chrono::Duration::days(today.weekday().num_days_from_monday() as i64);
+    let t = monday.and_hms(LEADERBOARD_HOUR, 0, 0).with_timezone(&Utc);
+    if t > now {
+        t
+    } else {
+        t + chrono::Duration::weeks(1)
+    }
+}
+
+fn leaderboard_markdown(kpis: &[ReviewerKpi]) -> String {
+    if kpis.is_empty() {
+        return "上周没有人标记".to_string();
+    }
+    kpis.iter()
+        .enumerate()
+        .map(|(i, k)| {
+            let rank = match i {
+                0 => "🥇".to_string(),
+                1 => "🥈".to_string(),
+                2 => "🥉".to_string(),
+                _ => format!("{}.", i + 1),
+            };
+            format!(
+                "{} **{}**：{} 条（选入 {}，拒绝 {}），平均 {} 分钟",
+                rank,
+                k.name,
+                k.total,
+                k.accepted,
+                k.rejected,
+                k.avg_latency_secs / 60
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 发 `t` 所在这周之前一周的排行榜
+async fn post_leaderboard(
+    chat_id: &str,
+    t: DateTime<Utc>,
+    client: &FeishuClient,
+    pool: &db::Pool,
+) -> Result<()> {
+    let today = t.with_timezone(&Shanghai).date().naive_local();
+    let from = today - chrono::Duration::days(7);
+    let to = today - chrono::Duration::days(1);
+    let kpis = range(from, to, pool, client).await?;
+    let title = format!("{}~{} 标记排行榜", from.format("%m-%d"), to.format("%m-%d"));
+    let card = biz::cards::text_card(&title, &leaderboard_markdown(&kpis));
+    // 重启后马上又到点的时候由飞书去重
+    let uuid = format!("leaderboard-{}", from);
+    let receiver = Receiver::Chat(chat_id.to_string());
+    client.send_card_once(&receiver, card, &uuid).await?;
+    info!("已发送 {} 排行榜", title);
+    Ok(())
+}
+
+/// 每周一上午把上周的排行榜发到 `chat_id`
+pub async fn leaderboard_forever(
+    chat_id: String,
+    client: FeishuClient,
+    pool: db::Pool,
+    task: Task,
+) -> ! {
+    loop {
+        let now = Utc::now();
+        let next = next_leaderboard_time(now);
+        info!("下次发送排行榜：{}", next.with_timezone(&Shanghai));
+        tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+        match post_leaderboard(&chat_id, next, &client, &pool).await {
+            Ok(()) => task.succeeded(),
+            Err(e) => {
+                error!("发送排行榜失败：{:?}", e);
+                task.failed(&e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mark(marker: &str, category: Option<&str>, latency_minutes: i64) -> db::Mark {
+        let t: DateTime<Utc> = "2021-10-18T04:00:00Z".parse().unwrap();
+        db::Mark {
+            marker: marker.to_string(),
+            kind: if category == Some("ok") {
+                db::ItemKind::Dynamic
+            } else {
+                db::ItemKind::Video
+            },
+            category: category.map(|s| s.to_string()),
+            pushed_at: t,
+            marked_at: t + chrono::Duration::minutes(latency_minutes),
+        }
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let categories = vec![db::Category {
+            name: "动态".to_string(),
+            kind: db::ItemKind::Dynamic,
+            sort_order: 0,
+            aliases: vec!["ok".to_string()],
+            active: true,
+            section_title: None,
+        }];
+        let marks = vec![
+            mark("a", Some("音乐"), 10),
+            mark("b", Some("ok"), 5),
+            mark("a", None, 20),
+            mark("a", Some("音乐"), 60),
+        ];
+        let kpis = aggregate(marks, &categories);
+        assert_eq!(kpis.len(), 2);
+        let a = &kpis[0];
+        assert_eq!(
+            (a.user_id.as_str(), a.total, a.accepted, a.rejected),
+            ("a", 3, 2, 1)
+        );
+        assert_eq!(a.by_category.get("音乐"), Some(&2));
+        assert_eq!(a.by_kind.get("video"), Some(&3));
+        assert_eq!(a.avg_latency_secs, 30 * 60);
+        assert_eq!(a.median_latency_secs, 20 * 60);
+        let b = &kpis[1];
+        assert_eq!(b.by_category.get("动态"), Some(&1));
+        assert_eq!(b.by_kind.get("dynamic"), Some(&1));
+
+        let markdown = leaderboard_markdown(&kpis);
+        assert!(markdown.starts_with("🥇 **a**：3 条（选入 2，拒绝 1），平均 30 分钟"));
+    }
+
+    #[test]
+    fn test_next_leaderboard_time() {
+        let t = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        // 北京时间周三
+        assert_eq!(
+            next_leaderboard_time(t("2021-10-20T04:00:00Z")),
+            t("2021-10-25T02:00:00Z")
+        );
+        // 周一早上还没到点
+        assert_eq!(
+            next_leaderboard_time(t("2021-10-25T01:00:00Z")),
+            t("2021-10-25T02:00:00Z")
+        );
+        // 刚好到点，下一次是下周
+        assert_eq!(
+            next_leaderboard_time(t("2021-10-25T02:00:00Z")),
+            t("2021-11-01T02:00:00Z")
+        );
+    }
+}
@@ -1,5 +1,18 @@
+pub mod archive;
 pub mod bilibili;
 pub mod callback;
 pub mod cards;
+pub mod category;
+pub mod command;
+pub mod control;
+pub mod directory;
+pub mod events;
+pub mod export;
 pub mod group;
+pub mod image;
+pub mod kpi;
+pub mod review;
+pub mod route;
+pub mod schedule;
 pub mod summary;
+pub mod webhook;
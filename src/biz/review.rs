@@ -0,0 +1,74 @@
+//! 筛选：选入、拒绝、撤销。飞书卡片和网页都走这里
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::{biz, db, feishu::FeishuClient};
+
+async fn get_item(id: &str, pool: &db::Pool) -> Result<db::Item> {
+    db::Item::from_id(id, pool)
+        .await?
+        .ok_or_else(|| anyhow!("Item {} not exist", id))
+}
+
+/// 标记分类，然后在后台发到归档群
+pub async fn categorize(
+    id: &str,
+    category: &str,
+    marker: &str,
+    pool: &db::Pool,
+    client: &FeishuClient,
+) -> Result<db::Item> {
+    get_item(id, pool).await?;
+    db::Item::set_category(id, category, marker, pool).await?;
+    let item = get_item(id, pool).await?;
+    info!("{} 标记为 {}", id, category);
+    biz::events::publish(db::EventKind::ItemCategorized, &item, pool).await;
+
+    let _item = item.clone();
+    let _pool = pool.clone();
+    let _feishu = client.clone();
+    tokio::spawn(async move {
+        if let Err(e) = biz::archive::send_archive(_item, _pool, _feishu).await {
+            error!("发送归档信息失败：{:?}", e);
+        }
+    });
+    Ok(item)
+}
+
+/// 不选入
+pub async fn reject(id: &str, marker: &str, pool: &db::Pool) -> Result<db::Item> {
+    get_item(id, pool).await?;
+    db::Item::reject(id, marker, pool).await?;
+    info!("{} 被 {} 拒绝", id, marker);
+    let item = get_item(id, pool).await?;
+    biz::events::publish(db::EventKind::ItemRejected, &item, pool).await;
+    Ok(item)
+}
+
+/// 撤销选入或者拒绝，已经发到归档群的不会撤回
+pub async fn undo(id: &str, pool: &db::Pool) -> Result<db::Item> {
+    get_item(id, pool).await?;
+    db::Item::remove_category(id, pool).await?;
+    info!("{} 撤销标记", id);
+    let item = get_item(id, pool).await?;
+    biz::events::publish(db::EventKind::ItemReset, &item, pool).await;
+    Ok(item)
+}
+
+/// 一条飞书消息里所有条目的卡片
+pub async fn message_body(message_id: &str, pool: &db::Pool) -> Result<Vec<Value>> {
+    let items = db::Item::all_in_message(message_id, pool).await?;
+    let bodies = items.iter().map(biz::cards::item_card).collect();
+    Ok(biz::cards::merge_body(bodies))
+}
+
+/// 不是通过卡片操作的时候，更新飞书里对应的卡片
+pub async fn refresh_card(item: &db::Item, pool: &db::Pool, client: &FeishuClient) -> Result<()> {
+    if item.message_id.is_empty() {
+        return Ok(());
+    }
+    let body = message_body(&item.message_id, pool).await?;
+    client
+        .patch_card(&item.message_id, biz::cards::wrap_card_body(body))
+        .await
+}
@@ -0,0 +1,215 @@
+//! 按配置的规则决定推送发到哪里
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::config::{RouteConfig, CONFIG};
+use crate::db::{self, ItemKind};
+use crate::{biz, feishu::FeishuClient, feishu::Receiver};
+
+/// 推送的目的地
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// 群名（会按轮换方式加上日期）和拉进群的人，为空时拉 `init_user_ids`
+    Group { name: String, members: Vec<String> },
+    /// 私聊发给这个人
+    Direct(String),
+}
+
+impl Target {
+    /// 默认的群，拉 `init_user_ids` 进群
+    pub fn default_group(name: &str) -> Self {
+        Target::Group {
+            name: name.to_string(),
+            members: vec![],
+        }
+    }
+
+    fn from_route(route: &RouteConfig) -> Option<Self> {
+        if let Some(user_id) = &route.dm {
+            return Some(Target::Direct(user_id.clone()));
+        }
+        Some(Target::Group {
+            name: route.group.clone()?,
+            members: route.members.clone(),
+        })
+    }
+}
+
+/// 用来匹配规则的条目信息
+#[derive(Debug)]
+pub struct Candidate<'a> {
+    pub kind: ItemKind,
+    /// 从哪些 tag 拉到的
+    pub tags: &'a [String],
+    pub uploader: Option<i64>,
+    /// 只有归档的时候有分类
+    pub category: Option<&'a str>,
+}
+
+fn is_match(route: &RouteConfig, c: &Candidate) -> bool {
+    // 有分类的规则只用于归档，反之亦然
+    if route.categories.is_empty() == c.category.is_some() {
+        return false;
+    }
+    if !route.kinds.is_empty() && !route.kinds.contains(&c.kind) {
+        return false;
+    }
+    if !route.tags.is_empty() && !c.tags.iter().any(|t| route.tags.contains(t)) {
+        return false;
+    }
+    if !route.uploaders.is_empty() {
+        match c.uploader {
+            Some(uid) if route.uploaders.contains(&uid) => {}
+            _ => return false,
+        }
+    }
+    if let Some(category) = c.category {
+        if !route.categories.iter().any(|c| c == category) {
+            return false;
+        }
+    }
+    true
+}
+
+/// 第一条匹配且有目的地的规则
+fn find(routes: &[RouteConfig], c: &Candidate) -> Option<Target> {
+    routes
+        .iter()
+        .filter(|r| is_match(r, c))
+        .find_map(Target::from_route)
+}
+
+/// 按规则找目的地，都不匹配时用 `default`
+pub fn route(c: &Candidate, default: impl FnOnce() -> Target) -> Target {
+    find(&CONFIG.routes, c).unwrap_or_else(default)
+}
+
+/// 按目的地分组，保持原来的顺序
+pub fn split<T>(items: Vec<T>, target: impl Fn(&T) -> Target) -> Vec<(Target, Vec<T>)> {
+    let mut ans: Vec<(Target, Vec<T>)> = vec![];
+    for item in items {
+        let t = target(&item);
+        match ans.iter_mut().find(|(target, _)| *target == t) {
+            Some((_, items)) => items.push(item),
+            None => ans.push((t, vec![item])),
+        }
+    }
+    ans
+}
+
+/// 得到实际的接收者，群不存在时会建群
+pub async fn resolve(
+    target: &Target,
+    time: DateTime<Utc>,
+    pool: &db::Pool,
+    client: &FeishuClient,
+) -> Result<Receiver> {
+    match target {
+        Target::Group { name, members } => {
+            let members = if members.is_empty() {
+                &CONFIG.feishu.init_user_ids
+            } else {
+                members
+            };
+            let group =
+                biz::group::create_group_with_members(name, time, members, pool, client).await?;
+            Ok(Receiver::Chat(group.chat_id))
+        }
+        Target::Direct(user_id) => Ok(Receiver::User(user_id.clone())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(s: &str) -> RouteConfig {
+        toml::from_str(s).unwrap()
+    }
+
+    fn video<'a>(tags: &'a [String], uploader: i64) -> Candidate<'a> {
+        Candidate {
+            kind: ItemKind::Video,
+            tags,
+            uploader: Some(uploader),
+            category: None,
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let routes = vec![
+            rule(
+                r#"
+                kinds = ["video"]
+                tags = ["向晚", "向晚大魔王"]
+                group = "向晚视频筛选"
+                members = ["a"]
+                "#,
+            ),
+            rule(
+                r#"
+                uploaders = [114514]
+                dm = "b"
+                "#,
+            ),
+            rule(
+                r#"
+                categories = ["MMD"]
+                group = "MMD归档"
+                "#,
+            ),
+        ];
+        let tags = vec!["A-SOUL".to_string(), "向晚".to_string()];
+        assert_eq!(
+            find(&routes, &video(&tags, 1)),
+            Some(Target::Group {
+                name: "向晚视频筛选".to_string(),
+                members: vec!["a".to_string()]
+            })
+        );
+        // 动态不匹配第一条
+        let dynamic = Candidate {
+            kind: ItemKind::Dynamic,
+            tags: &tags,
+            uploader: Some(114514),
+            category: None,
+        };
+        assert_eq!(
+            find(&routes, &dynamic),
+            Some(Target::Direct("b".to_string()))
+        );
+        assert_eq!(find(&routes, &video(&[], 1)), None);
+
+        // 有分类的只匹配归档规则
+        let archived = Candidate {
+            kind: ItemKind::Video,
+            tags: &[],
+            uploader: Some(114514),
+            category: Some("MMD"),
+        };
+        assert!(matches!(
+            find(&routes, &archived),
+            Some(Target::Group { name, .. }) if name == "MMD归档"
+        ));
+        let archived = Candidate {
+            category: Some("音乐"),
+            ..archived
+        };
+        assert_eq!(find(&routes, &archived), None);
+    }
+
+    #[test]
+    fn test_split() {
+        let a = Target::Direct("a".to_string());
+        let b = Target::Direct("b".to_string());
+        let split = split(vec![1, 2, 3, 4, 5], |i| {
+            if i % 2 == 0 {
+                a.clone()
+            } else {
+                b.clone()
+            }
+        });
+        assert_eq!(split, vec![(b, vec![1, 3, 5]), (a, vec![2, 4])]);
+    }
+}
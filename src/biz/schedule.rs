@@ -0,0 +1,204 @@
+//! 拉取的时间表：按北京时间所在的窗口决定间隔，再加上随机抖动
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use chrono_tz::Asia::Shanghai;
+use rand::Rng;
+use std::time::Duration;
+
+use crate::config::{ScheduleConfig, ScheduleWindow};
+
+/// 拉取一轮最多几分钟，超过这么久没有报告就算卡住了
+const MIN_SILENCE_MINUTES: i64 = 20;
+
+/// 测试的时候换成假的时钟
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+fn matches(window: &ScheduleWindow, t: DateTime<Utc>) -> bool {
+    let t = t.with_timezone(&Shanghai);
+    window.hours.contains(t.hour())
+        && (window.weekdays.is_empty()
+            || window.weekdays.contains(&t.weekday().number_from_monday()))
+}
+
+pub struct Schedule<C = SystemClock> {
+    config: ScheduleConfig,
+    clock: C,
+}
+
+impl Schedule {
+    pub fn new(config: ScheduleConfig) -> Self {
+        Self::with_clock(config, SystemClock)
+    }
+}
+
+impl<C: Clock> Schedule<C> {
+    pub fn with_clock(config: ScheduleConfig, clock: C) -> Self {
+        Self { config, clock }
+    }
+
+    /// 现在所在窗口的间隔，不含抖动
+    pub fn interval(&self) -> Duration {
+        let now = self.clock.now();
+        let secs = self
+            .config
+            .windows
+            .iter()
+            .find(|w| matches(w, now))
+            .map_or(self.config.interval_secs, |w| w.interval_secs);
+        Duration::from_secs(secs.max(1))
+    }
+
+    /// 到下一轮要等多久
+    pub fn next_wait(&self, rng: &mut impl Rng) -> Duration {
+        let jitter = rng.gen_range(0..=self.config.jitter_secs);
+        self.interval() + Duration::from_secs(jitter)
+    }
+
+    /// 同一轮里请求两个 tag 之间的间隔
+    pub fn tag_spacing(&self) -> Duration {
+        // tokio 的 interval 不接受 0
+        Duration::from_millis(self.config.tag_spacing_ms.max(1))
+    }
+
+    /// 超过这么久没有拉取完一轮就算卡住了
+    pub fn max_silence(&self) -> chrono::Duration {
+        let longest = self
+            .config
+            .windows
+            .iter()
+            .map(|w| w.interval_secs)
+            .chain(std::iter::once(self.config.interval_secs))
+            .max()
+            .unwrap_or_default();
+        let secs = (longest + self.config.jitter_secs) as i64 * 4;
+        chrono::Duration::seconds(secs).max(chrono::Duration::minutes(MIN_SILENCE_MINUTES))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::HourRange;
+    use parking_lot::Mutex;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::convert::TryFrom;
+
+    struct MockClock(Mutex<DateTime<Utc>>);
+
+    impl MockClock {
+        fn set(&self, t: &str) {
+            *self.0.lock() = t.parse().unwrap();
+        }
+    }
+
+    impl Clock for &MockClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock()
+        }
+    }
+
+    fn config() -> ScheduleConfig {
+        toml::from_str(
+            r#"
+            interval_secs = 180
+            jitter_secs = 30
+            tag_spacing_ms = 0
+
+            # 周末白天
+            [[windows]]
+            hours = "9-18"
+            weekdays = [6, 7]
+            interval_secs = 60
+
+            [[windows]]
+            hours = "23-6"
+            interval_secs = 600
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_hour_range() {
+        let range = HourRange::try_from("1-8".to_string()).unwrap();
+        assert!(range.contains(1) && range.contains(8) && !range.contains(9));
+        let range = HourRange::try_from("22-2".to_string()).unwrap();
+        assert!(range.contains(23) && range.contains(0) && !range.contains(3));
+        assert_eq!(
+            HourRange::try_from("7".to_string()).unwrap(),
+            HourRange { start: 7, end: 7 }
+        );
+        assert!(HourRange::try_from("8-24".to_string()).is_err());
+        assert!(
+            toml::from_str::<ScheduleConfig>("[[windows]]\nhours = \"x\"\ninterval_secs = 1")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_weekdays() {
+        let parse = |weekdays: &str| {
+            toml::from_str::<ScheduleConfig>(&format!(
+                "[[windows]]\nhours = \"1-8\"\nweekdays = {}\ninterval_secs = 1",
+                weekdays
+            ))
+        };
+        assert_eq!(parse("[1, 7]").unwrap().windows[0].weekdays, vec![1, 7]);
+        assert!(parse("[0]").is_err());
+        assert!(parse("[6, 8]").is_err());
+    }
+
+    #[test]
+    fn test_interval() {
+        let clock = MockClock(Mutex::new(Utc::now()));
+        let schedule = Schedule::with_clock(config(), &clock);
+        // 2021-10-18 是周一，北京时间 12 点
+        clock.set("2021-10-18T04:00:00Z");
+        assert_eq!(schedule.interval(), Duration::from_secs(180));
+        // 周六北京时间 12 点
+        clock.set("2021-10-23T04:00:00Z");
+        assert_eq!(schedule.interval(), Duration::from_secs(60));
+        // 跨零点的窗口：北京时间 23 点和 3 点
+        clock.set("2021-10-18T15:00:00Z");
+        assert_eq!(schedule.interval(), Duration::from_secs(600));
+        clock.set("2021-10-18T19:00:00Z");
+        assert_eq!(schedule.interval(), Duration::from_secs(600));
+        clock.set("2021-10-18T23:00:00Z");
+        assert_eq!(schedule.interval(), Duration::from_secs(180));
+
+        assert_eq!(schedule.tag_spacing(), Duration::from_millis(1));
+        assert_eq!(schedule.max_silence(), chrono::Duration::seconds(630 * 4));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let clock = MockClock(Mutex::new("2021-10-18T04:00:00Z".parse().unwrap()));
+        let schedule = Schedule::with_clock(config(), &clock);
+        let mut rng = StdRng::seed_from_u64(42);
+        let waits: Vec<_> = (0..100).map(|_| schedule.next_wait(&mut rng)).collect();
+        assert!(waits.iter().all(|w| (180..=210).contains(&w.as_secs())));
+        assert!(waits.iter().any(|w| *w != waits[0]));
+    }
+
+    #[test]
+    fn test_default() {
+        let clock = MockClock(Mutex::new("2021-10-17T19:00:00Z".parse().unwrap()));
+        let config = crate::config::Schedules::default().video;
+        let schedule = Schedule::with_clock(config, &clock);
+        // 北京时间 3 点五分钟一次，白天三分钟一次
+        assert_eq!(schedule.interval(), Duration::from_secs(5 * 60));
+        clock.set("2021-10-18T04:00:00Z");
+        assert_eq!(schedule.interval(), Duration::from_secs(3 * 60));
+        assert_eq!(schedule.tag_spacing(), Duration::from_secs(2));
+        assert_eq!(schedule.max_silence(), chrono::Duration::minutes(20));
+    }
+}
@@ -1,27 +1,197 @@
-use crate::db;
+use crate::{biz, db};
 use anyhow::*;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Asia::Shanghai;
 use std::collections::BTreeMap as Map;
 
+/// 一次最多查这么多天
+const MAX_DAYS: i64 = 93;
+
+pub use api::{GroupBy, SummaryGroup, SummaryItem};
+
+fn link(item: &db::Item) -> String {
+    match item.kind {
+        db::ItemKind::Video => item.id.clone(),
+        db::ItemKind::Dynamic => format!("https://t.bilibili.com/{}", item.id),
+    }
+}
+
+fn to_summary_item(item: db::Item, categories: &[db::Category]) -> SummaryItem {
+    // 旧的分类名，如 ok => 动态
+    let category =
+        biz::category::canonical_name(categories, item.category.as_deref().unwrap_or_default());
+    SummaryItem {
+        link: link(&item),
+        id: item.id,
+        kind: item.kind,
+        title: item.title,
+        uploader_uid: item.uploader_uid,
+        uploader_name: item.uploader_name,
+        category,
+        published_at: item.published_at,
+        marker: item.marker,
+        marked_at: item.marked_at,
+    }
+}
+
+/// 检查日期范围，太长的查询会很慢
+pub fn check_range(from: NaiveDate, to: NaiveDate) -> Result<()> {
+    if from > to {
+        bail!("开始日期 {} 晚于结束日期 {}", from, to);
+    }
+    let days = (to - from).num_days() + 1;
+    if days > MAX_DAYS {
+        bail!("一次最多查询 {} 天，请求了 {} 天", MAX_DAYS, days);
+    }
+    Ok(())
+}
+
+async fn items_in_dates(
+    from: NaiveDate,
+    to: NaiveDate,
+    pool: &db::Pool,
+) -> Result<(Vec<db::Item>, Vec<db::Category>)> {
+    check_range(from, to)?;
+    let items = db::Item::all_categorized_in_dates(from, to, pool).await?;
+    let categories = db::Category::all(pool).await?;
+    Ok((items, categories))
+}
+
+/// 兼容模式，分类 => 链接
 pub async fn categorized(date: DateTime<Utc>, pool: &db::Pool) -> Result<Map<String, Vec<String>>> {
-    let items = db::Item::all_categorized_in_date(date, pool).await?;
+    let date = date.with_timezone(&Shanghai).date().naive_local();
+    categorized_in_dates(date, date, pool).await
+}
 
+/// 兼容模式，北京时间 `from` 到 `to` 的汇总，两头都包含
+pub async fn categorized_in_dates(
+    from: NaiveDate,
+    to: NaiveDate,
+    pool: &db::Pool,
+) -> Result<Map<String, Vec<String>>> {
+    let (items, categories) = items_in_dates(from, to, pool).await?;
     let mut map: Map<String, Vec<String>> = Map::new();
     for item in items {
-        map.entry(item.category.unwrap()).or_default().push(item.id);
+        let item = to_summary_item(item, &categories);
+        map.entry(item.category).or_default().push(item.link);
+    }
+    Ok(map)
+}
+
+/// 带条目信息的汇总，按 `group_by` 分组
+pub async fn grouped(
+    from: NaiveDate,
+    to: NaiveDate,
+    group_by: GroupBy,
+    pool: &db::Pool,
+) -> Result<Vec<SummaryGroup>> {
+    let (items, categories) = items_in_dates(from, to, pool).await?;
+    let items = items
+        .into_iter()
+        .map(|item| to_summary_item(item, &categories))
+        .collect();
+    Ok(group(items, group_by, &categories))
+}
+
+/// 按天分组时按日期排；按分类时按分类的顺序，不认识的分类排在后面；按 UP 主时条目多的在前。
+///
+/// 组内保持发布时间的顺序
+fn group(
+    items: Vec<SummaryItem>,
+    group_by: GroupBy,
+    categories: &[db::Category],
+) -> Vec<SummaryGroup> {
+    let key = |item: &SummaryItem| match group_by {
+        GroupBy::Day => item
+            .published_at
+            .with_timezone(&Shanghai)
+            .format("%Y-%m-%d")
+            .to_string(),
+        GroupBy::Category => item.category.clone(),
+        GroupBy::Uploader => item.uploader_name.clone(),
+    };
+    let mut groups: Vec<SummaryGroup> = vec![];
+    for item in items {
+        let k = key(&item);
+        match groups.iter_mut().find(|g| g.key == k) {
+            Some(g) => g.items.push(item),
+            None => groups.push(SummaryGroup {
+                key: k,
+                items: vec![item],
+            }),
+        }
     }
-    // legacy: rename ok => 动态
-    let mut dynamic_ids = vec![];
-    if let Some(dynamics) = map.remove("ok") {
-        dynamic_ids.extend(dynamics);
+    match group_by {
+        GroupBy::Day => groups.sort_by(|a, b| a.key.cmp(&b.key)),
+        GroupBy::Category => groups.sort_by_key(|g| {
+            categories
+                .iter()
+                .position(|c| c.name == g.key)
+                .unwrap_or(usize::MAX)
+        }),
+        GroupBy::Uploader => groups.sort_by(|a, b| b.items.len().cmp(&a.items.len())),
     }
-    if let Some(dynamics) = map.remove("动态") {
-        dynamic_ids.extend(dynamics);
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(id: &str, t: &str, category: &str, uploader: &str) -> SummaryItem {
+        let mut item = db::Item::sample(id, t.parse().unwrap());
+        item.category = Some(category.to_string());
+        item.uploader_name = uploader.to_string();
+        to_summary_item(item, &[])
     }
-    for dynamic_id in dynamic_ids {
-        map.entry("动态".to_string())
-            .or_default()
-            .push(format!("https://t.bilibili.com/{}", dynamic_id));
+
+    fn keys(groups: &[SummaryGroup]) -> Vec<&str> {
+        groups.iter().map(|g| g.key.as_str()).collect()
+    }
+
+    #[test]
+    fn test_group() {
+        let categories: Vec<db::Category> = ["音乐", "舞蹈"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| db::Category {
+                name: name.to_string(),
+                kind: db::ItemKind::Video,
+                sort_order: i as i64,
+                aliases: vec![],
+                active: true,
+                section_title: None,
+            })
+            .collect();
+        let items = || {
+            vec![
+                // 北京时间 10-18 早上
+                item("BV1", "2021-10-17T23:00:00Z", "舞蹈", "a"),
+                item("BV2", "2021-10-18T01:00:00Z", "炸厨房", "b"),
+                item("BV3", "2021-10-19T01:00:00Z", "音乐", "b"),
+                item("123", "2021-10-19T02:00:00Z", "舞蹈", "c"),
+            ]
+        };
+
+        let by_day = group(items(), GroupBy::Day, &categories);
+        assert_eq!(keys(&by_day), vec!["2021-10-18", "2021-10-19"]);
+        assert_eq!(by_day[0].items.len(), 2);
+        assert_eq!(by_day[1].items[1].link, "https://t.bilibili.com/123");
+
+        let by_category = group(items(), GroupBy::Category, &categories);
+        assert_eq!(keys(&by_category), vec!["音乐", "舞蹈", "炸厨房"]);
+        let ids: Vec<_> = by_category[1].items.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, vec!["BV1", "123"]);
+
+        let by_uploader = group(items(), GroupBy::Uploader, &categories);
+        assert_eq!(keys(&by_uploader), vec!["b", "a", "c"]);
+    }
+
+    #[test]
+    fn test_check_range() {
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        assert!(check_range(date("2021-10-18"), date("2021-10-24")).is_ok());
+        assert!(check_range(date("2021-10-24"), date("2021-10-18")).is_err());
+        assert!(check_range(date("2021-01-01"), date("2021-12-31")).is_err());
     }
-    Ok(map)
 }
@@ -0,0 +1,258 @@
+//! 筛选之后通知其他工具。先写进数据库，再由后台投递，失败了按指数退避重试
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::config::{WebhookConfig, CONFIG};
+use crate::db::{self, WebhookEvent};
+use crate::supervisor::Task;
+
+/// 最多投递几次
+const MAX_ATTEMPTS: u32 = 8;
+/// 第一次重试等多久，之后每次翻倍
+const BASE_BACKOFF_SECS: i64 = 30;
+const MAX_BACKOFF_SECS: i64 = 60 * 60;
+/// 没有新事件的时候多久检查一次要重试的
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const BATCH_SIZE: u32 = 20;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    /// 有新的投递时唤醒后台任务
+    static ref WAKE: Notify = Notify::new();
+}
+
+/// 给订阅了这个事件的 webhook 各入队一次
+async fn enqueue(
+    webhooks: &[WebhookConfig],
+    event: WebhookEvent,
+    item: &db::Item,
+    pool: &db::Pool,
+) -> Result<usize> {
+    let webhooks: Vec<_> = webhooks.iter().filter(|w| w.subscribes(event)).collect();
+    if webhooks.is_empty() {
+        return Ok(0);
+    }
+    let payload = serde_json::to_string(&api::WebhookPayload {
+        event,
+        timestamp: Utc::now(),
+        item: item.clone().into(),
+    })?;
+    for webhook in webhooks.iter() {
+        db::WebhookDelivery::enqueue(&webhook.name, event, &item.id, &payload, pool).await?;
+    }
+    WAKE.notify_one();
+    Ok(webhooks.len())
+}
+
+/// 通知其他工具，失败只记日志，不影响筛选
+pub async fn publish(event: WebhookEvent, item: &db::Item, pool: &db::Pool) {
+    if let Err(e) = enqueue(&CONFIG.webhooks, event, item, pool).await {
+        error!(
+            "{} 的 {} webhook 入队失败：{:?}",
+            item.id,
+            event.as_str(),
+            e
+        );
+    }
+}
+
+/// `sha256=` 加上十六进制的 HMAC-SHA256
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC 的 key 可以是任意长度");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// 第 `attempts` 次失败之后等多久
+fn backoff(attempts: u32) -> chrono::Duration {
+    let secs = BASE_BACKOFF_SECS.saturating_mul(1 << attempts.saturating_sub(1).min(20));
+    chrono::Duration::seconds(secs.min(MAX_BACKOFF_SECS))
+}
+
+/// 发一次，返回 (状态码, 错误)
+async fn post(
+    webhook: &WebhookConfig,
+    delivery: &db::WebhookDelivery,
+    client: &reqwest::Client,
+) -> (Option<u16>, Option<String>) {
+    let mut request = client
+        .post(&webhook.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Event", delivery.event.as_str())
+        .header("X-Webhook-Delivery", delivery.id.to_string());
+    if let Some(secret) = &webhook.secret {
+        request = request.header(
+            "X-Webhook-Signature",
+            sign(secret, delivery.payload.as_bytes()),
+        );
+    }
+    match request.body(delivery.payload.clone()).send().await {
+        Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+        Ok(response) => {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            let body: String = body.chars().take(200).collect();
+            (
+                Some(status.as_u16()),
+                Some(format!("HTTP {}: {}", status, body)),
+            )
+        }
+        Err(e) => (None, Some(e.to_string())),
+    }
+}
+
+/// 投递所有到时间的，返回处理了几条
+async fn deliver_due(
+    webhooks: &[WebhookConfig],
+    now: DateTime<Utc>,
+    client: &reqwest::Client,
+    pool: &db::Pool,
+) -> Result<usize> {
+    let deliveries = db::WebhookDelivery::due(now, BATCH_SIZE, pool).await?;
+    for delivery in deliveries.iter() {
+        let webhook = match webhooks.iter().find(|w| w.name == delivery.webhook) {
+            Some(webhook) => webhook,
+            None => {
+                warn!("webhook {} 已经从配置里删除，放弃投递", delivery.webhook);
+                let error = "webhook 已经从配置里删除";
+                db::WebhookDelivery::set_failed(delivery.id, None, error, None, pool).await?;
+                continue;
+            }
+        };
+        match post(webhook, delivery, client).await {
+            (Some(status), None) => {
+                debug!("webhook {} 投递成功：{}", webhook.name, delivery.id);
+                db::WebhookDelivery::set_delivered(delivery.id, status, pool).await?;
+            }
+            (status, error) => {
+                let error = error.unwrap_or_default();
+                let attempts = delivery.attempts + 1;
+                let retry_at = Some(now + backoff(attempts)).filter(|_| attempts < MAX_ATTEMPTS);
+                warn!(
+                    "webhook {} 第 {} 次投递 {} 失败：{}",
+                    webhook.name, attempts, delivery.id, error
+                );
+                db::WebhookDelivery::set_failed(delivery.id, status, &error, retry_at, pool)
+                    .await?;
+            }
+        }
+    }
+    Ok(deliveries.len())
+}
+
+/// 后台投递，有新事件时马上投递，否则定时检查要重试的
+pub async fn deliver_forever(webhooks: Vec<WebhookConfig>, pool: db::Pool, task: Task) -> ! {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("创建 HTTP client 失败");
+    loop {
+        match deliver_due(&webhooks, Utc::now(), &client, &pool).await {
+            // 一批没处理完，接着处理
+            Ok(n) if n as u32 == BATCH_SIZE => continue,
+            Ok(_) => task.succeeded(),
+            Err(e) => {
+                error!("投递 webhook 失败：{:?}", e);
+                task.failed(&e);
+            }
+        }
+        tokio::select! {
+            _ = WAKE.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wiremock::matchers::{header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), chrono::Duration::seconds(30));
+        assert_eq!(backoff(2), chrono::Duration::seconds(60));
+        assert_eq!(backoff(4), chrono::Duration::seconds(240));
+        assert_eq!(backoff(20), chrono::Duration::seconds(MAX_BACKOFF_SECS));
+    }
+
+    #[tokio::test]
+    async fn test_deliver() -> Result<()> {
+        let pool = db::init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/ok"))
+            .and(header("X-Webhook-Event", "item.categorized"))
+            .and(header_exists("X-Webhook-Signature"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/down"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+        let webhook = |name: &str, events: Vec<WebhookEvent>| WebhookConfig {
+            name: name.to_string(),
+            url: format!("{}/{}", server.uri(), name),
+            events,
+            secret: Some("secret".to_string()),
+        };
+        let webhooks = vec![
+            webhook("ok", vec![WebhookEvent::ItemCategorized]),
+            webhook("down", vec![]),
+        ];
+
+        let t: DateTime<Utc> = "2021-10-18T04:00:00Z".parse().unwrap();
+        let mut item = db::Item::sample("BV1", t);
+        item.category = Some("音乐".to_string());
+        let n = enqueue(&webhooks, WebhookEvent::ItemCategorized, &item, &pool).await?;
+        assert_eq!(n, 2);
+        // 只有 down 订阅了拒绝
+        let n = enqueue(&webhooks, WebhookEvent::ItemRejected, &item, &pool).await?;
+        assert_eq!(n, 1);
+
+        let client = reqwest::Client::new();
+        let now = Utc::now();
+        assert_eq!(deliver_due(&webhooks, now, &client, &pool).await?, 3);
+        let all = db::WebhookDelivery::list(None, None, None, 10, &pool).await?;
+        let ok: Vec<_> = all.iter().filter(|d| d.webhook == "ok").collect();
+        assert_eq!(ok.len(), 1);
+        assert_eq!(ok[0].status, db::DeliveryStatus::Delivered);
+        assert_eq!(ok[0].last_status_code, Some(204));
+        for d in all.iter().filter(|d| d.webhook == "down") {
+            assert_eq!(d.status, db::DeliveryStatus::Pending);
+            assert_eq!(d.last_status_code, Some(503));
+            assert_eq!(
+                d.next_attempt_at.timestamp(),
+                (now + backoff(1)).timestamp()
+            );
+        }
+
+        // 还没到重试时间
+        assert_eq!(deliver_due(&webhooks, now, &client, &pool).await?, 0);
+
+        let requests = server.received_requests().await.unwrap();
+        let request = requests.iter().find(|r| r.url.path() == "/ok").unwrap();
+        let payload: api::WebhookPayload = serde_json::from_slice(&request.body)?;
+        assert_eq!(payload.item.category.as_deref(), Some("音乐"));
+        Ok(())
+    }
+}
@@ -1,7 +1,10 @@
 use anyhow::Result;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::{io::Read, path::Path};
 
+use crate::db::{ItemKind, WebhookEvent};
+
 lazy_static::lazy_static! {
     // SAFETY: 程序在启动的时候会载入配置，这里直接 unwrap 不会 panic
     pub static ref CONFIG: Config = Config::from_file("./config.toml").expect("载入配置文件失败");
@@ -11,14 +14,42 @@ lazy_static::lazy_static! {
 pub struct Config {
     /// 监听的 http 地址
     pub http_addr: String,
+    /// 控制接口和分类管理要带的 Bearer token，也能用来筛选，不填则这些接口不可用
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// 网页筛选用的 Bearer token，名字 => token，标记记录成 `web:名字`
+    #[serde(default)]
+    pub web_reviewers: HashMap<String, String>,
     /// sqlite 协议
     pub sqlite_url: String,
-    /// 对视频的分类
+    /// 对视频的分类，只在分类表为空的时候用来初始化，之后通过 HTTP 接口管理
+    #[serde(default)]
     pub video_categories: Vec<String>,
     /// 监控的 tag，tag 名 => tag id
     pub watch_tags: HashMap<String, u64>,
+    /// 视频和动态各自的拉取时间表
+    #[serde(default)]
+    pub schedules: Schedules,
+    /// 请求哔哩哔哩的请求头、cookie 和限流
+    #[serde(default)]
+    pub bilibili: bilibili::ClientConfig,
     /// 飞书的配置
     pub feishu: FeishuConfig,
+    /// 筛选群的轮换和归档
+    #[serde(default)]
+    pub group: GroupConfig,
+    /// 分类之后发到归档群
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    /// 推送的路由规则，按顺序匹配第一条，都不匹配时发到默认的群
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// 标记统计
+    #[serde(default)]
+    pub kpi: KpiConfig,
+    /// 筛选之后通知其他工具
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,6 +58,274 @@ pub struct FeishuConfig {
     pub app_secret: String,
     /// 建群拉人的时候的初始 user id
     pub init_user_ids: Vec<String>,
+    /// 可以在群里使用机器人命令的 user id，为空时使用 `init_user_ids`
+    #[serde(default)]
+    pub reviewer_ids: Vec<String>,
+    /// 图片下载或者上传失败时使用的 image_key，不填则启动时上传内置的占位图
+    #[serde(default)]
+    pub fallback_image_key: Option<String>,
+    /// 飞书开发者后台的 Verification Token，填了之后会校验回调。
+    /// 不填的话不处理事件回调（机器人命令）
+    #[serde(default)]
+    pub verification_token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct KpiConfig {
+    /// 每周一把上周的排行榜发到这个群，不填则不发
+    #[serde(default)]
+    pub leaderboard_chat_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct GroupConfig {
+    /// 多久换一个新群
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// 超过这么多天没有推送的群会被归档，不填则不归档
+    #[serde(default)]
+    pub archive_after_days: Option<u32>,
+    /// 归档的方式
+    #[serde(default)]
+    pub archive_action: ArchiveAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    /// 一直用同一个群
+    Persistent,
+    /// 每周一个群
+    Weekly,
+    /// 每天一个群
+    Daily,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation::Daily
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveAction {
+    /// 解散群
+    Dissolve,
+    /// 把人都移出群，保留聊天记录
+    RemoveMembers,
+}
+
+impl Default for ArchiveAction {
+    fn default() -> Self {
+        ArchiveAction::RemoveMembers
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveConfig {
+    /// 是否把通过的视频和动态发到归档群
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_video_archive_group")]
+    pub video_group: String,
+    #[serde(default = "default_dynamic_archive_group")]
+    pub dynamic_group: String,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            video_group: default_video_archive_group(),
+            dynamic_group: default_dynamic_archive_group(),
+        }
+    }
+}
+
+fn default_video_archive_group() -> String {
+    "视频归档".to_string()
+}
+
+fn default_dynamic_archive_group() -> String {
+    "动态归档".to_string()
+}
+
+/// 一条路由规则，条件之间是“且”，同一个条件里的多个值是“或”，不填的条件不限制。
+///
+/// 填了 `categories` 的规则只用于归档，其余的只用于待筛选的推送
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    /// 视频 video、动态 dynamic
+    #[serde(default)]
+    pub kinds: Vec<ItemKind>,
+    /// 从哪些监控的 tag 拉到的，对应 `watch_tags` 的 tag 名
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// UP 主的 uid
+    #[serde(default)]
+    pub uploaders: Vec<i64>,
+    /// 审核给的分类
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// 发到这个群，群名会按 `[group]` 的方式轮换
+    #[serde(default)]
+    pub group: Option<String>,
+    /// 拉进群的人，不填则使用 `init_user_ids`
+    #[serde(default)]
+    pub members: Vec<String>,
+    /// 私聊发给这个人（user id），优先于 `group`
+    #[serde(default)]
+    pub dm: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    /// 投递记录里用这个名字区分，不能重复
+    pub name: String,
+    pub url: String,
+    /// 订阅的事件，不填则订阅全部
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    /// 填了之后用 HMAC-SHA256 给 body 签名，放在 `X-Webhook-Signature` 头里
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+impl WebhookConfig {
+    pub fn subscribes(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Schedules {
+    #[serde(default = "default_video_schedule")]
+    pub video: ScheduleConfig,
+    #[serde(default = "default_dynamic_schedule")]
+    pub dynamic: ScheduleConfig,
+}
+
+impl Default for Schedules {
+    fn default() -> Self {
+        Self {
+            video: default_video_schedule(),
+            dynamic: default_dynamic_schedule(),
+        }
+    }
+}
+
+/// 一个来源的拉取时间表，填了就整段替换默认的
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleConfig {
+    /// 按顺序匹配第一个时间窗口，都不匹配时用 `interval_secs`
+    #[serde(default)]
+    pub windows: Vec<ScheduleWindow>,
+    /// 两轮拉取之间隔多少秒
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// 每轮在间隔上随机多等 0 到这么多秒，避免总在同一时刻请求
+    #[serde(default)]
+    pub jitter_secs: u64,
+    /// 同一轮里请求两个 tag 之间隔多少毫秒
+    #[serde(default = "default_tag_spacing_ms")]
+    pub tag_spacing_ms: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleWindow {
+    /// 北京时间的小时，包含两端，比如 "1-8"；"22-2" 表示跨过零点
+    pub hours: HourRange,
+    /// 星期几，1 是周一，7 是周日，不填则每天
+    #[serde(default, deserialize_with = "deserialize_weekdays")]
+    pub weekdays: Vec<u32>,
+    /// 这个窗口里两轮拉取之间隔多少秒
+    pub interval_secs: u64,
+}
+
+/// 小时的范围，包含两端
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HourRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl TryFrom<String> for HourRange {
+    type Error = anyhow::Error;
+    fn try_from(s: String) -> Result<Self> {
+        let parse = |h: &str| -> Result<u32> {
+            match h.trim().parse() {
+                Ok(h) if h < 24 => Ok(h),
+                _ => bail!("无法识别的小时：{}", h),
+            }
+        };
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => (parse(&s)?, parse(&s)?),
+        };
+        Ok(Self { start, end })
+    }
+}
+
+fn deserialize_weekdays<'de, D>(deserializer: D) -> std::result::Result<Vec<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let weekdays = <Vec<u32> as serde::Deserialize>::deserialize(deserializer)?;
+    if let Some(w) = weekdays.iter().find(|w| !(1..=7).contains(*w)) {
+        return Err(serde::de::Error::custom(format!(
+            "无法识别的星期：{}，1 是周一，7 是周日",
+            w
+        )));
+    }
+    Ok(weekdays)
+}
+
+impl HourRange {
+    pub fn contains(self, hour: u32) -> bool {
+        if self.start <= self.end {
+            self.start <= hour && hour <= self.end
+        } else {
+            // 跨过零点
+            hour >= self.start || hour <= self.end
+        }
+    }
+}
+
+/// 1~8 点五分钟一次，其他时间三分钟一次
+fn default_windows() -> Vec<ScheduleWindow> {
+    vec![ScheduleWindow {
+        hours: HourRange { start: 1, end: 8 },
+        weekdays: vec![],
+        interval_secs: 5 * 60,
+    }]
+}
+
+fn default_interval_secs() -> u64 {
+    3 * 60
+}
+
+fn default_tag_spacing_ms() -> u64 {
+    1000
+}
+
+fn default_video_schedule() -> ScheduleConfig {
+    ScheduleConfig {
+        windows: default_windows(),
+        interval_secs: default_interval_secs(),
+        jitter_secs: 0,
+        tag_spacing_ms: 2000,
+    }
+}
+
+fn default_dynamic_schedule() -> ScheduleConfig {
+    ScheduleConfig {
+        windows: default_windows(),
+        interval_secs: default_interval_secs(),
+        jitter_secs: 0,
+        tag_spacing_ms: default_tag_spacing_ms(),
+    }
 }
 
 impl Config {
@@ -40,3 +339,15 @@ impl Config {
         Ok(c)
     }
 }
+
+impl FeishuConfig {
+    /// 是否可以使用机器人命令
+    pub fn is_reviewer(&self, user_id: &str) -> bool {
+        let reviewers = if self.reviewer_ids.is_empty() {
+            &self.init_user_ids
+        } else {
+            &self.reviewer_ids
+        };
+        reviewers.iter().any(|id| id == user_id)
+    }
+}
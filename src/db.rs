@@ -1,11 +1,13 @@
 //!
 
-use std::convert::{TryFrom, TryInto};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::str::FromStr;
 
 use anyhow::*;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 use chrono_tz::Asia::Shanghai;
-use sqlx::{sqlite::SqlitePoolOptions, Sqlite};
+use sqlx::{sqlite::SqlitePoolOptions, FromRow, Row, Sqlite};
 
 pub type Pool = sqlx::Pool<Sqlite>;
 
@@ -17,37 +19,190 @@ pub async fn init(database_url: &str) -> Result<Pool> {
     Ok(pool)
 }
 
-#[derive(Clone)]
-pub struct RawItem<T> {
+pub use api::{ItemKind, ItemSort};
+
+/// 动态或者视频
+#[derive(Debug, Clone)]
+pub struct Item {
+    /// BV 号或者动态 id
     pub id: String,
-    pub json: String,
+    pub kind: ItemKind,
+    /// 视频标题，动态的正文
+    pub title: String,
+    /// 视频简介，动态没有
+    pub description: String,
+    /// 旧数据没有
+    pub uploader_uid: Option<i64>,
+    pub uploader_name: String,
+    /// 视频封面，动态的第一张图
+    pub cover_url: String,
+    /// 卡片上的图片在飞书的 image_key
+    pub image_key: String,
+    /// 视频长度，秒
+    pub duration: Option<u32>,
+    /// 动态的图片数
+    pub picture_count: Option<u32>,
+    /// 从哪些监控的 tag 拉到的
+    pub source_tags: Vec<String>,
+    /// 推送时的播放、点赞、评论、弹幕数
+    pub stat: Stat,
     pub message_id: String,
-    pub create_time: T,
+    /// 视频、动态的发布时间
+    pub published_at: DateTime<Utc>,
+    /// 推送到飞书的时间
+    pub pushed_at: DateTime<Utc>,
     pub category: Option<String>,
-    pub author: String,
+    pub marker: Option<String>,
+    pub marked_at: Option<DateTime<Utc>>,
 }
 
-/// 动态或者视频
-pub type Item = RawItem<DateTime<Utc>>;
-
-impl TryFrom<RawItem<String>> for Item {
-    type Error = chrono::ParseError;
-    fn try_from(item: RawItem<String>) -> Result<Self, Self::Error> {
-        let t = &item.create_time;
-        let create_time = NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S")
-            .or_else(|_| NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S%.f"))
-            .map(|t| DateTime::from_utc(t, Utc))?;
+/// 推送时的数据，旧数据解析不到的为空
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stat {
+    pub view: Option<u32>,
+    pub like: Option<u32>,
+    pub reply: Option<u32>,
+    pub danmaku: Option<u32>,
+}
+
+/// 数据库里的一行
+#[derive(sqlx::FromRow)]
+struct ItemRow {
+    id: String,
+    kind: String,
+    title: String,
+    description: String,
+    uploader_uid: Option<i64>,
+    uploader_name: String,
+    cover_url: String,
+    image_key: String,
+    duration: Option<i64>,
+    picture_count: Option<i64>,
+    source_tags: String,
+    view_count: Option<i64>,
+    like_count: Option<i64>,
+    reply_count: Option<i64>,
+    danmaku_count: Option<i64>,
+    message_id: String,
+    published_at: i64,
+    pushed_at: i64,
+    category: Option<String>,
+    marker: Option<String>,
+    marked_at: Option<i64>,
+}
+
+/// 列表接口的条件是拼出来的，没法用 `query_as!`，要和 [`ItemRow`] 的字段保持一致
+const ITEM_COLUMNS: &str =
+    "`id`, `kind`, `title`, `description`, `uploader_uid`, `uploader_name`, \
+    `cover_url`, `image_key`, `duration`, `picture_count`, `source_tags`, \
+    `view_count`, `like_count`, `reply_count`, `danmaku_count`, `message_id`, \
+    `published_at`, `pushed_at`, `category`, `marker`, `marked_at`";
+
+fn from_timestamp(t: i64) -> DateTime<Utc> {
+    Utc.timestamp(t, 0)
+}
+
+impl TryFrom<ItemRow> for Item {
+    type Error = anyhow::Error;
+    fn try_from(row: ItemRow) -> Result<Self> {
         Ok(Self {
-            id: item.id,
-            json: item.json,
-            message_id: item.message_id,
-            create_time,
-            category: item.category,
-            author: item.author,
+            id: row.id,
+            kind: ItemKind::from_str(&row.kind)?,
+            title: row.title,
+            description: row.description,
+            uploader_uid: row.uploader_uid,
+            uploader_name: row.uploader_name,
+            cover_url: row.cover_url,
+            image_key: row.image_key,
+            duration: row.duration.map(|v| v as u32),
+            picture_count: row.picture_count.map(|v| v as u32),
+            source_tags: serde_json::from_str(&row.source_tags)?,
+            stat: Stat {
+                view: row.view_count.map(|v| v as u32),
+                like: row.like_count.map(|v| v as u32),
+                reply: row.reply_count.map(|v| v as u32),
+                danmaku: row.danmaku_count.map(|v| v as u32),
+            },
+            message_id: row.message_id,
+            published_at: from_timestamp(row.published_at),
+            pushed_at: from_timestamp(row.pushed_at),
+            category: row.category,
+            marker: row.marker,
+            marked_at: row.marked_at.map(from_timestamp),
         })
     }
 }
 
+#[cfg(test)]
+impl Item {
+    /// 测试用的条目，除了 id 和时间都是默认值
+    pub fn sample(id: &str, t: DateTime<Utc>) -> Self {
+        Self {
+            id: id.to_string(),
+            kind: ItemKind::guess(id),
+            title: "title".to_string(),
+            description: String::new(),
+            uploader_uid: Some(1),
+            uploader_name: "uploader".to_string(),
+            cover_url: String::new(),
+            image_key: String::new(),
+            duration: None,
+            picture_count: None,
+            source_tags: vec![],
+            stat: Stat::default(),
+            message_id: "om_test".to_string(),
+            published_at: t,
+            pushed_at: t,
+            category: None,
+            marker: None,
+            marked_at: None,
+        }
+    }
+}
+
+/// 一次标记，用来统计 KPI
+#[derive(Debug, Clone)]
+pub struct Mark {
+    pub marker: String,
+    pub kind: ItemKind,
+    /// 为空表示拒绝
+    pub category: Option<String>,
+    pub pushed_at: DateTime<Utc>,
+    pub marked_at: DateTime<Utc>,
+}
+
+/// 旧数据里需要解析卡片 json 的条目
+#[derive(Debug)]
+pub struct LegacyItem {
+    pub id: String,
+    pub kind: String,
+    pub legacy_json: String,
+}
+
+/// 从旧数据的卡片里解析出来的字段，解析不到的保持默认值
+#[derive(Debug, Default, PartialEq)]
+pub struct LegacyFields {
+    pub title: String,
+    pub uploader_name: Option<String>,
+    pub image_key: String,
+    pub duration: Option<u32>,
+    pub picture_count: Option<u32>,
+    pub stat: Stat,
+}
+
+/// UTC+8 的这一天的起止时间戳
+fn day_range(date: DateTime<Utc>) -> (i64, i64) {
+    let date = date.with_timezone(&Shanghai).date().naive_local();
+    dates_range(date, date)
+}
+
+/// 北京时间 `from` 到 `to` 这几天，两头都包含
+fn dates_range(from: NaiveDate, to: NaiveDate) -> (i64, i64) {
+    let start = Shanghai.from_local_date(&from).unwrap().and_hms(0, 0, 0);
+    let end = Shanghai.from_local_date(&to).unwrap().and_hms(23, 59, 59);
+    (start.timestamp(), end.timestamp())
+}
+
 impl Item {
     pub async fn is_sent(id: &str, pool: &Pool) -> Result<bool> {
         let cnt = sqlx::query_scalar!(
@@ -56,7 +211,7 @@ impl Item {
             FROM `item`
             WHERE
                 `id` = ?;
-        "#,
+            "#,
             id
         )
         .fetch_one(&*pool)
@@ -66,45 +221,57 @@ impl Item {
     }
 
     pub async fn insert(self, pool: &Pool) -> Result<()> {
+        let kind = self.kind.as_str();
+        let source_tags = serde_json::to_string(&self.source_tags)?;
+        let published_at = self.published_at.timestamp();
+        let pushed_at = self.pushed_at.timestamp();
+        let marked_at = self.marked_at.map(|t| t.timestamp());
         sqlx::query!(
             r"
             INSERT INTO `item`
-            (`id`, `json`, `message_id`, `create_time`, `category`, `author`)
+            (`id`, `kind`, `title`, `description`, `uploader_uid`, `uploader_name`,
+             `cover_url`, `image_key`, `duration`, `picture_count`, `source_tags`,
+             `view_count`, `like_count`, `reply_count`, `danmaku_count`, `message_id`,
+             `published_at`, `pushed_at`, `category`, `marker`, `marked_at`)
             VALUES
-            (?, ?, ?, ?, ?, ?);
+            (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
             ",
             self.id,
-            self.json,
+            kind,
+            self.title,
+            self.description,
+            self.uploader_uid,
+            self.uploader_name,
+            self.cover_url,
+            self.image_key,
+            self.duration,
+            self.picture_count,
+            source_tags,
+            self.stat.view,
+            self.stat.like,
+            self.stat.reply,
+            self.stat.danmaku,
             self.message_id,
-            self.create_time,
+            published_at,
+            pushed_at,
             self.category,
-            self.author
+            self.marker,
+            marked_at
         )
         .execute(&*pool)
         .await?;
         Ok(())
     }
 
-    pub async fn json_from(id: &str, pool: &Pool) -> Result<String> {
-        let json = sqlx::query_scalar!(
-            r"
-            SELECT `json`
-            FROM `item`
-            WHERE `id` = ?
-            LIMIT 1;
-            ",
-            id
-        )
-        .fetch_one(&*pool)
-        .await?;
-        Ok(json)
-    }
-
     pub async fn from_id(id: &str, pool: &Pool) -> Result<Option<Item>> {
-        let raw_item = sqlx::query_as!(
-            RawItem::<String>,
+        let row = sqlx::query_as!(
+            ItemRow,
             r"
-            SELECT `id`, `json`, `message_id`, `create_time`, `category`, `author`
+            SELECT
+                `id`, `kind`, `title`, `description`, `uploader_uid`, `uploader_name`,
+                `cover_url`, `image_key`, `duration`, `picture_count`, `source_tags`,
+                `view_count`, `like_count`, `reply_count`, `danmaku_count`, `message_id`,
+                `published_at`, `pushed_at`, `category`, `marker`, `marked_at`
             FROM `item`
             WHERE `id` = ?
             LIMIT 1;
@@ -113,43 +280,44 @@ impl Item {
         )
         .fetch_optional(&*pool)
         .await?;
-
-        match raw_item {
-            Some(item) => Ok(Some(item.try_into()?)),
-            None => Ok(None),
-        }
+        row.map(Item::try_from).transpose()
     }
 
-    pub async fn set_json(id: &str, json: &str, pool: &Pool) -> Result<()> {
+    pub async fn set_category(id: &str, category: &str, marker: &str, pool: &Pool) -> Result<()> {
+        let t = Utc::now().timestamp();
         sqlx::query!(
             r"
             UPDATE `item`
-            SET 
-                `json` = ?
+            SET
+                `category` = ?,
+                `marked_at` = ?,
+                `marker` = ?
             WHERE
                 `id` = ?
             ",
-            json,
-            id
+            category,
+            t,
+            marker,
+            id,
         )
         .execute(&*pool)
         .await?;
         Ok(())
     }
 
-    pub async fn set_category(id: &str, category: &str, marker: &str, pool: &Pool) -> Result<()> {
-        let t = Utc::now();
+    /// 看过但是不选入，只记录谁看的，分类为空
+    pub async fn reject(id: &str, marker: &str, pool: &Pool) -> Result<()> {
+        let t = Utc::now().timestamp();
         sqlx::query!(
             r"
             UPDATE `item`
-            SET 
-                `category` = ?,
-                `mark_time` = ?,
+            SET
+                `category` = NULL,
+                `marked_at` = ?,
                 `marker` = ?
             WHERE
                 `id` = ?
             ",
-            category,
             t,
             marker,
             id,
@@ -160,13 +328,13 @@ impl Item {
     }
 
     pub async fn remove_category(id: &str, pool: &Pool) -> Result<()> {
-        let t = Utc::now();
+        let t = Utc::now().timestamp();
         sqlx::query!(
             r"
             UPDATE `item`
-            SET 
+            SET
                 `category` = NULL,
-                `mark_time` = ?,
+                `marked_at` = ?,
                 `marker` = NULL
             WHERE
                 `id` = ?
@@ -179,97 +347,463 @@ impl Item {
         Ok(())
     }
 
-    pub async fn all_item_json(message_id: &str, pool: &Pool) -> Result<Vec<String>> {
-        let s = sqlx::query_scalar!(
+    /// 同一条飞书消息里的所有条目，按推送顺序
+    pub async fn all_in_message(message_id: &str, pool: &Pool) -> Result<Vec<Self>> {
+        let rows = sqlx::query_as!(
+            ItemRow,
             r"
-            SELECT `json`
+            SELECT
+                `id`, `kind`, `title`, `description`, `uploader_uid`, `uploader_name`,
+                `cover_url`, `image_key`, `duration`, `picture_count`, `source_tags`,
+                `view_count`, `like_count`, `reply_count`, `danmaku_count`, `message_id`,
+                `published_at`, `pushed_at`, `category`, `marker`, `marked_at`
             FROM `item`
-            WHERE 
-                `message_id` = ?
+            WHERE `message_id` = ?
+            ORDER BY `rowid` ASC;
             ",
             message_id
         )
         .fetch_all(&*pool)
         .await?;
-        Ok(s)
+        rows.into_iter().map(Item::try_from).collect()
     }
 
-    pub async fn all_categorized_in_date(date: DateTime<Utc>, pool: &Pool) -> Result<Vec<Self>> {
-        let date = date.with_timezone(&Shanghai).date();
-        let start = date.and_hms(0, 0, 0).with_timezone(&Utc);
-        let start = start.format("%Y-%m-%d %H:%M:%S").to_string();
-        let end = date.and_hms(23, 59, 59).with_timezone(&Utc);
-        let end = end.format("%Y-%m-%d %H:%M:%S").to_string();
-
-        let items = sqlx::query_as!(
-            RawItem::<String>,
-            r#"
-            SELECT `id`, `json`, `message_id`, `create_time`, `category`, `author`
+    /// 北京时间 `from` 到 `to` 发布的已经分类的条目，两头都包含
+    pub async fn all_categorized_in_dates(
+        from: NaiveDate,
+        to: NaiveDate,
+        pool: &Pool,
+    ) -> Result<Vec<Self>> {
+        let (start, end) = dates_range(from, to);
+        let rows = sqlx::query_as!(
+            ItemRow,
+            r"
+            SELECT
+                `id`, `kind`, `title`, `description`, `uploader_uid`, `uploader_name`,
+                `cover_url`, `image_key`, `duration`, `picture_count`, `source_tags`,
+                `view_count`, `like_count`, `reply_count`, `danmaku_count`, `message_id`,
+                `published_at`, `pushed_at`, `category`, `marker`, `marked_at`
             FROM `item`
             WHERE
-                `create_time` BETWEEN ? AND ?
+                `published_at` BETWEEN ? AND ?
                 AND `category` is not null
-            ORDER BY `create_time` ASC;
-            "#,
+            ORDER BY `published_at` ASC;
+            ",
             start,
             end
         )
         .fetch_all(&*pool)
         .await?;
-
-        let mut res = vec![];
-        for item in items {
-            res.push(item.try_into()?);
-        }
-        Ok(res)
+        rows.into_iter().map(Item::try_from).collect()
     }
 
     /// 返回 (user_id, 标记次数)
     pub async fn get_kpi(date: DateTime<Utc>, pool: &Pool) -> Result<Vec<(String, u32)>> {
-        // 把 Utc 切换成这个日期对应的 UTC+8 的自然资源，然后再表示成 Utc 时间
-        let date = date.with_timezone(&Shanghai).date();
-        let start = date.and_hms(0, 0, 0).with_timezone(&Utc);
-        let start = start.format("%Y-%m-%d %H:%M:%S").to_string();
-        let end = date.and_hms(23, 59, 59).with_timezone(&Utc);
-        let end = end.format("%Y-%m-%d %H:%M:%S").to_string();
-
-        #[derive(sqlx::FromRow)]
-        struct MarkerScore {
-            marker: String,
-            count: u32,
-        }
+        let (start, end) = day_range(date);
 
-        let items: Vec<MarkerScore> = sqlx::query_as(
+        let items = sqlx::query!(
             r#"
-            SELECT `marker`, COUNT(*) as count
+            SELECT `marker` as "marker!", COUNT(*) as "count!: u32"
             FROM `item`
             WHERE
                 `marker` is not NULL
                 AND
-                `mark_time` BETWEEN $1 AND $2
+                `marked_at` BETWEEN $1 AND $2
             GROUP BY `marker`
             "#,
+            start,
+            end
         )
-        .bind(start)
-        .bind(end)
         .fetch_all(&*pool)
         .await?;
 
         Ok(items.into_iter().map(|m| (m.marker, m.count)).collect())
     }
+
+    /// 北京时间 `from` 到 `to` 的所有标记（选入和拒绝），两头都包含
+    pub async fn marks_in_dates(from: NaiveDate, to: NaiveDate, pool: &Pool) -> Result<Vec<Mark>> {
+        let (start, end) = dates_range(from, to);
+        let rows = sqlx::query!(
+            r#"
+            SELECT `marker` as "marker!", `kind`, `category`, `pushed_at`, `marked_at` as "marked_at!"
+            FROM `item`
+            WHERE
+                `marker` IS NOT NULL
+                AND `marked_at` BETWEEN ? AND ?
+            ORDER BY `marked_at` ASC;
+            "#,
+            start,
+            end
+        )
+        .fetch_all(&*pool)
+        .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(Mark {
+                    marker: row.marker,
+                    kind: ItemKind::from_str(&row.kind)?,
+                    category: row.category,
+                    pushed_at: from_timestamp(row.pushed_at),
+                    marked_at: from_timestamp(row.marked_at),
+                })
+            })
+            .collect()
+    }
+
+    /// 返回 (这一天推送的条目数, 其中已经分类的条目数)
+    pub async fn count_in_date(date: DateTime<Utc>, pool: &Pool) -> Result<(u32, u32)> {
+        let (start, end) = day_range(date);
+
+        let r = sqlx::query!(
+            r#"
+            SELECT COUNT(*) as "total: u32", COUNT(`category`) as "categorized!: u32"
+            FROM `item`
+            WHERE
+                `pushed_at` BETWEEN $1 AND $2
+            "#,
+            start,
+            end
+        )
+        .fetch_one(&*pool)
+        .await?;
+        Ok((r.total, r.categorized))
+    }
+
+    /// 还没有解析过卡片 json 的旧数据
+    pub async fn legacy(pool: &Pool) -> Result<Vec<LegacyItem>> {
+        let items = sqlx::query_as!(
+            LegacyItem,
+            r#"
+            SELECT `id`, `kind`, `legacy_json` as "legacy_json!"
+            FROM `item`
+            WHERE `legacy_json` IS NOT NULL;
+            "#
+        )
+        .fetch_all(&*pool)
+        .await?;
+        Ok(items)
+    }
+
+    /// 写入从旧数据解析出来的字段，并清空 legacy_json
+    pub async fn fill_legacy(id: &str, fields: LegacyFields, pool: &Pool) -> Result<()> {
+        sqlx::query!(
+            r"
+            UPDATE `item`
+            SET
+                `title` = ?,
+                `uploader_name` = COALESCE(?, `uploader_name`),
+                `image_key` = ?,
+                `duration` = ?,
+                `picture_count` = ?,
+                `view_count` = ?,
+                `reply_count` = ?,
+                `danmaku_count` = ?,
+                `legacy_json` = NULL
+            WHERE
+                `id` = ?
+            ",
+            fields.title,
+            fields.uploader_name,
+            fields.image_key,
+            fields.duration,
+            fields.picture_count,
+            fields.stat.view,
+            fields.stat.reply,
+            fields.stat.danmaku,
+            id
+        )
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// 排序用的表达式，没有标记的 marked_at 当成 0，保证游标能比较
+fn sort_expr(sort: ItemSort) -> &'static str {
+    match sort {
+        ItemSort::PublishedAt => "`published_at`",
+        ItemSort::PushedAt => "`pushed_at`",
+        ItemSort::MarkedAt => "COALESCE(`marked_at`, 0)",
+    }
+}
+
+/// 列表接口的筛选条件，都是可选的
+#[derive(Debug, Default)]
+pub struct ItemFilter {
+    pub kind: Option<ItemKind>,
+    /// 分类名和它的别名，空的时候不筛选
+    pub categories: Vec<String>,
+    /// 只要还没分类的
+    pub uncategorized: bool,
+    pub uploader_uid: Option<i64>,
+    pub uploader_name: Option<String>,
+    pub marker: Option<String>,
+    pub published_from: Option<DateTime<Utc>>,
+    pub published_to: Option<DateTime<Utc>>,
+    pub marked_from: Option<DateTime<Utc>>,
+    pub marked_to: Option<DateTime<Utc>>,
+    /// 搜索标题（视频标题、动态正文）和视频简介
+    pub q: Option<String>,
+}
+
+/// 按 (排序字段, seq) 翻页
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ItemCursor {
+    key: i64,
+    seq: i64,
+}
+impl ItemCursor {
+    pub fn parse(s: &str) -> Result<Self> {
+        let (key, seq) = s.split_once('_').context("游标格式错误")?;
+        Ok(Self {
+            key: key.parse().context("游标格式错误")?,
+            seq: seq.parse().context("游标格式错误")?,
+        })
+    }
+}
+impl std::fmt::Display for ItemCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.key, self.seq)
+    }
+}
+
+#[derive(Debug)]
+pub struct ItemPage {
+    pub items: Vec<Item>,
+    /// 没有下一页时为空
+    pub next_cursor: Option<String>,
+}
+
+impl From<Item> for api::Item {
+    fn from(item: Item) -> Self {
+        Self {
+            id: item.id,
+            kind: item.kind,
+            title: item.title,
+            description: item.description,
+            uploader_uid: item.uploader_uid,
+            uploader_name: item.uploader_name,
+            cover_url: item.cover_url,
+            image_key: item.image_key,
+            duration: item.duration,
+            picture_count: item.picture_count,
+            source_tags: item.source_tags,
+            view_count: item.stat.view,
+            like_count: item.stat.like,
+            reply_count: item.stat.reply,
+            danmaku_count: item.stat.danmaku,
+            message_id: item.message_id,
+            published_at: item.published_at,
+            pushed_at: item.pushed_at,
+            category: item.category,
+            marker: item.marker,
+            marked_at: item.marked_at,
+        }
+    }
+}
+
+impl From<ItemPage> for api::ItemPage {
+    fn from(page: ItemPage) -> Self {
+        Self {
+            items: page.items.into_iter().map(Into::into).collect(),
+            next_cursor: page.next_cursor,
+        }
+    }
+}
+
+enum Arg {
+    Int(i64),
+    Text(String),
+}
+
+/// fts5 的 trigram 至少要三个字，短的用 LIKE
+const FTS_MIN_CHARS: usize = 3;
+
+fn like_pattern(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn fts_phrase(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+impl ItemFilter {
+    fn where_clause(&self, conditions: &mut Vec<String>, args: &mut Vec<Arg>) {
+        if !self.categories.is_empty() {
+            let placeholders = vec!["?"; self.categories.len()].join(", ");
+            conditions.push(format!("`category` IN ({})", placeholders));
+            args.extend(self.categories.iter().cloned().map(Arg::Text));
+        }
+        let mut cond = |sql: &str, arg: Option<Arg>| {
+            conditions.push(sql.to_string());
+            args.extend(arg);
+        };
+        if let Some(kind) = self.kind {
+            cond("`kind` = ?", Some(Arg::Text(kind.as_str().to_string())));
+        }
+        if self.uncategorized {
+            cond("`category` IS NULL", None);
+        }
+        if let Some(uid) = self.uploader_uid {
+            cond("`uploader_uid` = ?", Some(Arg::Int(uid)));
+        }
+        if let Some(name) = &self.uploader_name {
+            cond("`uploader_name` = ?", Some(Arg::Text(name.clone())));
+        }
+        if let Some(marker) = &self.marker {
+            cond("`marker` = ?", Some(Arg::Text(marker.clone())));
+        }
+        let times = [
+            (&self.published_from, "`published_at` >= ?"),
+            (&self.published_to, "`published_at` < ?"),
+            (&self.marked_from, "`marked_at` >= ?"),
+            (&self.marked_to, "`marked_at` < ?"),
+        ];
+        for &(t, sql) in times.iter() {
+            if let Some(t) = t {
+                cond(sql, Some(Arg::Int(t.timestamp())));
+            }
+        }
+        match self.q.as_deref().map(str::trim) {
+            Some(q) if q.chars().count() >= FTS_MIN_CHARS => cond(
+                "`seq` IN (SELECT `rowid` FROM `item_fts` WHERE `item_fts` MATCH ?)",
+                Some(Arg::Text(fts_phrase(q))),
+            ),
+            Some(q) if !q.is_empty() => {
+                conditions.push(
+                    r"(`title` LIKE ? ESCAPE '\' OR `description` LIKE ? ESCAPE '\')".to_string(),
+                );
+                args.push(Arg::Text(like_pattern(q)));
+                args.push(Arg::Text(like_pattern(q)));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Item {
+    /// 按条件列出条目，`cursor` 是上一页返回的 `next_cursor`
+    pub async fn list(
+        filter: &ItemFilter,
+        sort: ItemSort,
+        desc: bool,
+        cursor: Option<ItemCursor>,
+        limit: u32,
+        pool: &Pool,
+    ) -> Result<ItemPage> {
+        let mut conditions = vec![];
+        let mut args = vec![];
+        filter.where_clause(&mut conditions, &mut args);
+        let expr = sort_expr(sort);
+        let (cmp, order) = if desc { ("<", "DESC") } else { (">", "ASC") };
+        if let Some(cursor) = cursor {
+            conditions.push(format!(
+                "({expr} {cmp} ? OR ({expr} = ? AND `seq` {cmp} ?))",
+                expr = expr,
+                cmp = cmp
+            ));
+            args.extend([
+                Arg::Int(cursor.key),
+                Arg::Int(cursor.key),
+                Arg::Int(cursor.seq),
+            ]);
+        }
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        let sql = format!(
+            r"
+            SELECT {columns}, `seq`, {expr} AS `sort_key`
+            FROM `item`
+            {where_clause}
+            ORDER BY {expr} {order}, `seq` {order}
+            LIMIT ?;
+            ",
+            columns = ITEM_COLUMNS,
+            expr = expr,
+            where_clause = where_clause,
+            order = order
+        );
+        let mut query = sqlx::query(&sql);
+        for arg in args {
+            query = match arg {
+                Arg::Int(i) => query.bind(i),
+                Arg::Text(s) => query.bind(s),
+            };
+        }
+        // 多取一条判断有没有下一页
+        let rows = query.bind(limit as i64 + 1).fetch_all(&*pool).await?;
+
+        let has_more = rows.len() > limit as usize;
+        let mut items = vec![];
+        let mut next_cursor = None;
+        for row in rows.iter().take(limit as usize) {
+            items.push(Item::try_from(ItemRow::from_row(row)?)?);
+            next_cursor = Some(ItemCursor {
+                key: row.try_get("sort_key")?,
+                seq: row.try_get("seq")?,
+            });
+        }
+        Ok(ItemPage {
+            items,
+            next_cursor: next_cursor.filter(|_| has_more).map(|c| c.to_string()),
+        })
+    }
+}
+
+/// 群的生命周期
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupState {
+    /// 使用中
+    Active,
+    /// 已经把人都移出群了，群还在
+    MembersRemoved,
+    /// 已经解散
+    Dissolved,
+}
+impl GroupState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GroupState::Active => "active",
+            GroupState::MembersRemoved => "members_removed",
+            GroupState::Dissolved => "dissolved",
+        }
+    }
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "active" => GroupState::Active,
+            "members_removed" => GroupState::MembersRemoved,
+            "dissolved" => GroupState::Dissolved,
+            _ => bail!("unknown group state: {}", s),
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Group {
     pub name: String,
     pub chat_id: String,
+    pub state: GroupState,
 }
 impl Group {
+    fn from_row(name: String, chat_id: String, state: String) -> Result<Self> {
+        Ok(Self {
+            name,
+            chat_id,
+            state: GroupState::from_str(&state)?,
+        })
+    }
+
     pub async fn from_name(name: &str, pool: &Pool) -> Result<Option<Group>> {
-        let group = sqlx::query_as!(
-            Self,
+        let row = sqlx::query!(
             r#"
-            SELECT  `name`, chat_id
+            SELECT  `name`, `chat_id`, `state`
             FROM    `group`
             WHERE   `name` = ?
             LIMIT 1;
@@ -278,95 +812,726 @@ impl Group {
         )
         .fetch_optional(&*pool)
         .await?;
-        Ok(group)
+        row.map(|r| Self::from_row(r.name, r.chat_id, r.state))
+            .transpose()
     }
-    pub async fn insert(name: &str, chat_id: &str, pool: &Pool) -> Result<Self> {
+
+    /// 插入新群，或者重新启用已经归档的同名群
+    pub async fn upsert(name: &str, chat_id: &str, pool: &Pool) -> Result<Self> {
+        let now = Utc::now();
         sqlx::query!(
             r"
             INSERT INTO `group`
-            (`name`, `chat_id`)
+            (`name`, `chat_id`, `state`, `create_time`, `last_active_time`)
             VALUES
-            (?, ?);
+            (?, ?, 'active', ?, ?)
+            ON CONFLICT(`name`) DO UPDATE SET
+                `chat_id` = excluded.`chat_id`,
+                `state` = 'active',
+                `last_active_time` = excluded.`last_active_time`,
+                `archive_time` = NULL;
             ",
             name,
-            chat_id
+            chat_id,
+            now,
+            now
         )
         .execute(&*pool)
         .await?;
         Ok(Self {
             name: name.to_string(),
             chat_id: chat_id.to_string(),
+            state: GroupState::Active,
         })
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
 
-    async fn _test_serde_item(t: DateTime<Utc>) {
-        let id = "1dkfjgndkfjg".to_string();
-        let json = "[]".to_string();
-        let item = Item {
-            id: id.clone(),
-            json: json.to_string(),
-            message_id: "asds".to_string(),
-            create_time: t,
-            category: None,
-            author: "test author".to_string(),
-        };
-        let pool = init("sqlite://:memory:").await.unwrap();
-        sqlx::migrate!().run(&pool).await.unwrap();
-        item.insert(&pool).await.unwrap();
-        let item = Item::from_id(&id, &pool).await.unwrap().unwrap();
+    /// 记录群又被用了一次
+    pub async fn touch(name: &str, pool: &Pool) -> Result<()> {
+        let now = Utc::now();
+        sqlx::query!(
+            r"
+            UPDATE `group`
+            SET `last_active_time` = ?
+            WHERE `name` = ?;
+            ",
+            now,
+            name
+        )
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
 
-        assert_eq!(item.json, json);
-        assert_eq!(item.create_time, t);
+    /// `before` 之后就没有再用过的、还没归档的群
+    pub async fn inactive_since(before: DateTime<Utc>, pool: &Pool) -> Result<Vec<Group>> {
+        let rows = sqlx::query!(
+            r"
+            SELECT  `name`, `chat_id`, `state`
+            FROM    `group`
+            WHERE   `state` = 'active'
+                AND `last_active_time` < ?
+            ORDER BY `last_active_time` ASC;
+            ",
+            before
+        )
+        .fetch_all(&*pool)
+        .await?;
+        rows.into_iter()
+            .map(|r| Self::from_row(r.name, r.chat_id, r.state))
+            .collect()
     }
 
-    #[tokio::test]
-    async fn test_serde_item() {
-        _test_serde_item("2021-07-23 03:40:28Z".parse().unwrap()).await;
-        _test_serde_item(Utc::now()).await;
+    pub async fn set_state(name: &str, state: GroupState, pool: &Pool) -> Result<()> {
+        let state = state.as_str();
+        let now = Utc::now();
+        sqlx::query!(
+            r"
+            UPDATE `group`
+            SET `state` = ?,
+                `archive_time` = ?
+            WHERE `name` = ?;
+            ",
+            state,
+            now,
+            name
+        )
+        .execute(&*pool)
+        .await?;
+        Ok(())
     }
+}
 
-    #[test]
-    fn test_parse_t() {
-        use chrono::*;
-        let t = NaiveDateTime::parse_from_str("2021-07-23 03:40:28", "%Y-%m-%d %H:%M:%S").unwrap();
-        let t: DateTime<Utc> = DateTime::from_utc(t, Utc);
-        dbg!(t);
-        dbg!(t.to_string());
+/// 被屏蔽的 UP 主
+pub struct BlockedUser;
+impl BlockedUser {
+    pub async fn all(pool: &Pool) -> Result<HashSet<i64>> {
+        let uids: Vec<i64> = sqlx::query_scalar(
+            r"
+            SELECT `uid`
+            FROM `blocked_user`;
+            ",
+        )
+        .fetch_all(&*pool)
+        .await?;
+        Ok(uids.into_iter().collect())
     }
 
-    #[tokio::test]
-    async fn test_get_kpi() -> Result<()> {
-        let pool = init("sqlite://:memory:").await?;
-        sqlx::migrate!().run(&pool).await?;
-        let id = "sdjkfneig".to_string();
-        let item = Item {
-            id: id.to_string(),
-            json: "123".to_string(),
-            message_id: "123".to_string(),
-            create_time: Utc::now(),
-            category: None,
-            author: "123".to_string(),
-        };
-        item.insert(&pool).await?;
-        Item::set_category(&id, "category", "marker", &pool).await?;
-        let kpi = Item::get_kpi(Utc::now(), &pool).await?;
-        assert_eq!(kpi, vec![("marker".to_string(), 1)]);
+    pub async fn insert(uid: i64, operator: &str, pool: &Pool) -> Result<()> {
+        sqlx::query(
+            r"
+            INSERT OR REPLACE INTO `blocked_user`
+            (`uid`, `operator`, `create_time`)
+            VALUES
+            (?, ?, ?);
+            ",
+        )
+        .bind(uid)
+        .bind(operator)
+        .bind(Utc::now())
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
 
-        let id = "kjtrnyx".to_string();
-        let item = Item {
-            id: id.to_string(),
-            json: "123".to_string(),
-            message_id: "123".to_string(),
-            create_time: Utc::now(),
-            category: None,
-            author: "123".to_string(),
+    /// 返回是否确实删除了
+    pub async fn remove(uid: i64, pool: &Pool) -> Result<bool> {
+        let r = sqlx::query(
+            r"
+            DELETE FROM `blocked_user`
+            WHERE `uid` = ?;
+            ",
+        )
+        .bind(uid)
+        .execute(&*pool)
+        .await?;
+        Ok(r.rows_affected() > 0)
+    }
+}
+
+/// 上传到飞书的图片的 image_key 缓存
+pub struct ImageCache;
+impl ImageCache {
+    /// source 为图片的 url 或者内容的 hash
+    pub async fn get(source: &str, pool: &Pool) -> Result<Option<String>> {
+        let image_key: Option<String> = sqlx::query_scalar(
+            r"
+            SELECT `image_key`
+            FROM `image_cache`
+            WHERE `source` = ?
+            LIMIT 1;
+            ",
+        )
+        .bind(source)
+        .fetch_optional(&*pool)
+        .await?;
+        Ok(image_key)
+    }
+
+    pub async fn insert(source: &str, image_key: &str, pool: &Pool) -> Result<()> {
+        sqlx::query(
+            r"
+            INSERT OR REPLACE INTO `image_cache`
+            (`source`, `image_key`, `create_time`)
+            VALUES
+            (?, ?, ?);
+            ",
+        )
+        .bind(source)
+        .bind(image_key)
+        .bind(Utc::now())
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// 发到归档群的记录
+pub struct Archive;
+impl Archive {
+    /// 占住一个条目的归档，返回 false 表示已经归档过或者正在归档。
+    ///
+    /// 占住超过 `stale_before` 还没发出去的（比如中途进程退出）可以重新占住
+    pub async fn claim(
+        item_id: &str,
+        chat_id: &str,
+        stale_before: DateTime<Utc>,
+        pool: &Pool,
+    ) -> Result<bool> {
+        let r = sqlx::query(
+            r"
+            INSERT INTO `archive`
+            (`item_id`, `chat_id`, `message_id`, `create_time`)
+            VALUES
+            (?, ?, NULL, ?)
+            ON CONFLICT(`item_id`) DO UPDATE SET
+                `chat_id` = excluded.`chat_id`,
+                `create_time` = excluded.`create_time`
+            WHERE `message_id` IS NULL
+                AND `create_time` < ?;
+            ",
+        )
+        .bind(item_id)
+        .bind(chat_id)
+        .bind(Utc::now())
+        .bind(stale_before)
+        .execute(&*pool)
+        .await?;
+        Ok(r.rows_affected() > 0)
+    }
+
+    /// 发送成功
+    pub async fn set_message_id(item_id: &str, message_id: &str, pool: &Pool) -> Result<()> {
+        sqlx::query(
+            r"
+            UPDATE `archive`
+            SET `message_id` = ?
+            WHERE `item_id` = ?;
+            ",
+        )
+        .bind(message_id)
+        .bind(item_id)
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 发送失败，放弃占住的归档，下次可以重新发
+    pub async fn release(item_id: &str, pool: &Pool) -> Result<()> {
+        sqlx::query(
+            r"
+            DELETE FROM `archive`
+            WHERE `item_id` = ?
+                AND `message_id` IS NULL;
+            ",
+        )
+        .bind(item_id)
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// 分类
+#[derive(Debug, Clone, PartialEq)]
+pub struct Category {
+    pub name: String,
+    /// 出现在哪种卡片上
+    pub kind: ItemKind,
+    /// 越小越靠前
+    pub sort_order: i64,
+    /// 旧的分类名，如 `ok` => `动态`
+    pub aliases: Vec<String>,
+    pub active: bool,
+    /// 日报里的小标题，为空时用分类名
+    pub section_title: Option<String>,
+}
+impl From<Category> for api::Category {
+    fn from(c: Category) -> Self {
+        Self {
+            name: c.name,
+            kind: c.kind,
+            sort_order: c.sort_order,
+            aliases: c.aliases,
+            active: c.active,
+            section_title: c.section_title,
+        }
+    }
+}
+impl Category {
+    fn from_row(
+        (name, kind, sort_order, aliases, active, section_title): (
+            String,
+            String,
+            i64,
+            String,
+            bool,
+            Option<String>,
+        ),
+    ) -> Result<Self> {
+        Ok(Self {
+            name,
+            kind: ItemKind::from_str(&kind)?,
+            sort_order,
+            aliases: serde_json::from_str(&aliases)?,
+            active,
+            section_title,
+        })
+    }
+
+    /// 所有分类，包括停用的，按顺序排好
+    pub async fn all(pool: &Pool) -> Result<Vec<Self>> {
+        let rows = sqlx::query_as(
+            r"
+            SELECT  `name`, `kind`, `sort_order`, `aliases`, `active`, `section_title`
+            FROM    `category`
+            ORDER BY `sort_order` ASC, `name` ASC;
+            ",
+        )
+        .fetch_all(&*pool)
+        .await?;
+        rows.into_iter().map(Self::from_row).collect()
+    }
+
+    pub async fn from_name(name: &str, pool: &Pool) -> Result<Option<Self>> {
+        let row = sqlx::query_as(
+            r"
+            SELECT  `name`, `kind`, `sort_order`, `aliases`, `active`, `section_title`
+            FROM    `category`
+            WHERE   `name` = ?
+            LIMIT 1;
+            ",
+        )
+        .bind(name)
+        .fetch_optional(&*pool)
+        .await?;
+        row.map(Self::from_row).transpose()
+    }
+
+    pub async fn insert(&self, pool: &Pool) -> Result<()> {
+        sqlx::query(
+            r"
+            INSERT INTO `category`
+            (`name`, `kind`, `sort_order`, `aliases`, `active`, `section_title`, `create_time`)
+            VALUES
+            (?, ?, ?, ?, ?, ?, ?);
+            ",
+        )
+        .bind(&self.name)
+        .bind(self.kind.as_str())
+        .bind(self.sort_order)
+        .bind(serde_json::to_string(&self.aliases)?)
+        .bind(self.active)
+        .bind(&self.section_title)
+        .bind(Utc::now())
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 按名字更新其他字段
+    pub async fn update(&self, pool: &Pool) -> Result<()> {
+        sqlx::query(
+            r"
+            UPDATE `category`
+            SET `kind` = ?,
+                `sort_order` = ?,
+                `aliases` = ?,
+                `active` = ?,
+                `section_title` = ?
+            WHERE `name` = ?;
+            ",
+        )
+        .bind(self.kind.as_str())
+        .bind(self.sort_order)
+        .bind(serde_json::to_string(&self.aliases)?)
+        .bind(self.active)
+        .bind(&self.section_title)
+        .bind(&self.name)
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 返回是否确实删除了，已经分类的条目不受影响
+    pub async fn remove(name: &str, pool: &Pool) -> Result<bool> {
+        let r = sqlx::query(
+            r"
+            DELETE FROM `category`
+            WHERE `name` = ?;
+            ",
+        )
+        .bind(name)
+        .execute(&*pool)
+        .await?;
+        Ok(r.rows_affected() > 0)
+    }
+}
+
+pub use api::{DeliveryStatus, WebhookEvent};
+
+/// webhook 投递队列里的一条
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook: String,
+    pub event: WebhookEvent,
+    pub item_id: String,
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow)]
+struct WebhookDeliveryRow {
+    id: i64,
+    webhook: String,
+    event: String,
+    item_id: String,
+    payload: String,
+    status: String,
+    attempts: u32,
+    last_status_code: Option<u16>,
+    last_error: Option<String>,
+    created_at: i64,
+    next_attempt_at: i64,
+    delivered_at: Option<i64>,
+}
+
+const WEBHOOK_DELIVERY_COLUMNS: &str = "`id`, `webhook`, `event`, `item_id`, `payload`, \
+    `status`, `attempts`, `last_status_code`, `last_error`, `created_at`, `next_attempt_at`, \
+    `delivered_at`";
+
+impl TryFrom<WebhookDeliveryRow> for WebhookDelivery {
+    type Error = anyhow::Error;
+    fn try_from(row: WebhookDeliveryRow) -> Result<Self> {
+        Ok(Self {
+            id: row.id,
+            webhook: row.webhook,
+            event: row.event.parse()?,
+            item_id: row.item_id,
+            payload: row.payload,
+            status: row.status.parse()?,
+            attempts: row.attempts,
+            last_status_code: row.last_status_code,
+            last_error: row.last_error,
+            created_at: from_timestamp(row.created_at),
+            next_attempt_at: from_timestamp(row.next_attempt_at),
+            delivered_at: row.delivered_at.map(from_timestamp),
+        })
+    }
+}
+
+impl From<WebhookDelivery> for api::WebhookDelivery {
+    fn from(d: WebhookDelivery) -> Self {
+        Self {
+            id: d.id,
+            webhook: d.webhook,
+            event: d.event,
+            item_id: d.item_id,
+            status: d.status,
+            attempts: d.attempts,
+            last_status_code: d.last_status_code,
+            last_error: d.last_error,
+            created_at: d.created_at,
+            next_attempt_at: d.next_attempt_at,
+            delivered_at: d.delivered_at,
+        }
+    }
+}
+
+impl WebhookDelivery {
+    /// 入队，马上可以投递
+    pub async fn enqueue(
+        webhook: &str,
+        event: WebhookEvent,
+        item_id: &str,
+        payload: &str,
+        pool: &Pool,
+    ) -> Result<i64> {
+        let now = Utc::now().timestamp();
+        let r = sqlx::query(
+            r"
+            INSERT INTO `webhook_delivery`
+            (`webhook`, `event`, `item_id`, `payload`, `status`, `attempts`,
+                `created_at`, `next_attempt_at`)
+            VALUES
+            (?, ?, ?, ?, 'pending', 0, ?, ?);
+            ",
+        )
+        .bind(webhook)
+        .bind(event.as_str())
+        .bind(item_id)
+        .bind(payload)
+        .bind(now)
+        .bind(now)
+        .execute(&*pool)
+        .await?;
+        Ok(r.last_insert_rowid())
+    }
+
+    /// 到了投递时间的，先入队的在前
+    pub async fn due(now: DateTime<Utc>, limit: u32, pool: &Pool) -> Result<Vec<Self>> {
+        let sql = format!(
+            r"
+            SELECT {}
+            FROM `webhook_delivery`
+            WHERE `status` = 'pending'
+                AND `next_attempt_at` <= ?
+            ORDER BY `id`
+            LIMIT ?;
+            ",
+            WEBHOOK_DELIVERY_COLUMNS
+        );
+        let rows: Vec<WebhookDeliveryRow> = sqlx::query_as(&sql)
+            .bind(now.timestamp())
+            .bind(limit)
+            .fetch_all(&*pool)
+            .await?;
+        rows.into_iter().map(Self::try_from).collect()
+    }
+
+    pub async fn set_delivered(id: i64, status_code: u16, pool: &Pool) -> Result<()> {
+        sqlx::query(
+            r"
+            UPDATE `webhook_delivery`
+            SET `status` = 'delivered',
+                `attempts` = `attempts` + 1,
+                `last_status_code` = ?,
+                `last_error` = NULL,
+                `delivered_at` = ?
+            WHERE `id` = ?;
+            ",
+        )
+        .bind(status_code)
+        .bind(Utc::now().timestamp())
+        .bind(id)
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 投递失败，`retry_at` 为空表示不再重试
+    pub async fn set_failed(
+        id: i64,
+        status_code: Option<u16>,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+        pool: &Pool,
+    ) -> Result<()> {
+        let status = match retry_at {
+            Some(_) => DeliveryStatus::Pending,
+            None => DeliveryStatus::Failed,
         };
-        item.insert(&pool).await?;
+        sqlx::query(
+            r"
+            UPDATE `webhook_delivery`
+            SET `status` = ?,
+                `attempts` = `attempts` + 1,
+                `last_status_code` = ?,
+                `last_error` = ?,
+                `next_attempt_at` = COALESCE(?, `next_attempt_at`)
+            WHERE `id` = ?;
+            ",
+        )
+        .bind(status.as_str())
+        .bind(status_code)
+        .bind(error)
+        .bind(retry_at.map(|t| t.timestamp()))
+        .bind(id)
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 投递记录，新的在前，`before` 用来翻页
+    pub async fn list(
+        webhook: Option<&str>,
+        status: Option<DeliveryStatus>,
+        before: Option<i64>,
+        limit: u32,
+        pool: &Pool,
+    ) -> Result<Vec<Self>> {
+        let sql = format!(
+            r"
+            SELECT {}
+            FROM `webhook_delivery`
+            WHERE (? IS NULL OR `webhook` = ?)
+                AND (? IS NULL OR `status` = ?)
+                AND (? IS NULL OR `id` < ?)
+            ORDER BY `id` DESC
+            LIMIT ?;
+            ",
+            WEBHOOK_DELIVERY_COLUMNS
+        );
+        let status = status.map(|s| s.as_str());
+        let rows: Vec<WebhookDeliveryRow> = sqlx::query_as(&sql)
+            .bind(webhook)
+            .bind(webhook)
+            .bind(status)
+            .bind(status)
+            .bind(before)
+            .bind(before)
+            .bind(limit)
+            .fetch_all(&*pool)
+            .await?;
+        rows.into_iter().map(Self::try_from).collect()
+    }
+}
+
+pub use api::EventKind;
+
+/// 推给 `/events` 的一条事件
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Event {
+    pub id: i64,
+    pub kind: String,
+    pub item_id: String,
+    /// [`api::Item`] 的 json
+    pub item: String,
+    pub created_at: i64,
+}
+
+impl TryFrom<Event> for api::Event {
+    type Error = anyhow::Error;
+    fn try_from(e: Event) -> Result<Self> {
+        Ok(Self {
+            id: e.id,
+            kind: e.kind.parse()?,
+            created_at: from_timestamp(e.created_at),
+            item: serde_json::from_str(&e.item)?,
+        })
+    }
+}
+
+impl Event {
+    /// 记下一条事件，返回 id
+    pub async fn insert(
+        kind: EventKind,
+        item: &api::Item,
+        created_at: DateTime<Utc>,
+        pool: &Pool,
+    ) -> Result<i64> {
+        let r = sqlx::query(
+            r"
+            INSERT INTO `event`
+            (`kind`, `item_id`, `item`, `created_at`)
+            VALUES
+            (?, ?, ?, ?);
+            ",
+        )
+        .bind(kind.as_str())
+        .bind(&item.id)
+        .bind(serde_json::to_string(item)?)
+        .bind(created_at.timestamp())
+        .execute(&*pool)
+        .await?;
+        Ok(r.last_insert_rowid())
+    }
+
+    /// `id` 之后的事件，先发生的在前
+    pub async fn after(id: i64, limit: u32, pool: &Pool) -> Result<Vec<api::Event>> {
+        let events: Vec<Self> = sqlx::query_as(
+            r"
+            SELECT `id`, `kind`, `item_id`, `item`, `created_at`
+            FROM `event`
+            WHERE `id` > ?
+            ORDER BY `id`
+            LIMIT ?;
+            ",
+        )
+        .bind(id)
+        .bind(limit)
+        .fetch_all(&*pool)
+        .await?;
+        events.into_iter().map(api::Event::try_from).collect()
+    }
+
+    /// 最新的事件 id
+    pub async fn last_id(pool: &Pool) -> Result<Option<i64>> {
+        let id: Option<i64> = sqlx::query_scalar("SELECT MAX(`id`) FROM `event`;")
+            .fetch_one(&*pool)
+            .await?;
+        Ok(id)
+    }
+
+    /// 删掉 `before` 之前的事件，返回删了几条
+    pub async fn prune(before: DateTime<Utc>, pool: &Pool) -> Result<u64> {
+        let r = sqlx::query("DELETE FROM `event` WHERE `created_at` < ?;")
+            .bind(before.timestamp())
+            .execute(&*pool)
+            .await?;
+        Ok(r.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn _test_serde_item(t: DateTime<Utc>) {
+        let id = "1dkfjgndkfjg".to_string();
+        let mut item = Item::sample(&id, t);
+        item.duration = Some(75);
+        item.source_tags = vec!["A-SOUL".to_string(), "嘉然".to_string()];
+        item.stat.view = Some(100);
+        let pool = init("sqlite://:memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        item.insert(&pool).await.unwrap();
+        let item = Item::from_id(&id, &pool).await.unwrap().unwrap();
+
+        assert_eq!(item.kind, ItemKind::Dynamic);
+        assert_eq!(item.duration, Some(75));
+        assert_eq!(item.source_tags, vec!["A-SOUL", "嘉然"]);
+        assert_eq!(item.stat.view, Some(100));
+        assert_eq!(item.stat.reply, None);
+        // 时间戳精确到秒
+        assert_eq!(item.published_at.timestamp(), t.timestamp());
+        assert_eq!(item.marked_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_serde_item() {
+        _test_serde_item("2021-07-23 03:40:28Z".parse().unwrap()).await;
+        _test_serde_item(Utc::now()).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_kpi() -> Result<()> {
+        let pool = init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+        let id = "sdjkfneig".to_string();
+        Item::sample(&id, Utc::now()).insert(&pool).await?;
+        Item::set_category(&id, "category", "marker", &pool).await?;
+        let kpi = Item::get_kpi(Utc::now(), &pool).await?;
+        assert_eq!(kpi, vec![("marker".to_string(), 1)]);
+
+        let id = "kjtrnyx".to_string();
+        Item::sample(&id, Utc::now()).insert(&pool).await?;
         Item::set_category(&id, "category", "marker2", &pool).await?;
         let kpi = Item::get_kpi(Utc::now(), &pool).await?;
         assert_eq!(
@@ -380,4 +1545,325 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_legacy_item() -> Result<()> {
+        let pool = init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+        sqlx::query(
+            r"
+            INSERT INTO `item` (`id`, `kind`, `message_id`, `published_at`, `pushed_at`, `legacy_json`)
+            VALUES ('BV1xx411c7mD', 'video', 'om', 0, 0, '[]');
+            ",
+        )
+        .execute(&pool)
+        .await?;
+        let legacy = Item::legacy(&pool).await?;
+        assert_eq!(legacy.len(), 1);
+
+        let fields = LegacyFields {
+            title: "标题".to_string(),
+            uploader_name: Some("UP".to_string()),
+            duration: Some(61),
+            ..Default::default()
+        };
+        Item::fill_legacy("BV1xx411c7mD", fields, &pool).await?;
+        assert!(Item::legacy(&pool).await?.is_empty());
+        let item = Item::from_id("BV1xx411c7mD", &pool).await?.unwrap();
+        assert_eq!(item.title, "标题");
+        assert_eq!(item.uploader_name, "UP");
+        assert_eq!(item.duration, Some(61));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_image_cache() -> Result<()> {
+        let pool = init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+
+        assert_eq!(
+            ImageCache::get("https://example.com/a.jpg", &pool).await?,
+            None
+        );
+        ImageCache::insert("https://example.com/a.jpg", "img_v2_a", &pool).await?;
+        assert_eq!(
+            ImageCache::get("https://example.com/a.jpg", &pool).await?,
+            Some("img_v2_a".to_string())
+        );
+        // 重新上传之后覆盖
+        ImageCache::insert("https://example.com/a.jpg", "img_v2_b", &pool).await?;
+        assert_eq!(
+            ImageCache::get("https://example.com/a.jpg", &pool).await?,
+            Some("img_v2_b".to_string())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocked_user() -> Result<()> {
+        let pool = init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+
+        BlockedUser::insert(114514, "operator", &pool).await?;
+        BlockedUser::insert(114514, "operator", &pool).await?;
+        assert_eq!(
+            BlockedUser::all(&pool).await?,
+            vec![114514].into_iter().collect()
+        );
+        assert!(BlockedUser::remove(114514, &pool).await?);
+        assert!(!BlockedUser::remove(114514, &pool).await?);
+        assert!(BlockedUser::all(&pool).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_group_lifecycle() -> Result<()> {
+        let pool = init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+
+        assert!(Group::from_name("视频筛选 10-18", &pool).await?.is_none());
+        Group::upsert("视频筛选 10-18", "oc_a", &pool).await?;
+        let group = Group::from_name("视频筛选 10-18", &pool).await?.unwrap();
+        assert_eq!(group.state, GroupState::Active);
+
+        let later = Utc::now() + chrono::Duration::seconds(1);
+        let stale = Group::inactive_since(later, &pool).await?;
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].chat_id, "oc_a");
+
+        Group::set_state("视频筛选 10-18", GroupState::Dissolved, &pool).await?;
+        assert!(Group::inactive_since(later, &pool).await?.is_empty());
+        let group = Group::from_name("视频筛选 10-18", &pool).await?.unwrap();
+        assert_eq!(group.state, GroupState::Dissolved);
+
+        // 解散之后再用到同名的群，换成新的 chat_id
+        Group::upsert("视频筛选 10-18", "oc_b", &pool).await?;
+        let group = Group::from_name("视频筛选 10-18", &pool).await?.unwrap();
+        assert_eq!(group.state, GroupState::Active);
+        assert_eq!(group.chat_id, "oc_b");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_category() -> Result<()> {
+        let pool = init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+
+        let mut dynamic = Category {
+            name: "动态".to_string(),
+            kind: ItemKind::Dynamic,
+            sort_order: 100,
+            aliases: vec!["ok".to_string()],
+            active: true,
+            section_title: None,
+        };
+        let music = Category {
+            name: "音乐".to_string(),
+            kind: ItemKind::Video,
+            sort_order: 0,
+            aliases: vec![],
+            active: true,
+            section_title: Some("音乐区".to_string()),
+        };
+        dynamic.insert(&pool).await?;
+        music.insert(&pool).await?;
+        assert!(music.insert(&pool).await.is_err());
+        assert_eq!(
+            Category::all(&pool).await?,
+            vec![music.clone(), dynamic.clone()]
+        );
+
+        dynamic.active = false;
+        dynamic.aliases.push("图片".to_string());
+        dynamic.update(&pool).await?;
+        assert_eq!(Category::from_name("动态", &pool).await?, Some(dynamic));
+
+        assert!(Category::remove("动态", &pool).await?);
+        assert!(!Category::remove("动态", &pool).await?);
+        assert_eq!(Category::all(&pool).await?, vec![music]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_items() -> Result<()> {
+        let pool = init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+        let t: DateTime<Utc> = "2021-10-18T04:00:00Z".parse()?;
+        let titles = [
+            "【向晚】超好听的翻唱",
+            "嘉然今天吃什么",
+            "100%_纯手工",
+            "向晚的手书",
+            "珈乐的舞蹈",
+        ];
+        for (i, title) in titles.iter().enumerate() {
+            let mut item = Item::sample(&format!("BV{}", i), t + chrono::Duration::hours(i as i64));
+            item.title = title.to_string();
+            item.uploader_uid = Some(i as i64 % 2);
+            if i == 4 {
+                item.description = "原曲：向晚的歌".to_string();
+            }
+            item.insert(&pool).await?;
+        }
+        Item::set_category("BV0", "音乐", "a", &pool).await?;
+        Item::set_category("BV3", "ok", "b", &pool).await?;
+
+        let ids =
+            |page: &ItemPage| -> Vec<String> { page.items.iter().map(|i| i.id.clone()).collect() };
+        let list = |filter: ItemFilter| {
+            let pool = pool.clone();
+            async move { Item::list(&filter, ItemSort::default(), true, None, 10, &pool).await }
+        };
+
+        let page = list(ItemFilter::default()).await?;
+        assert_eq!(ids(&page), vec!["BV4", "BV3", "BV2", "BV1", "BV0"]);
+        assert_eq!(page.next_cursor, None);
+
+        let filter = ItemFilter {
+            categories: vec!["动态".to_string(), "ok".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(ids(&list(filter).await?), vec!["BV3"]);
+        let filter = ItemFilter {
+            uncategorized: true,
+            uploader_uid: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(ids(&list(filter).await?), vec!["BV4", "BV2"]);
+        let filter = ItemFilter {
+            marker: Some("a".to_string()),
+            published_to: Some(t + chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        assert_eq!(ids(&list(filter).await?), vec!["BV0"]);
+
+        // 全文索引，update 之后也能搜到
+        let q = |q: &str| ItemFilter {
+            q: Some(q.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&list(q("今天吃")).await?), vec!["BV1"]);
+        // 简介里的也能搜到，短的用 LIKE
+        assert_eq!(ids(&list(q("向晚")).await?), vec!["BV4", "BV3", "BV0"]);
+        assert_eq!(ids(&list(q("原曲")).await?), vec!["BV4"]);
+        assert_eq!(ids(&list(q("向晚的歌")).await?), vec!["BV4"]);
+        assert_eq!(ids(&list(q("%_")).await?), vec!["BV2"]);
+        assert_eq!(ids(&list(q("不存在的")).await?), Vec::<String>::new());
+        sqlx::query("UPDATE `item` SET `title` = '新标题来了' WHERE `id` = 'BV1'")
+            .execute(&pool)
+            .await?;
+        assert_eq!(ids(&list(q("今天吃")).await?), Vec::<String>::new());
+        assert_eq!(ids(&list(q("新标题")).await?), vec!["BV1"]);
+
+        // 翻页
+        let mut cursor = None;
+        let mut all = vec![];
+        loop {
+            let page = Item::list(
+                &ItemFilter::default(),
+                ItemSort::MarkedAt,
+                false,
+                cursor,
+                2,
+                &pool,
+            )
+            .await?;
+            all.extend(ids(&page));
+            match page.next_cursor {
+                Some(c) => cursor = Some(ItemCursor::parse(&c)?),
+                None => break,
+            }
+        }
+        assert_eq!(all.len(), 5);
+        assert_eq!(&all[3..], &["BV0", "BV3"]);
+        assert!(ItemCursor::parse("abc").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reject() -> Result<()> {
+        let pool = init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+        Item::sample("BV1", Utc::now()).insert(&pool).await?;
+
+        Item::set_category("BV1", "音乐", "a", &pool).await?;
+        Item::reject("BV1", "web:b", &pool).await?;
+        let item = Item::from_id("BV1", &pool).await?.unwrap();
+        assert_eq!(item.category, None);
+        assert_eq!(item.marker.as_deref(), Some("web:b"));
+        // 拒绝也算 KPI
+        assert_eq!(
+            Item::get_kpi(Utc::now(), &pool).await?,
+            vec![("web:b".to_string(), 1)]
+        );
+
+        Item::remove_category("BV1", &pool).await?;
+        let item = Item::from_id("BV1", &pool).await?.unwrap();
+        assert_eq!(item.marker, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_webhook_delivery() -> Result<()> {
+        let pool = init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+        let event = WebhookEvent::ItemCategorized;
+        let a = WebhookDelivery::enqueue("discord", event, "BV1", "{}", &pool).await?;
+        let b = WebhookDelivery::enqueue("stats", event, "BV1", "{}", &pool).await?;
+        let now = Utc::now();
+
+        let due = WebhookDelivery::due(now, 10, &pool).await?;
+        assert_eq!(due.iter().map(|d| d.id).collect::<Vec<_>>(), vec![a, b]);
+
+        WebhookDelivery::set_delivered(a, 204, &pool).await?;
+        let retry_at = now + chrono::Duration::minutes(1);
+        WebhookDelivery::set_failed(b, Some(500), "boom", Some(retry_at), &pool).await?;
+        assert!(WebhookDelivery::due(now, 10, &pool).await?.is_empty());
+        let due = WebhookDelivery::due(retry_at, 10, &pool).await?;
+        assert_eq!(due.len(), 1);
+        assert_eq!(
+            (due[0].attempts, due[0].last_status_code, due[0].status),
+            (1, Some(500), DeliveryStatus::Pending)
+        );
+
+        WebhookDelivery::set_failed(b, None, "timeout", None, &pool).await?;
+        assert!(WebhookDelivery::due(retry_at, 10, &pool).await?.is_empty());
+
+        let all = WebhookDelivery::list(None, None, None, 10, &pool).await?;
+        assert_eq!(all.iter().map(|d| d.id).collect::<Vec<_>>(), vec![b, a]);
+        let failed =
+            WebhookDelivery::list(None, Some(DeliveryStatus::Failed), None, 10, &pool).await?;
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].attempts, 2);
+        assert_eq!(failed[0].last_error.as_deref(), Some("timeout"));
+        let discord = WebhookDelivery::list(Some("discord"), None, None, 10, &pool).await?;
+        assert_eq!(discord[0].status, DeliveryStatus::Delivered);
+        assert!(WebhookDelivery::list(None, None, Some(a), 10, &pool)
+            .await?
+            .is_empty());
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_event() -> Result<()> {
+        let pool = init("sqlite://:memory:").await?;
+        sqlx::migrate!().run(&pool).await?;
+        let t: DateTime<Utc> = "2021-10-18T04:00:00Z".parse().unwrap();
+        let item: api::Item = Item::sample("BV1", t).into();
+        let a = Event::insert(EventKind::CandidateSent, &item, t, &pool).await?;
+        let later = t + chrono::Duration::days(1);
+        let b = Event::insert(EventKind::ItemRejected, &item, later, &pool).await?;
+
+        let events = Event::after(0, 10, &pool).await?;
+        assert_eq!(events.iter().map(|e| e.id).collect::<Vec<_>>(), vec![a, b]);
+        assert_eq!(events[0].kind, EventKind::CandidateSent);
+        assert_eq!(events[0].created_at, t);
+        assert_eq!(events[0].item.id, "BV1");
+        assert_eq!(Event::after(a, 10, &pool).await?.len(), 1);
+        assert_eq!(Event::after(0, 1, &pool).await?[0].id, a);
+
+        assert_eq!(Event::prune(later, &pool).await?, 1);
+        let events = Event::after(0, 10, &pool).await?;
+        assert_eq!(events.iter().map(|e| e.id).collect::<Vec<_>>(), vec![b]);
+        Ok(())
+    }
 }
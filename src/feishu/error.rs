@@ -0,0 +1,186 @@
+//! 飞书接口的错误类型
+use reqwest::StatusCode;
+use std::{fmt, time::Duration};
+
+/// 飞书的 tenant_access_token 不合法或已过期
+const TOKEN_INVALID_CODES: [i64; 3] = [99991661, 99991663, 99991668];
+/// 触发了飞书的频率限制
+const RATE_LIMIT_CODE: i64 = 99991400;
+
+#[derive(Debug)]
+pub enum FeishuError {
+    /// 飞书返回了非 0 的 code
+    Api {
+        code: i64,
+        msg: String,
+        /// 频率限制时飞书会告知多久之后可以重试
+        retry_after: Option<Duration>,
+    },
+    /// 5xx 或者 429，body 不一定是 json
+    Status {
+        status: StatusCode,
+        body: String,
+        retry_after: Option<Duration>,
+    },
+    /// 网络错误
+    Transport(reqwest::Error),
+    /// 返回的 body 无法解析
+    Decode(serde_json::Error),
+}
+
+impl FeishuError {
+    pub fn api(code: i64, msg: impl Into<String>) -> Self {
+        Self::Api {
+            code,
+            msg: msg.into(),
+            retry_after: None,
+        }
+    }
+
+    /// 飞书的错误码，不是飞书返回的错误时为 None
+    pub fn code(&self) -> Option<i64> {
+        match self {
+            Self::Api { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// token 失效，需要立刻刷新 token
+    pub fn is_token_invalid(&self) -> bool {
+        matches!(self.code(), Some(code) if TOKEN_INVALID_CODES.contains(&code))
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        match self {
+            Self::Api { code, .. } => *code == RATE_LIMIT_CODE,
+            Self::Status { status, .. } => *status == StatusCode::TOO_MANY_REQUESTS,
+            _ => false,
+        }
+    }
+
+    /// 指标里的错误类型
+    pub fn kind(&self) -> &'static str {
+        if self.is_rate_limited() {
+            return "rate_limited";
+        }
+        if self.is_token_invalid() {
+            return "token_invalid";
+        }
+        match self {
+            Self::Api { .. } => "api",
+            Self::Status { .. } => "status",
+            Self::Transport(_) => "transport",
+            Self::Decode(_) => "decode",
+        }
+    }
+
+    /// 是否是暂时性的错误，等一会儿重试可能会成功
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::Transport(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+            _ => self.is_rate_limited(),
+        }
+    }
+
+    /// 飞书肯定没有处理这个请求（频率限制、连接失败），不幂等的请求也可以重试
+    pub fn is_unprocessed(&self) -> bool {
+        match self {
+            Self::Transport(e) => e.is_connect(),
+            _ => self.is_rate_limited(),
+        }
+    }
+
+    /// 飞书建议的重试等待时间
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Api { retry_after, .. } | Self::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    pub(super) fn with_retry_after(mut self, after: Option<Duration>) -> Self {
+        if let Self::Api { retry_after, .. } = &mut self {
+            *retry_after = after;
+        }
+        self
+    }
+}
+
+impl fmt::Display for FeishuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Api { code, msg, .. } => write!(f, "Feishu error ({}) {}", code, msg),
+            Self::Status { status, body, .. } => {
+                write!(f, "Feishu HTTP error {}: {}", status, body)
+            }
+            Self::Transport(e) => write!(f, "Feishu request failed: {}", e),
+            Self::Decode(e) => write!(f, "Feishu response decode failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FeishuError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(e) => Some(e),
+            Self::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for FeishuError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Transport(e)
+    }
+}
+
+impl From<serde_json::Error> for FeishuError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Decode(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let e = FeishuError::api(99991663, "tenant access token invalid");
+        assert!(e.is_token_invalid());
+        assert_eq!(e.kind(), "token_invalid");
+        assert!(!e.is_transient());
+        assert_eq!(
+            e.to_string(),
+            "Feishu error (99991663) tenant access token invalid"
+        );
+
+        let e = FeishuError::api(99991400, "request trigger frequency limit")
+            .with_retry_after(Some(Duration::from_secs(3)));
+        assert!(e.is_rate_limited());
+        assert!(e.is_transient());
+        assert!(e.is_unprocessed());
+        assert_eq!(e.kind(), "rate_limited");
+        assert_eq!(e.retry_after(), Some(Duration::from_secs(3)));
+
+        let e = FeishuError::Status {
+            status: StatusCode::BAD_GATEWAY,
+            body: String::new(),
+            retry_after: None,
+        };
+        assert!(e.is_transient());
+        // 5xx 的时候飞书可能已经处理了
+        assert!(!e.is_unprocessed());
+        assert!(!e.is_token_invalid());
+        assert_eq!(e.code(), None);
+
+        let e = FeishuError::api(230002, "bot not in chat");
+        assert_eq!(e.kind(), "api");
+        assert!(!e.is_transient());
+        assert!(!e.is_token_invalid());
+    }
+}
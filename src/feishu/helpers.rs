@@ -1,4 +1,7 @@
 use anyhow::Result;
+use futures::{stream, Future, Stream, TryStreamExt};
+
+use super::FeishuError;
 
 #[derive(Debug, Deserialize)]
 pub struct FlatResponse<T> {
@@ -8,12 +11,10 @@ pub struct FlatResponse<T> {
     data: Option<T>,
 }
 impl<T> FlatResponse<T> {
-    pub fn ok(self) -> Result<T> {
+    pub fn ok(self) -> Result<T, FeishuError> {
         match (self.code, self.data) {
             (0, Some(data)) => Ok(data),
-            (code, _) => {
-                bail!("Feishu error ({}) {}", code, self.msg)
-            }
+            (code, _) => Err(FeishuError::api(code, self.msg)),
         }
     }
 }
@@ -25,18 +26,18 @@ pub struct DataResponse<T> {
     data: Option<T>,
 }
 impl<T> DataResponse<T> {
-    pub fn ok(self) -> Result<T> {
+    pub fn ok(self) -> Result<T, FeishuError> {
         match (self.code, self.data) {
             (0, Some(data)) => Ok(data),
-            (code, _) => {
-                bail!("Feishu error ({}) {}", code, self.msg)
-            }
+            (code, _) => Err(FeishuError::api(code, self.msg)),
         }
     }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Page<T> {
+    /// 没有数据的时候飞书会直接不返回这个字段
+    #[serde(default = "Vec::new")]
     pub items: Vec<T>,
     pub page_token: Option<String>,
     pub has_more: bool,
@@ -45,4 +46,98 @@ impl<T> Page<T> {
     pub fn into_inner(self) -> Vec<T> {
         self.items
     }
+
+    /// 下一页的 page_token，没有下一页时返回 None
+    fn next_page_token(&self) -> Option<String> {
+        match (self.has_more, &self.page_token) {
+            (true, Some(token)) if !token.is_empty() => Some(token.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// 把翻页接口展开成一个逐条返回的 stream
+///
+/// `fetch` 接收 page_token（第一页为 None），返回对应的一页，会一直请求到 `has_more = false`
+pub fn paginate<'a, T, F, Fut>(fetch: F) -> impl Stream<Item = Result<T>> + 'a
+where
+    T: 'a,
+    F: FnMut(Option<String>) -> Fut + 'a,
+    Fut: Future<Output = Result<Page<T>>> + 'a,
+{
+    // state: (fetch, 下一次请求的 page_token)，page_token 为 None 代表已经结束
+    let init = (fetch, Some(None));
+    stream::try_unfold(init, |(mut fetch, page_token)| async move {
+        let page_token = match page_token {
+            Some(page_token) => page_token,
+            None => return Ok::<_, anyhow::Error>(None),
+        };
+        let page = fetch(page_token).await?;
+        let next = page.next_page_token().map(Some);
+        let items = page.into_inner();
+        Ok(Some((
+            stream::iter(items.into_iter().map(Ok)),
+            (fetch, next),
+        )))
+    })
+    .try_flatten()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn page(items: Vec<u32>, page_token: Option<&str>, has_more: bool) -> Page<u32> {
+        Page {
+            items,
+            page_token: page_token.map(|s| s.to_string()),
+            has_more,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_paginate() -> Result<()> {
+        let mut requested = vec![];
+        let items: Vec<u32> = paginate(|page_token: Option<String>| {
+            requested.push(page_token.clone());
+            async move {
+                Ok(match page_token.as_deref() {
+                    None => page(vec![1, 2], Some("a"), true),
+                    Some("a") => page(vec![], Some("b"), true),
+                    Some("b") => page(vec![3], Some("c"), false),
+                    Some(t) => bail!("unexpected page token {}", t),
+                })
+            }
+        })
+        .try_collect()
+        .await?;
+        assert_eq!(items, vec![1, 2, 3]);
+        assert_eq!(
+            requested,
+            vec![None, Some("a".to_string()), Some("b".to_string())]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_paginate_stops_without_token() -> Result<()> {
+        let items: Vec<u32> = paginate(|_| async { Ok(page(vec![1], None, true)) })
+            .try_collect()
+            .await?;
+        assert_eq!(items, vec![1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_paginate_error() {
+        let r: Result<Vec<u32>> = paginate(|page_token: Option<String>| async move {
+            match page_token {
+                None => Ok(page(vec![1], Some("a"), true)),
+                Some(_) => bail!("boom"),
+            }
+        })
+        .try_collect()
+        .await;
+        assert!(r.is_err());
+    }
 }
@@ -1,8 +1,9 @@
-use std::{collections::HashSet, sync::Arc, time::Duration};
+use std::{collections::HashSet, time::Duration};
 
 use anyhow::{Context, Result};
-use parking_lot::RwLock;
-use reqwest::{Client, ClientBuilder};
+use futures::{Stream, TryStreamExt};
+use reqwest::{header::HeaderMap, Client, ClientBuilder, Request, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 
 mod token_manager;
@@ -11,7 +12,18 @@ pub use token_manager::TokenManager;
 mod helpers;
 pub use helpers::*;
 
+mod error;
+pub use error::FeishuError;
+
+use crate::metrics;
+
 const AUTHORIZATION: &str = "Authorization";
+const BASE_URL: &str = "https://open.feishu.cn/open-apis";
+
+/// 一个请求最多尝试的次数（包括第一次）
+const MAX_ATTEMPTS: u32 = 4;
+/// 重试前最多等待的时间
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Deserialize)]
 pub struct Group {
@@ -46,58 +58,191 @@ pub struct SentMessage {
     // pub chat_id: String,
 }
 
+/// 消息的接收者
+#[derive(Debug, Clone, PartialEq)]
+pub enum Receiver {
+    /// chat_id
+    Chat(String),
+    /// user_id，私聊
+    User(String),
+}
+impl Receiver {
+    fn id_type(&self) -> &'static str {
+        match self {
+            Receiver::Chat(_) => "chat_id",
+            Receiver::User(_) => "user_id",
+        }
+    }
+    pub fn id(&self) -> &str {
+        match self {
+            Receiver::Chat(id) | Receiver::User(id) => id,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct FeishuClient {
     pub client: Client,
-    token: Arc<RwLock<String>>,
+    token_manager: TokenManager,
+    /// 测试的时候换成 mock server
+    base_url: String,
 }
 impl FeishuClient {
-    pub fn new(token: Arc<RwLock<String>>) -> Self {
+    pub fn new(token_manager: TokenManager) -> Self {
+        Self::with_base_url(token_manager, BASE_URL)
+    }
+
+    pub fn with_base_url(token_manager: TokenManager, base_url: impl Into<String>) -> Self {
         Self {
             client: ClientBuilder::new()
                 .timeout(Duration::from_secs(2 * 60))
                 .build()
                 .unwrap(),
-            token,
+            token_manager,
+            base_url: base_url.into(),
         }
     }
-    fn token(&self) -> String {
-        format!("Bearer {}", self.token.read())
+
+    pub fn token_manager(&self) -> &TokenManager {
+        &self.token_manager
     }
-    #[allow(unused)]
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// 发送一次请求并解析飞书的返回，记下调用次数和错误
+    async fn request_once<T: DeserializeOwned>(
+        &self,
+        req: RequestBuilder,
+        token: &str,
+    ) -> Result<T, FeishuError> {
+        let req = req
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .build()?;
+        let api = api_name(req.url().path());
+        metrics::FEISHU_REQUESTS.with_label_values(&[&api]).inc();
+        let r = self.execute(req).await;
+        if let Err(e) = &r {
+            metrics::FEISHU_ERRORS
+                .with_label_values(&[&api, e.kind()])
+                .inc();
+        }
+        r
+    }
+
+    async fn execute<T: DeserializeOwned>(&self, req: Request) -> Result<T, FeishuError> {
+        let r = self.client.execute(req).await?;
+        let status = r.status();
+        let retry_after = retry_after(r.headers());
+        let body = r.bytes().await?;
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(FeishuError::Status {
+                status,
+                body: String::from_utf8_lossy(&body).into_owned(),
+                retry_after,
+            });
+        }
+        let r: DataResponse<T> = serde_json::from_slice(&body)?;
+        r.ok().map_err(|e| e.with_retry_after(retry_after))
+    }
+
+    /// 请求幂等的飞书接口，失败时会按错误类型重试。每次尝试都会调用 `build` 重新构造请求。
+    ///
+    /// - token 失效：立刻刷新 token 后重试
+    /// - 频率限制、5xx、网络错误：退避一段时间后重试
+    async fn request<T, F>(&self, build: F) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        self.request_with(true, build).await
+    }
+
+    /// 请求不幂等的接口（建群、拉人进群），只在飞书肯定没有处理的时候重试。
+    /// 5xx 和超时的时候请求可能已经生效了，直接返回错误
+    async fn request_non_idempotent<T, F>(&self, build: F) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        self.request_with(false, build).await
+    }
+
+    async fn request_with<T, F>(&self, idempotent: bool, build: F) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let mut attempt = 1;
+        loop {
+            let token = self.token_manager.current();
+            let e = match self.request_once(build(&self.client), &token).await {
+                Ok(data) => return Ok(data),
+                Err(e) => e,
+            };
+            if attempt >= MAX_ATTEMPTS {
+                return Err(e.into());
+            }
+            if e.is_token_invalid() {
+                self.token_manager
+                    .refresh_stale(&token)
+                    .await
+                    .context("刷新 token 失败")?;
+            } else if e.is_transient() && (idempotent || e.is_unprocessed()) {
+                let wait = e
+                    .retry_after()
+                    .unwrap_or_else(|| backoff(attempt))
+                    .min(MAX_RETRY_WAIT);
+                warn!("飞书请求失败，{:?} 后第 {} 次重试：{}", wait, attempt, e);
+                tokio::time::sleep(wait).await;
+            } else {
+                return Err(e.into());
+            }
+            attempt += 1;
+        }
+    }
+
+    /// 对翻页的 GET 接口，逐条返回所有页的数据
+    fn get_paged<'a, T>(
+        &'a self,
+        url: impl Into<String>,
+        query: Vec<(&'static str, String)>,
+    ) -> impl Stream<Item = Result<T>> + 'a
+    where
+        T: DeserializeOwned + 'a,
+    {
+        let url = url.into();
+        paginate(move |page_token| {
+            let mut query = query.clone();
+            if let Some(page_token) = page_token {
+                query.push(("page_token", page_token));
+            }
+            let url = url.clone();
+            async move { self.request(|c| c.get(&url).query(&query)).await }
+        })
+    }
+
     pub async fn get_groups(&self) -> Result<Vec<Group>> {
-        // NOTE: 这里没处理翻页
-        let url = "https://open.feishu.cn/open-apis/im/v1/chats?page_size=100";
-        let r = self
-            .client
-            .get(url)
-            .header(AUTHORIZATION, self.token())
-            .send()
-            .await?;
-        let groups: DataResponse<Page<Group>> = r.json().await?;
-        let groups = groups.ok()?;
-        Ok(groups.into_inner())
+        let url = self.url("/im/v1/chats");
+        self.get_paged(url, vec![("page_size", "100".to_string())])
+            .try_collect()
+            .await
     }
 
     pub async fn create_group(&self, name: &str) -> Result<Group> {
         info!("新建群组 {}", name);
-        let url = "https://open.feishu.cn/open-apis/im/v1/chats";
-        let r = self
-            .client
-            .post(url)
-            .header(AUTHORIZATION, self.token())
-            .json(&json!({
+        let url = self.url("/im/v1/chats");
+        self.request_non_idempotent(|c| {
+            c.post(&url).json(&json!({
                 "name": name,
                 "chat_mode": "group",
                 "chat_type": "public",
                 "membership_approval": "no_approval_required",
                 "add_member_permission": "all_members"
             }))
-            .send()
-            .await?;
-        let g: DataResponse<Group> = r.json().await?;
-        let g = g.ok()?;
-        Ok(g)
+        })
+        .await
     }
 
     #[allow(unused)]
@@ -114,38 +259,45 @@ impl FeishuClient {
 
     pub async fn add_user_to_group(&self, user_ids: &[String], chat_id: &str) -> Result<()> {
         debug!("adding users {:?} to chat {}", user_ids, chat_id);
-        let url = format!(
-            "https://open.feishu.cn/open-apis/im/v1/chats/{}/members",
-            chat_id
-        );
-        let r = self
-            .client
-            .post(url)
-            .header(AUTHORIZATION, self.token())
-            .query(&[("member_id_type", "user_id")])
-            .json(&json!({ "id_list": user_ids }))
-            .send()
+        let url = self.url(&format!("/im/v1/chats/{}/members", chat_id));
+        let _: Value = self
+            .request_non_idempotent(|c| {
+                c.post(&url)
+                    .query(&[("member_id_type", "user_id")])
+                    .json(&json!({ "id_list": user_ids }))
+            })
             .await?;
-        let r: DataResponse<Value> = r.json().await?;
-        r.ok()?;
         Ok(())
     }
 
-    pub async fn get_group_users(&self, chat_id: &str) -> Result<Vec<GroupUser>> {
-        let url = format!(
-            "https://open.feishu.cn/open-apis/im/v1/chats/{}/members",
-            chat_id
-        );
-        let r = self
-            .client
-            .get(url)
-            .header(AUTHORIZATION, self.token())
-            .query(&[("member_id_type", "user_id"), ("page_size", "100")])
-            .send()
+    pub async fn remove_users_from_group(&self, user_ids: &[String], chat_id: &str) -> Result<()> {
+        debug!("removing users {:?} from chat {}", user_ids, chat_id);
+        let url = self.url(&format!("/im/v1/chats/{}/members", chat_id));
+        let _: Value = self
+            .request(|c| {
+                c.delete(&url)
+                    .query(&[("member_id_type", "user_id")])
+                    .json(&json!({ "id_list": user_ids }))
+            })
             .await?;
-        let r: DataResponse<Page<GroupUser>> = r.json().await?;
-        let r = r.ok()?.into_inner();
-        Ok(r)
+        Ok(())
+    }
+
+    /// 解散群，只有群主（机器人自己建的群）才能解散
+    pub async fn dissolve_group(&self, chat_id: &str) -> Result<()> {
+        info!("解散群 {}", chat_id);
+        let url = self.url(&format!("/im/v1/chats/{}", chat_id));
+        let _: Value = self.request(|c| c.delete(&url)).await?;
+        Ok(())
+    }
+
+    pub async fn get_group_users(&self, chat_id: &str) -> Result<Vec<GroupUser>> {
+        let url = self.url(&format!("/im/v1/chats/{}/members", chat_id));
+        let query = vec![
+            ("member_id_type", "user_id".to_string()),
+            ("page_size", "100".to_string()),
+        ];
+        self.get_paged(url, query).try_collect().await
     }
 
     pub async fn ensure_users_in_group(&self, user_ids: Vec<String>, chat_id: &str) -> Result<()> {
@@ -167,73 +319,76 @@ impl FeishuClient {
 
     #[allow(unused)]
     pub async fn get_all_users(&self) -> Result<Vec<User>> {
-        let url = "https://open.feishu.cn/open-apis/contact/v3/users";
-        let r = self
-            .client
-            .get(url)
-            .header(AUTHORIZATION, self.token())
-            .query(&[("user_id_type", "user_id"), ("page_size", "100")])
-            .send()
-            .await?;
-        let r: DataResponse<Page<User>> = r.json().await?;
-        let r = r.ok()?.into_inner();
-        Ok(r)
+        let url = self.url("/contact/v3/users");
+        let query = vec![
+            ("user_id_type", "user_id".to_string()),
+            ("page_size", "100".to_string()),
+        ];
+        self.get_paged(url, query).try_collect().await
     }
 
     pub async fn send_card(&self, chat_id: &str, card: Value) -> Result<SentMessage> {
-        // let url = "https://open.feishu.cn/open-apis/im/v1/messages?receive_id_type=chat_id";
-        let url = "https://open.feishu.cn/open-apis/message/v4/send/";
-        let r = self
-            .client
-            .post(url)
-            .header(AUTHORIZATION, self.token())
-            .json(&json!({
-                // "receive_id": receiver,
-                "chat_id": chat_id,
-                "msg_type": "interactive",
-                "card": card,
-                "update_multi": true,
-            }))
-            .send()
-            .await?;
-        let r: DataResponse<SentMessage> = r.json().await?;
-        let r = r.ok()?;
-        Ok(r)
+        self.send_card_to(&Receiver::Chat(chat_id.to_string()), card)
+            .await
     }
 
-    /// 返回 img key
-    pub async fn upload_image_url(&self, url: &str) -> Result<String> {
-        // download to mem
-        let bytes = self.client.get(url).send().await?.bytes().await?;
-        debug!(
-            "image downloaded, size = {:.2} MiB",
-            bytes.len() as f64 / 1024. / 1024.
-        );
-        self.upload_image_bytes(bytes.to_vec()).await
+    /// 发送卡片，每次调用生成一个新的 uuid，请求失败重试的时候不会重复发送
+    pub async fn send_card_to(&self, receiver: &Receiver, card: Value) -> Result<SentMessage> {
+        let uuid = format!("{:032x}", rand::random::<u128>());
+        self.send_card_once(receiver, card, &uuid).await
+    }
+
+    /// 用新版的消息接口发送卡片，相同 `uuid` 的请求一小时内只会发出一条消息，
+    /// 重试的时候不会重复发送
+    pub async fn send_card_once(
+        &self,
+        receiver: &Receiver,
+        card: Value,
+        uuid: &str,
+    ) -> Result<SentMessage> {
+        let url = self.url("/im/v1/messages");
+        let content = card.to_string();
+        self.request(|c| {
+            c.post(&url)
+                .query(&[("receive_id_type", receiver.id_type())])
+                .json(&json!({
+                    "receive_id": receiver.id(),
+                    "msg_type": "interactive",
+                    "content": content,
+                    "uuid": uuid,
+                }))
+        })
+        .await
+    }
+
+    /// 更新已经发出去的卡片，发送时需要 `update_multi`
+    pub async fn patch_card(&self, message_id: &str, card: Value) -> Result<()> {
+        let url = self.url(&format!("/im/v1/messages/{}", message_id));
+        let content = card.to_string();
+        let _: Value = self
+            .request(|c| c.patch(&url).json(&json!({ "content": content })))
+            .await?;
+        Ok(())
     }
 
     /// 返回 img key
     pub async fn upload_image_bytes(&self, bytes: Vec<u8>) -> Result<String> {
         use reqwest::multipart;
-        let form = multipart::Form::new()
-            .text("image_type", "message")
-            .part("image", multipart::Part::bytes(bytes));
-
-        let url = "https://open.feishu.cn/open-apis/im/v1/images";
-        let r = self
-            .client
-            .post(url)
-            .header(AUTHORIZATION, self.token())
-            .multipart(form)
-            .send()
-            .await?;
+        let url = self.url("/im/v1/images");
 
         #[derive(Debug, Deserialize)]
         struct R {
             image_key: String,
         }
-        let r: DataResponse<R> = r.json().await?;
-        let r = r.ok().context("upload image to feishu failed.")?;
+        let r: R = self
+            .request(|c| {
+                let form = multipart::Form::new()
+                    .text("image_type", "message")
+                    .part("image", multipart::Part::bytes(bytes.clone()));
+                c.post(&url).multipart(form)
+            })
+            .await
+            .context("upload image to feishu failed.")?;
         debug!("image {} uploaded, img key = {}", url, r.image_key);
         Ok(r.image_key)
     }
@@ -241,11 +396,14 @@ impl FeishuClient {
     #[allow(unused)]
     pub async fn update_card(&self, data: Value) -> Result<()> {
         info!("calling update card API");
-        let url = "https://open.feishu.cn/open-apis/interactive/v1/card/update";
+        let url = self.url("/interactive/v1/card/update");
         let r = self
             .client
-            .post(url)
-            .header(AUTHORIZATION, self.token())
+            .post(&url)
+            .header(
+                AUTHORIZATION,
+                format!("Bearer {}", self.token_manager.current()),
+            )
             .json(&data)
             .send()
             .await?
@@ -258,22 +416,184 @@ impl FeishuClient {
     /// https://open.feishu.cn/open-apis/contact/v3/users
     pub async fn get_users_in_tenant(&self) -> Result<Vec<User>> {
         info!("getting users in tenant");
-        let url = "https://open.feishu.cn/open-apis/contact/v3/users";
-        let rsp: DataResponse<Page<User>> = self
-            .client
-            .get(url)
-            .query(&[
-                ("department_id", "0"),
-                ("user_id_type", "user_id"),
-                ("page_size", "50"),
-            ])
-            .header(AUTHORIZATION, self.token())
-            .send()
-            .await?
-            .json()
-            .await?;
-        let users = rsp.ok()?.into_inner();
+        let url = self.url("/contact/v3/users");
+        let query = vec![
+            ("department_id", "0".to_string()),
+            ("user_id_type", "user_id".to_string()),
+            ("page_size", "50".to_string()),
+        ];
+        let users: Vec<User> = self.get_paged(url, query).try_collect().await?;
+        info!("{} users in tenant", users.len());
 
         Ok(users)
     }
 }
+
+/// 飞书限频时会返回 x-ogw-ratelimit-reset，其他情况可能会有标准的 Retry-After
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    ["x-ogw-ratelimit-reset", "retry-after"]
+        .iter()
+        .filter_map(|name| headers.get(*name)?.to_str().ok()?.trim().parse().ok())
+        .map(Duration::from_secs)
+        .next()
+}
+
+/// 指标里的接口名，去掉前缀，群和消息的 id 换成占位符
+fn api_name(path: &str) -> String {
+    let path = path.trim_start_matches("/open-apis");
+    path.split('/')
+        .map(|segment| match segment.get(..3) {
+            Some("oc_") | Some("om_") | Some("ou_") => ":id",
+            _ => segment,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// 第 n 次重试前的等待时间：1s, 2s, 4s...
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(500 << attempt.min(6))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn setup() -> (MockServer, FeishuClient) {
+        let server = MockServer::start().await;
+        let base_url = format!("{}/open-apis", server.uri());
+        let client = FeishuClient::with_base_url(
+            TokenManager::with_token_and_base_url("t-old", base_url.clone()),
+            base_url,
+        );
+        (server, client)
+    }
+
+    fn sent() -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "code": 0,
+            "msg": "success",
+            "data": { "message_id": "om_1" }
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_retry_server_error() {
+        let (server, client) = setup().await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .respond_with(sent())
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let message = client.send_card("oc_1", json!({})).await.unwrap();
+        assert_eq!(message.message_id, "om_1");
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        let (server, client) = setup().await;
+        // 第一次重试默认只等 1s，等够 Retry-After 才说明用了飞书给的时间
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "2"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .respond_with(sent())
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let start = Instant::now();
+        client.send_card("oc_1", json!({})).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_invalid_token() {
+        let (server, client) = setup().await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .and(header("Authorization", "Bearer t-old"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 99991663,
+                "msg": "tenant access token invalid"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/auth/v3/tenant_access_token/internal/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 0,
+                "msg": "ok",
+                "tenant_access_token": "t-new",
+                "expire": 7200
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/messages"))
+            .and(header("Authorization", "Bearer t-new"))
+            .respond_with(sent())
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client.send_card("oc_1", json!({})).await.unwrap();
+        assert_eq!(client.token_manager().current(), "t-new");
+    }
+
+    #[tokio::test]
+    async fn test_non_idempotent_no_retry() {
+        let (server, client) = setup().await;
+        // 5xx 的时候群可能已经建好了、人可能已经拉进去了，不能重试
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/chats"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/open-apis/im/v1/chats/oc_1/members"))
+            .respond_with(ResponseTemplate::new(502))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert!(client.create_group("group").await.is_err());
+        assert!(client
+            .add_user_to_group(&["u1".to_string()], "oc_1")
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_api_name() {
+        assert_eq!(api_name("/open-apis/im/v1/chats"), "/im/v1/chats");
+        assert_eq!(
+            api_name("/open-apis/im/v1/chats/oc_a0553eda9014c201e6969b478895c230/members"),
+            "/im/v1/chats/:id/members"
+        );
+        assert_eq!(
+            api_name("/im/v1/messages/om_dc13264520392913993dd051dba21dcf"),
+            "/im/v1/messages/:id"
+        );
+    }
+}
@@ -1,22 +1,33 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use parking_lot::RwLock;
 use reqwest::Client;
 use std::{sync::Arc, time::Duration};
+use tokio::sync::Mutex;
 
-use super::FlatResponse;
+use super::{FlatResponse, BASE_URL};
+use crate::supervisor::Task;
 
+/// 管理 tenant_access_token，clone 出来的实例共享同一个 token
+#[derive(Clone)]
 pub struct TokenManager {
     app_id: String,
     app_secret: String,
     client: Client,
+    /// 测试的时候换成 mock server
+    base_url: String,
     token: Arc<RwLock<String>>,
+    /// 当前 token 的过期时间，还没有获取到时为空
+    expires_at: Arc<RwLock<Option<DateTime<Utc>>>>,
+    /// 保证同一时间只有一个刷新请求
+    refresh_lock: Arc<Mutex<()>>,
 }
 impl TokenManager {
     async fn force_refresh_token(&self) -> Result<()> {
-        const URL: &str = "https://open.feishu.cn/open-apis/auth/v3/tenant_access_token/internal/";
+        let url = format!("{}/auth/v3/tenant_access_token/internal/", self.base_url);
         let r = self
             .client
-            .post(URL)
+            .post(&url)
             .json(&json!({
                 "app_id": self.app_id,
                 "app_secret": self.app_secret
@@ -26,6 +37,8 @@ impl TokenManager {
         #[derive(Debug, Deserialize)]
         struct Response {
             tenant_access_token: String,
+            /// 有效期，秒
+            expire: i64,
         }
         let data: FlatResponse<Response> = r.json().await?;
         let r = data.ok()?;
@@ -39,6 +52,7 @@ impl TokenManager {
             );
             *self.token.write() = r.tenant_access_token;
         }
+        *self.expires_at.write() = Some(Utc::now() + chrono::Duration::seconds(r.expire));
         Ok(())
     }
     pub async fn new(app_id: impl Into<String>, app_secret: impl Into<String>) -> Result<Self> {
@@ -46,25 +60,67 @@ impl TokenManager {
             app_id: app_id.into(),
             app_secret: app_secret.into(),
             client: Client::new(),
+            base_url: BASE_URL.to_string(),
             token: Default::default(),
+            expires_at: Default::default(),
+            refresh_lock: Default::default(),
         };
         info!("initiate access token");
         this.force_refresh_token().await?;
         Ok(this)
     }
-    pub async fn auto_refresh(self) -> ! {
+    /// 测试用，固定的 token，不会去飞书获取
+    #[cfg(test)]
+    pub fn with_token(token: impl Into<String>) -> Self {
+        Self::with_token_and_base_url(token, BASE_URL)
+    }
+    /// 测试用，token 失效时到 `base_url` 刷新
+    #[cfg(test)]
+    pub fn with_token_and_base_url(token: impl Into<String>, base_url: impl Into<String>) -> Self {
+        TokenManager {
+            app_id: Default::default(),
+            app_secret: Default::default(),
+            client: Client::new(),
+            base_url: base_url.into(),
+            token: Arc::new(RwLock::new(token.into())),
+            expires_at: Default::default(),
+            refresh_lock: Default::default(),
+        }
+    }
+    pub async fn auto_refresh(self, task: Task) -> ! {
         // 十分钟刷新一次
         let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
 
         loop {
             interval.tick().await;
-            if let Err(e) = self.force_refresh_token().await {
-                // 忽略错误
-                error!("Failed to refresh token: {:?}", e);
+            let _guard = self.refresh_lock.lock().await;
+            match self.force_refresh_token().await {
+                Ok(()) => task.succeeded(),
+                Err(e) => {
+                    // 忽略错误
+                    error!("Failed to refresh token: {:?}", e);
+                    task.failed(&e);
+                }
             }
         }
     }
-    pub fn token(&self) -> Arc<RwLock<String>> {
-        self.token.clone()
+    /// 飞书返回 token 失效时调用，`stale` 是失效的 token。
+    ///
+    /// 多个请求同时失败时只会刷新一次，后面的请求会发现 token 已经变了，直接返回
+    pub async fn refresh_stale(&self, stale: &str) -> Result<()> {
+        let _guard = self.refresh_lock.lock().await;
+        if self.token.read().as_str() != stale {
+            debug!("token already refreshed by others.");
+            return Ok(());
+        }
+        warn!("access token invalid, force refresh now");
+        self.force_refresh_token().await
+    }
+    /// 当前的 token
+    pub fn current(&self) -> String {
+        self.token.read().clone()
+    }
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        *self.expires_at.read()
     }
 }
@@ -0,0 +1,119 @@
+use super::error::*;
+use crate::config::CONFIG;
+use actix_web::HttpRequest;
+use std::collections::HashMap;
+
+/// 用 admin_token 登录时记录的名字
+const ADMIN: &str = "admin";
+
+fn bearer(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// 校验 `Authorization: Bearer <admin_token>`
+fn authorize(req: &HttpRequest, expected: Option<&str>) -> Result<()> {
+    let expected = expected
+        .ok_or_else(|| Error::Unauthorized("没有配置 admin_token，接口不可用".to_string()))?;
+    if bearer(req) != Some(expected) {
+        return Err(Error::Unauthorized("admin token 不正确".to_string()));
+    }
+    Ok(())
+}
+
+/// 校验 token，返回对应的名字，admin_token 对应 `admin`
+fn identify(
+    token: Option<&str>,
+    admin_token: Option<&str>,
+    reviewers: &HashMap<String, String>,
+) -> Result<String> {
+    if admin_token.is_none() && reviewers.is_empty() {
+        return Err(Error::Unauthorized(
+            "没有配置 admin_token 和 web_reviewers，接口不可用".to_string(),
+        ));
+    }
+    let token = token.ok_or_else(|| Error::Unauthorized("缺少 token".to_string()))?;
+    if admin_token == Some(token) {
+        return Ok(ADMIN.to_string());
+    }
+    reviewers
+        .iter()
+        .find(|(_, t)| t.as_str() == token)
+        .map(|(name, _)| name.clone())
+        .ok_or_else(|| Error::Unauthorized("token 不正确".to_string()))
+}
+
+/// 只允许 admin_token，控制接口和分类的修改用
+pub fn admin(req: &HttpRequest) -> Result<()> {
+    authorize(req, CONFIG.admin_token.as_deref())
+}
+
+/// 允许 admin_token 和 `web_reviewers` 里的 token，返回标记时记录的名字
+pub fn reviewer(req: &HttpRequest) -> Result<String> {
+    identify(
+        bearer(req),
+        CONFIG.admin_token.as_deref(),
+        &CONFIG.web_reviewers,
+    )
+}
+
+/// 和 [`reviewer`] 一样，没有 Bearer token 时用参数里的，给不能设置请求头的 `EventSource` 用
+pub fn reviewer_or_query(req: &HttpRequest, token: Option<&str>) -> Result<String> {
+    identify(
+        bearer(req).or(token),
+        CONFIG.admin_token.as_deref(),
+        &CONFIG.web_reviewers,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn with_token(token: &str) -> HttpRequest {
+        TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_http_request()
+    }
+
+    #[test]
+    fn test_authorize() {
+        let req = with_token("secret");
+        assert!(authorize(&req, Some("secret")).is_ok());
+        assert!(authorize(&req, Some("other")).is_err());
+        assert!(authorize(&req, None).is_err());
+        let req = TestRequest::default().to_http_request();
+        assert!(authorize(&req, Some("secret")).is_err());
+    }
+
+    #[test]
+    fn test_identify() {
+        let reviewers: HashMap<_, _> = vec![("阿草".to_string(), "t1".to_string())]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            identify(bearer(&with_token("secret")), Some("secret"), &reviewers).unwrap(),
+            "admin"
+        );
+        assert_eq!(
+            identify(bearer(&with_token("t1")), Some("secret"), &reviewers).unwrap(),
+            "阿草"
+        );
+        assert_eq!(
+            identify(bearer(&with_token("t1")), None, &reviewers).unwrap(),
+            "阿草"
+        );
+        assert!(identify(bearer(&with_token("t2")), Some("secret"), &reviewers).is_err());
+        assert!(identify(bearer(&with_token("t1")), None, &HashMap::new()).is_err());
+        let req = TestRequest::default().to_http_request();
+        assert!(identify(bearer(&req), Some("secret"), &reviewers).is_err());
+        assert_eq!(
+            identify(bearer(&req).or(Some("t1")), None, &reviewers).unwrap(),
+            "阿草"
+        );
+    }
+}
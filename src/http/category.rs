@@ -1,10 +1,10 @@
-use super::error::*;
-use crate::db;
+use super::{auth, error::*};
+use crate::{biz, db};
 use actix_web::{
     delete, get, patch, post,
     web::{self, Json},
+    HttpRequest,
 };
-use anyhow::anyhow;
 use log::*;
 use serde::{Deserialize, Serialize};
 
@@ -13,31 +13,62 @@ struct Category {
     category: String,
 }
 
+async fn publish(kind: db::EventKind, id: &str, pool: &db::Pool) -> Result<()> {
+    if let Some(item) = db::Item::from_id(id, pool).await? {
+        biz::events::publish(kind, &item, pool).await;
+    }
+    Ok(())
+}
+
+/// 只接受启用的分类，可以用旧的名字
+fn resolve(category: String) -> Result<String> {
+    biz::category::resolve_active(&category)
+        .ok_or_else(|| Error::BadRequest(format!("不存在的分类：{}", category)))
+}
+
 #[post("/items/{id}/category")]
 async fn post_category(
+    req: HttpRequest,
     id: web::Path<(String,)>,
     data: web::Json<Category>,
     db: web::Data<db::Pool>,
 ) -> Result<Json<impl Serialize>> {
+    let reviewer = auth::reviewer(&req)?;
     let id = id.into_inner().0;
     info!("id = {}", id);
-    let category = data.into_inner().category;
+    let category = resolve(data.into_inner().category)?;
     info!("set category = {}", category);
     if db::Item::from_id(&id, &db).await?.is_some() {
-        return Err(Error(anyhow!("数据库已经存在 id 为 {} 的条目", id)));
+        return Err(Error::Conflict(format!(
+            "数据库已经存在 id 为 {} 的条目",
+            id
+        )));
     }
-    // create time 猜一个当天
-    let create_time = chrono::Utc::now();
+    // 发布时间猜一个当天
+    let now = chrono::Utc::now();
     let item = db::Item {
         id: id.clone(),
-        json: "".to_string(),
+        kind: db::ItemKind::guess(&id),
+        title: String::new(),
+        description: String::new(),
+        uploader_uid: None,
+        uploader_name: String::new(),
+        cover_url: String::new(),
+        image_key: String::new(),
+        duration: None,
+        picture_count: None,
+        source_tags: vec![],
+        stat: Default::default(),
         message_id: "".to_string(),
-        create_time,
+        published_at: now,
+        pushed_at: now,
         category: Some(category.clone()),
-        author: "unknown".to_string(),
+        marker: None,
+        marked_at: None,
     };
     item.insert(&db).await?;
-    db::Item::set_category(&id, &category, "HTTP API", &db).await?;
+    db::Item::set_category(&id, &category, &format!("web:{}", reviewer), &db).await?;
+    publish(db::EventKind::ItemCategorized, &id, &db).await?;
     Ok(Json(json!({
         "msg": "ok"
     })))
@@ -54,24 +85,27 @@ async fn get_category(
         Some(item) => Ok(Json(json!({
             "category": item.category
         }))),
-        None => Err(anyhow!("数据库不存在该条目").into()),
+        None => Err(Error::NotFound(format!("数据库不存在 {} 的条目", id))),
     }
 }
 
 #[patch("/items/{id}/category")]
 async fn patch_category(
+    req: HttpRequest,
     id: web::Path<(String,)>,
     data: web::Json<Category>,
     db: web::Data<db::Pool>,
 ) -> Result<Json<impl Serialize>> {
+    let reviewer = auth::reviewer(&req)?;
     let id = id.into_inner().0;
     info!("id = {}", id);
-    let category = data.into_inner().category;
+    let category = resolve(data.into_inner().category)?;
     info!("set category = {}", category);
     if db::Item::from_id(&id, &db).await?.is_none() {
-        return Err(Error(anyhow!("数据库不存在 {} 的条目", id)));
+        return Err(Error::NotFound(format!("数据库不存在 {} 的条目", id)));
     }
-    db::Item::set_category(&id, &category, "HTTP API", &db).await?;
+    db::Item::set_category(&id, &category, &format!("web:{}", reviewer), &db).await?;
+    publish(db::EventKind::ItemCategorized, &id, &db).await?;
     Ok(Json(json!({
         "msg": "ok"
     })))
@@ -79,15 +113,87 @@ async fn patch_category(
 
 #[delete("/items/{id}/category")]
 async fn remove_category(
+    req: HttpRequest,
     id: web::Path<(String,)>,
     db: web::Data<db::Pool>,
 ) -> Result<Json<impl Serialize>> {
+    auth::reviewer(&req)?;
     let id = id.into_inner().0;
     info!("id = {}, remove category", id);
     if db::Item::from_id(&id, &db).await?.is_none() {
-        return Err(Error(anyhow!("数据库不存在 {} 的条目", id)));
+        return Err(Error::NotFound(format!("数据库不存在 {} 的条目", id)));
     }
     db::Item::remove_category(&id, &db).await?;
+    publish(db::EventKind::ItemReset, &id, &db).await?;
+    Ok(Json(json!({
+        "msg": "ok"
+    })))
+}
+
+/// 和 `/summary` 一样不用 token，gen-article 生成专栏时要用
+#[get("/categories")]
+async fn list_categories(db: web::Data<db::Pool>) -> Result<Json<Vec<api::Category>>> {
+    let categories = db::Category::all(&db).await?;
+    Ok(Json(categories.into_iter().map(Into::into).collect()))
+}
+
+/// 分类修改不合法时返回对应的 4xx，其他错误照常处理
+fn category_error(e: anyhow::Error) -> Error {
+    use biz::category::CategoryError;
+    match e.downcast_ref::<CategoryError>() {
+        Some(CategoryError::EmptyName) => Error::bad_request(e),
+        Some(CategoryError::NotFound(_)) => Error::NotFound(e.to_string()),
+        Some(CategoryError::Exists(_)) | Some(CategoryError::Duplicate { .. }) => {
+            Error::conflict(e)
+        }
+        None => e.into(),
+    }
+}
+
+#[post("/categories")]
+async fn create_category(
+    req: HttpRequest,
+    data: web::Json<api::NewCategory>,
+    db: web::Data<db::Pool>,
+) -> Result<Json<api::Category>> {
+    auth::admin(&req)?;
+    let data = data.into_inner();
+    info!("create category {:?}", data);
+    let category = biz::category::create(data, &db)
+        .await
+        .map_err(category_error)?;
+    Ok(Json(category.into()))
+}
+
+#[patch("/categories/{name}")]
+async fn update_category_definition(
+    req: HttpRequest,
+    name: web::Path<(String,)>,
+    data: web::Json<api::CategoryPatch>,
+    db: web::Data<db::Pool>,
+) -> Result<Json<api::Category>> {
+    auth::admin(&req)?;
+    let name = name.into_inner().0;
+    let data = data.into_inner();
+    info!("patch category {}: {:?}", name, data);
+    let category = biz::category::update(&name, data, &db)
+        .await
+        .map_err(category_error)?;
+    Ok(Json(category.into()))
+}
+
+#[delete("/categories/{name}")]
+async fn delete_category_definition(
+    req: HttpRequest,
+    name: web::Path<(String,)>,
+    db: web::Data<db::Pool>,
+) -> Result<Json<impl Serialize>> {
+    auth::admin(&req)?;
+    let name = name.into_inner().0;
+    info!("delete category {}", name);
+    if !biz::category::remove(&name, &db).await? {
+        return Err(Error::NotFound(format!("不存在分类 {}", name)));
+    }
     Ok(Json(json!({
         "msg": "ok"
     })))
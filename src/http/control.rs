@@ -0,0 +1,59 @@
+use super::{auth::admin, error::*};
+use crate::{biz, db::ItemKind};
+use actix_web::{
+    get, post, put,
+    web::{self, Json},
+    HttpRequest,
+};
+use std::str::FromStr;
+
+fn parse_source(s: &str) -> Result<ItemKind> {
+    ItemKind::from_str(s).map_err(|_| Error::NotFound(format!("不存在的来源：{}", s)))
+}
+
+#[get("/control")]
+async fn get_control(req: HttpRequest) -> Result<Json<api::ControlState>> {
+    admin(&req)?;
+    Ok(Json(biz::control::state()))
+}
+
+/// 全部暂停或者恢复
+#[put("/control")]
+async fn put_control(
+    req: HttpRequest,
+    data: Json<api::PauseRequest>,
+) -> Result<Json<api::ControlState>> {
+    admin(&req)?;
+    biz::control::set_paused(data.paused);
+    Ok(Json(biz::control::state()))
+}
+
+#[put("/control/{source}")]
+async fn put_source(
+    req: HttpRequest,
+    source: web::Path<(String,)>,
+    data: Json<api::PauseRequest>,
+) -> Result<Json<api::ControlState>> {
+    admin(&req)?;
+    let source = parse_source(&source.into_inner().0)?;
+    biz::control::set_source_paused(source, data.paused);
+    Ok(Json(biz::control::state()))
+}
+
+/// 马上拉取一轮，暂停的时候不拉
+#[post("/control/{source}/run")]
+async fn run_source(
+    req: HttpRequest,
+    source: web::Path<(String,)>,
+) -> Result<Json<api::ControlState>> {
+    admin(&req)?;
+    let source = parse_source(&source.into_inner().0)?;
+    if biz::control::is_source_paused(source) {
+        return Err(Error::conflict(format!(
+            "{} 已暂停，先恢复再拉取",
+            source.as_str()
+        )));
+    }
+    biz::control::run_now(source);
+    Ok(Json(biz::control::state()))
+}
@@ -0,0 +1,26 @@
+//! 筛选网页，静态文件编译进二进制里
+use actix_web::{get, http::header, web, HttpResponse, Responder};
+
+const INDEX_HTML: &str = include_str!("../../assets/dashboard/index.html");
+const APP_JS: &str = include_str!("../../assets/dashboard/app.js");
+const STYLE_CSS: &str = include_str!("../../assets/dashboard/style.css");
+
+#[get("/dashboard")]
+async fn index() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(INDEX_HTML)
+}
+
+#[get("/dashboard/{file}")]
+async fn asset(file: web::Path<(String,)>) -> impl Responder {
+    let (content_type, body) = match file.into_inner().0.as_str() {
+        "app.js" => ("application/javascript; charset=utf-8", APP_JS),
+        "style.css" => ("text/css; charset=utf-8", STYLE_CSS),
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .body(body)
+}
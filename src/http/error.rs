@@ -1,34 +1,118 @@
-use actix_web::{HttpResponseBuilder, ResponseError};
+use crate::feishu::FeishuError;
+use actix_web::{http::StatusCode, HttpResponseBuilder, ResponseError};
 
-pub struct Error(pub anyhow::Error);
+/// 接口返回的错误，body 里的 `code` 给调用方判断用，不会变
+pub enum Error {
+    /// 参数不对
+    BadRequest(String),
+    Unauthorized(String),
+    NotFound(String),
+    /// 和已有的数据冲突
+    Conflict(String),
+    /// 飞书等上游接口出错
+    Upstream(anyhow::Error),
+    Internal(anyhow::Error),
+}
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+impl Error {
+    pub fn bad_request(e: impl std::fmt::Display) -> Self {
+        Self::BadRequest(e.to_string())
+    }
+
+    pub fn conflict(e: impl std::fmt::Display) -> Self {
+        Self::Conflict(e.to_string())
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::Upstream(_) => "upstream_error",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        match self {
+            Self::BadRequest(msg)
+            | Self::Unauthorized(msg)
+            | Self::NotFound(msg)
+            | Self::Conflict(msg) => msg.fmt(f),
+            Self::Upstream(e) | Self::Internal(e) => e.fmt(f),
+        }
     }
 }
 
 impl std::fmt::Debug for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        match self {
+            Self::Upstream(e) | Self::Internal(e) => write!(f, "{}: {:?}", self.code(), e),
+            _ => write!(f, "{}: {}", self.code(), self),
+        }
     }
 }
 
+/// 飞书接口的错误算作上游错误，其他的都是内部错误
 impl From<anyhow::Error> for Error {
     fn from(e: anyhow::Error) -> Self {
-        Self(e)
+        if e.downcast_ref::<FeishuError>().is_some() {
+            Self::Upstream(e)
+        } else {
+            Self::Internal(e)
+        }
     }
 }
 
 impl ResponseError for Error {
-    fn status_code(&self) -> reqwest::StatusCode {
-        reqwest::StatusCode::INTERNAL_SERVER_ERROR
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
     fn error_response(&self) -> actix_web::HttpResponse {
-        HttpResponseBuilder::new(self.status_code()).json(json!({
-            "error": format!("{}", self.0),
-            "detail": format!("{:?}", self.0)
-        }))
+        if self.status_code().is_server_error() {
+            error!("接口出错：{:?}", self);
+        }
+        HttpResponseBuilder::new(self.status_code()).json(api::ErrorBody {
+            code: self.code().to_string(),
+            error: self.to_string(),
+            // 完整的错误链只在开发环境返回
+            detail: std::option_env!("DEV").map(|_| format!("{:?}", self)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_code() {
+        let e = Error::NotFound("不存在分类 音乐".to_string());
+        assert_eq!(e.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(e.code(), "not_found");
+        assert_eq!(e.to_string(), "不存在分类 音乐");
+
+        let e = Error::conflict(anyhow!("分类 音乐 已经存在"));
+        assert_eq!(e.status_code(), StatusCode::CONFLICT);
+
+        let e: Error = anyhow::Error::from(FeishuError::api(230002, "bot not in chat"))
+            .context("发送卡片失败")
+            .into();
+        assert_eq!(e.code(), "upstream_error");
+        assert_eq!(e.status_code(), StatusCode::BAD_GATEWAY);
+
+        let e: Error = anyhow!("db locked").into();
+        assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
     }
 }
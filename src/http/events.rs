@@ -0,0 +1,116 @@
+use super::{auth, error::*};
+use crate::{biz, biz::events::Replay, db};
+use actix_web::{
+    get,
+    http::header::CACHE_CONTROL,
+    web::{self, Bytes},
+    HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// 多久没有事件就发一次注释，防止代理断开连接
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    /// 和 `Last-Event-ID` 一样，给不能设置请求头的客户端用
+    last_event_id: Option<i64>,
+    /// 和 `Authorization: Bearer` 一样，`EventSource` 不能设置请求头
+    token: Option<String>,
+}
+
+fn format_event(event: &api::Event) -> Bytes {
+    let data = serde_json::to_string(event).expect("事件一定能序列化");
+    Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event.id,
+        event.kind.as_str(),
+        data
+    ))
+}
+
+/// 告诉客户端补不全，要重新拉取
+fn format_reset(reset: &api::EventReset) -> Bytes {
+    let data = serde_json::to_string(reset).expect("事件一定能序列化");
+    Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        reset.last_event_id,
+        api::EventReset::KIND,
+        data
+    ))
+}
+
+struct State {
+    /// 重连时从数据库补的
+    backlog: VecDeque<Bytes>,
+    rx: broadcast::Receiver<api::Event>,
+    /// 补过的最大 id，之后广播里收到的旧事件跳过
+    replayed: i64,
+}
+
+/// 下一段要发的内容，`None` 时断开连接
+async fn next_chunk(state: &mut State) -> Option<Bytes> {
+    if let Some(chunk) = state.backlog.pop_front() {
+        return Some(chunk);
+    }
+    loop {
+        match tokio::time::timeout(HEARTBEAT, state.rx.recv()).await {
+            Err(_) => return Some(Bytes::from_static(b": ping\n\n")),
+            Ok(Ok(event)) if event.id <= state.replayed => continue,
+            Ok(Ok(event)) => return Some(format_event(&event)),
+            // 跟不上了，断开让客户端带着 Last-Event-ID 重连，从数据库补
+            Ok(Err(RecvError::Lagged(n))) => {
+                warn!("事件订阅者落后了 {} 条，断开", n);
+                return None;
+            }
+            Ok(Err(RecvError::Closed)) => return None,
+        }
+    }
+}
+
+/// 新的候选和筛选结果，带 `Last-Event-ID` 时先补上错过的，补不全时发 `reset`
+#[get("/events")]
+async fn events(
+    req: HttpRequest,
+    query: web::Query<EventsQuery>,
+    db: web::Data<db::Pool>,
+) -> Result<HttpResponse> {
+    auth::reviewer_or_query(&req, query.token.as_deref())?;
+    let last_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .or(query.last_event_id);
+    // 先订阅再查数据库，查询期间的事件不会丢
+    let rx = biz::events::subscribe();
+    let (backlog, replayed) = match last_id {
+        Some(id) => match biz::events::replay(id, &db).await? {
+            Replay::Events(events) => {
+                let replayed = events.last().map_or(id, |e| e.id);
+                (events.iter().map(format_event).collect(), replayed)
+            }
+            Replay::Reset(reset) => {
+                warn!("{} 之后错过的事件补不全，让客户端重新拉取", id);
+                (vec![format_reset(&reset)].into(), reset.last_event_id)
+            }
+        },
+        None => (VecDeque::new(), 0),
+    };
+    let state = State {
+        backlog,
+        rx,
+        replayed,
+    };
+    let stream = futures::stream::unfold(state, |mut state| async move {
+        let chunk = next_chunk(&mut state).await?;
+        Some((Ok::<_, actix_web::Error>(chunk), state))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(Box::pin(stream)))
+}
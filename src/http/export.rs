@@ -0,0 +1,48 @@
+use super::{auth, error::*};
+use crate::{biz, db, FeishuClient};
+use actix_web::{get, http::header, web, HttpRequest, HttpResponse};
+use biz::export::Format;
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+fn default_format() -> Format {
+    Format::Csv
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    /// 北京时间的日期，两头都包含，`to` 不填时只导出 `from` 这一天
+    from: NaiveDate,
+    to: Option<NaiveDate>,
+    #[serde(default = "default_format")]
+    format: Format,
+}
+
+#[get("/export")]
+async fn export(
+    req: HttpRequest,
+    data: web::Query<ExportQuery>,
+    db: web::Data<db::Pool>,
+    feishu_client: web::Data<FeishuClient>,
+) -> Result<HttpResponse> {
+    auth::reviewer(&req)?;
+    let query = data.into_inner();
+    let to = query.to.unwrap_or(query.from);
+    biz::summary::check_range(query.from, to).map_err(Error::bad_request)?;
+    let names = biz::directory::names(&feishu_client).await?;
+    let rows = biz::export::rows(query.from, to, &names, &db).await?;
+    let body = biz::export::render(&rows, query.format)?;
+    let filename = format!(
+        "asoul-weekly-{}-{}.{}",
+        query.from,
+        to,
+        query.format.extension()
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(query.format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        ))
+        .body(body))
+}
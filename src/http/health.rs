@@ -0,0 +1,77 @@
+use crate::{db, supervisor, FeishuClient};
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+
+fn check(r: Result<String, String>) -> api::Check {
+    match r {
+        Ok(detail) => api::Check {
+            ok: true,
+            detail: Some(detail).filter(|d| !d.is_empty()),
+        },
+        Err(detail) => api::Check {
+            ok: false,
+            detail: Some(detail),
+        },
+    }
+}
+
+async fn check_database(pool: &db::Pool) -> api::Check {
+    let r = sqlx::query("SELECT 1;").execute(pool).await;
+    check(r.map(|_| String::new()).map_err(|e| e.to_string()))
+}
+
+fn check_token(expires_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> api::Check {
+    check(match expires_at {
+        Some(t) if t > now => Ok(format!("{} 过期", t)),
+        Some(t) => Err(format!("已经在 {} 过期", t)),
+        None => Err("还没有获取到 token".to_string()),
+    })
+}
+
+async fn health(pool: &db::Pool, client: &FeishuClient) -> api::Health {
+    let now = Utc::now();
+    api::Health {
+        ok: true,
+        database: check_database(pool).await,
+        token: check_token(client.token_manager().expires_at(), now),
+        tasks: supervisor::report(now),
+    }
+}
+
+fn respond(health: api::Health) -> HttpResponse {
+    if health.ok {
+        HttpResponse::Ok().json(health)
+    } else {
+        HttpResponse::ServiceUnavailable().json(health)
+    }
+}
+
+/// 存活检查：数据库能连上，后台任务都在运行（没有挂掉等待重启）
+#[get("/healthz")]
+async fn healthz(db: web::Data<db::Pool>, client: web::Data<FeishuClient>) -> HttpResponse {
+    let mut health = health(&db, &client).await;
+    health.ok = health.database.ok && health.tasks.iter().all(|t| t.running);
+    respond(health)
+}
+
+/// 就绪检查：另外要求 token 有效，后台任务最近报告过
+#[get("/readyz")]
+async fn readyz(db: web::Data<db::Pool>, client: web::Data<FeishuClient>) -> HttpResponse {
+    let mut health = health(&db, &client).await;
+    health.ok =
+        health.database.ok && health.token.ok && health.tasks.iter().all(|t| t.running && t.fresh);
+    respond(health)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_token() {
+        let now: DateTime<Utc> = "2021-10-18T04:00:00Z".parse().unwrap();
+        assert!(check_token(Some(now + chrono::Duration::hours(1)), now).ok);
+        assert!(!check_token(Some(now), now).ok);
+        assert!(!check_token(None, now).ok);
+    }
+}
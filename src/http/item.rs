@@ -0,0 +1,87 @@
+use super::{auth, error::*};
+use crate::{biz, db, FeishuClient};
+use actix_web::{
+    get, post,
+    web::{self, Json},
+    HttpRequest,
+};
+use log::*;
+
+/// 一页最多多少条
+const MAX_LIMIT: u32 = 100;
+
+#[get("/items")]
+async fn list_items(
+    req: HttpRequest,
+    query: web::Query<api::ItemQuery>,
+    db: web::Data<db::Pool>,
+) -> Result<Json<api::ItemPage>> {
+    auth::reviewer(&req)?;
+    let query = query.into_inner();
+    debug!("list items: {:?}", query);
+    if query.category.is_some() && query.uncategorized {
+        return Err(Error::bad_request("category 和 uncategorized 不能同时使用"));
+    }
+    let filter = db::ItemFilter {
+        kind: query.kind,
+        categories: query
+            .category
+            .as_deref()
+            .map(biz::category::with_aliases)
+            .unwrap_or_default(),
+        uncategorized: query.uncategorized,
+        uploader_uid: query.uploader_uid,
+        uploader_name: query.uploader,
+        marker: query.marker,
+        published_from: query.published_from,
+        published_to: query.published_to,
+        marked_from: query.marked_from,
+        marked_to: query.marked_to,
+        q: query.q,
+    };
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(db::ItemCursor::parse)
+        .transpose()
+        .map_err(Error::bad_request)?;
+    let limit = query.limit.clamp(1, MAX_LIMIT);
+    let desc = matches!(query.order, api::Order::Desc);
+    let page = db::Item::list(&filter, query.sort, desc, cursor, limit, &db).await?;
+    Ok(Json(page.into()))
+}
+
+/// 网页上的筛选，和飞书卡片上的操作一样，操作完会更新飞书里的卡片
+///
+/// 标记人是 token 对应的名字
+#[post("/items/{id}/review")]
+async fn review_item(
+    req: HttpRequest,
+    id: web::Path<(String,)>,
+    data: web::Json<api::ReviewRequest>,
+    db: web::Data<db::Pool>,
+    feishu_client: web::Data<FeishuClient>,
+) -> Result<Json<api::Item>> {
+    let reviewer = auth::reviewer(&req)?;
+    let id = id.into_inner().0;
+    let data = data.into_inner();
+    info!("review {} by {}: {:?}", id, reviewer, data);
+    if db::Item::from_id(&id, &db).await?.is_none() {
+        return Err(Error::NotFound(format!("数据库不存在 {} 的条目", id)));
+    }
+    let marker = format!("web:{}", reviewer);
+    let item = match data.review {
+        api::Review::Categorize { category } => {
+            let category = biz::category::resolve_active(&category)
+                .ok_or_else(|| Error::BadRequest(format!("不存在的分类：{}", category)))?;
+            biz::review::categorize(&id, &category, &marker, &db, &feishu_client).await?
+        }
+        api::Review::Reject => biz::review::reject(&id, &marker, &db).await?,
+        api::Review::Undo => biz::review::undo(&id, &db).await?,
+    };
+    // 飞书卡片更新失败不影响结果
+    if let Err(e) = biz::review::refresh_card(&item, &db, &feishu_client).await {
+        warn!("更新 {} 的飞书卡片失败：{:?}", id, e);
+    }
+    Ok(Json(item.into()))
+}
@@ -1,21 +1,43 @@
+mod auth;
 mod category;
+mod control;
+mod dashboard;
 mod error;
+mod events;
+mod export;
+mod health;
+mod item;
+mod webhook;
 
-use std::collections::HashMap;
-
-use crate::{biz, db, FeishuClient};
+use crate::{biz, db, metrics, FeishuClient};
 use actix_web::{
     get, post,
     web::{self, Json},
-    App, HttpServer,
+    App, HttpResponse, HttpServer,
 };
-use chrono::{DateTime, Utc};
+use chrono_tz::Asia::Shanghai;
 use serde::Serialize;
 use serde_json::Value;
 
 use biz::callback::CallbackData;
 use error::*;
 
+/// 配置了 verification token 时校验飞书回调带的 token
+fn verify_token(data: &Value) -> Result<()> {
+    let expected = match &crate::config::CONFIG.feishu.verification_token {
+        Some(token) => token,
+        None => return Ok(()),
+    };
+    // 2.0 的事件放在 header 里，卡片回调和 url 验证在最外层
+    let token = data["header"]["token"]
+        .as_str()
+        .or_else(|| data["token"].as_str());
+    if token != Some(expected.as_str()) {
+        return Err(Error::Unauthorized("verification token 不正确".to_string()));
+    }
+    Ok(())
+}
+
 #[post("/callback")]
 async fn callback(
     // data: Json<CallbackData>,
@@ -26,12 +48,42 @@ async fn callback(
     let data = data.into_inner();
 
     debug!("callback data: {}", data.to_string());
-    let data: CallbackData = serde_json::from_value(data).map_err(anyhow::Error::from)?;
+    verify_token(&data)?;
+    let data: CallbackData = serde_json::from_value(data).map_err(Error::bad_request)?;
+    let kind = match &data {
+        CallbackData::Bind(_) => "bind",
+        CallbackData::Event(_) => "event",
+        CallbackData::Action(_) => "action",
+    };
+    let _timer = metrics::CALLBACK_SECONDS
+        .with_label_values(&[kind])
+        .start_timer();
 
     let j = match data {
         CallbackData::Bind(b) => json!({
             "challenge": b.challenge
         }),
+        CallbackData::Event(event) => {
+            // 事件会执行机器人命令，没法校验来源的时候不处理
+            if crate::config::CONFIG.feishu.verification_token.is_none() {
+                warn!(
+                    "没有配置 verification_token，忽略事件 {}",
+                    event.header.event_id
+                );
+                return Err(Error::Unauthorized(
+                    "没有配置 verification token，不处理事件".to_string(),
+                ));
+            }
+            info!(
+                "event: {} {}",
+                event.header.event_type, event.header.event_id
+            );
+            // 飞书要求尽快响应，命令异步处理
+            let pool = db_pool.get_ref().clone();
+            let client = feishu_client.get_ref().clone();
+            tokio::spawn(biz::command::handle_event(event, pool, client));
+            json!({})
+        }
         CallbackData::Action(action) => {
             info!("action: {:?}", action);
 
@@ -48,49 +100,77 @@ async fn callback(
     Ok(Json(j))
 }
 
-#[derive(Debug, Deserialize)]
-struct DateQuery {
-    t: DateTime<Utc>,
-}
-
 #[get("/summary")]
 async fn summary(
-    data: web::Query<DateQuery>,
+    data: web::Query<api::SummaryQuery>,
     db: web::Data<db::Pool>,
 ) -> Result<Json<impl Serialize>> {
-    let t = data.into_inner().t;
-    let map = biz::summary::categorized(t, &db).await?;
-    Ok(Json(map))
+    let query = data.into_inner();
+    let (from, to) = match (query.t, query.from) {
+        (_, Some(from)) => (from, query.to.unwrap_or(from)),
+        (Some(t), None) => {
+            let date = t.with_timezone(&Shanghai).date().naive_local();
+            (date, date)
+        }
+        (None, None) => return Err(Error::bad_request("缺少参数 t 或者 from")),
+    };
+    biz::summary::check_range(from, to).map_err(Error::bad_request)?;
+    let j = match query.group_by {
+        None => json!(biz::summary::categorized_in_dates(from, to, &db).await?),
+        Some(group_by) => json!(api::GroupedSummary {
+            from,
+            to,
+            group_by,
+            groups: biz::summary::grouped(from, to, group_by, &db).await?,
+        }),
+    };
+    Ok(Json(j))
 }
 
 #[get("/kpi")]
 async fn get_kpi(
-    data: web::Query<DateQuery>,
+    data: web::Query<api::KpiQuery>,
     db: web::Data<db::Pool>,
     feishu_client: web::Data<FeishuClient>,
-) -> Result<Json<impl Serialize>> {
-    let date = data.into_inner().t;
+) -> Result<Json<Value>> {
+    let query = data.into_inner();
+    let date = match (query.t, query.from) {
+        (_, Some(from)) => {
+            let to = query.to.unwrap_or(from);
+            biz::summary::check_range(from, to).map_err(Error::bad_request)?;
+            let reviewers = biz::kpi::range(from, to, &db, &feishu_client).await?;
+            return Ok(Json(json!(api::KpiRange {
+                from,
+                to,
+                reviewers
+            })));
+        }
+        (Some(t), None) => t,
+        (None, None) => return Err(Error::bad_request("缺少参数 t 或者 from")),
+    };
 
-    let kpi = db::Item::get_kpi(date, &db).await?;
-    let users = feishu_client.get_users_in_tenant().await?;
-    let user_id_to_name: HashMap<String, String> =
-        users.into_iter().map(|u| (u.user_id, u.name)).collect();
+    let kpi = biz::kpi::daily(date, &db, &feishu_client).await?;
+    let result: Vec<_> = kpi
+        .into_iter()
+        .map(|(name, times)| api::KpiEntry { name, times })
+        .collect();
 
-    let mut result = vec![];
-    for (user_id, times) in kpi {
-        match user_id_to_name.get(&user_id) {
-            Some(name) => result.push(json!({
-                "name": name,
-                "times": times,
-            })),
-            None => result.push(json!({
-                "name": "？？？",
-                "times": times
-            })),
-        }
-    }
+    Ok(Json(json!(result)))
+}
+
+/// Prometheus 的指标
+#[get("/metrics")]
+async fn get_metrics() -> Result<HttpResponse> {
+    let text = metrics::render()?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(text))
+}
 
-    Ok(Json(result))
+/// 接口文档，由 `api` 里的类型生成
+#[get("/openapi.json")]
+async fn openapi() -> Json<Value> {
+    Json(api::openapi())
 }
 
 pub async fn main(
@@ -105,10 +185,35 @@ pub async fn main(
             .service(callback)
             .service(summary)
             .service(get_kpi)
+            .service(openapi)
+            .service(get_metrics)
+            .service(health::healthz)
+            .service(health::readyz)
+            .service(control::get_control)
+            .service(control::put_control)
+            .service(control::put_source)
+            .service(control::run_source)
             .service(category::post_category)
             .service(category::patch_category)
             .service(category::remove_category)
             .service(category::get_category)
+            .service(item::list_items)
+            .service(item::review_item)
+            .service(dashboard::index)
+            .service(dashboard::asset)
+            .service(export::export)
+            .service(webhook::list_deliveries)
+            .service(events::events)
+            .service(category::list_categories)
+            .service(category::create_category)
+            .service(category::update_category_definition)
+            .service(category::delete_category_definition)
+            // 参数解析失败也返回统一的错误格式
+            .app_data(
+                web::QueryConfig::default().error_handler(|e, _| Error::bad_request(e).into()),
+            )
+            .app_data(web::JsonConfig::default().error_handler(|e, _| Error::bad_request(e).into()))
+            .app_data(web::PathConfig::default().error_handler(|e, _| Error::bad_request(e).into()))
             .app_data(db_pool.clone())
             .app_data(feishu_client.clone())
     })
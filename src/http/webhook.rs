@@ -0,0 +1,30 @@
+use super::{auth, error::*};
+use crate::db;
+use actix_web::{
+    get,
+    web::{self, Json},
+    HttpRequest,
+};
+
+/// 一次最多返回多少条
+const MAX_LIMIT: u32 = 200;
+
+/// webhook 的投递记录，新的在前
+#[get("/webhooks/deliveries")]
+async fn list_deliveries(
+    req: HttpRequest,
+    query: web::Query<api::DeliveryQuery>,
+    db: web::Data<db::Pool>,
+) -> Result<Json<Vec<api::WebhookDelivery>>> {
+    auth::admin(&req)?;
+    let query = query.into_inner();
+    let deliveries = db::WebhookDelivery::list(
+        query.webhook.as_deref(),
+        query.status,
+        query.before,
+        query.limit.clamp(1, MAX_LIMIT),
+        &db,
+    )
+    .await?;
+    Ok(Json(deliveries.into_iter().map(Into::into).collect()))
+}
@@ -11,8 +11,10 @@ extern crate anyhow;
 use anyhow::*;
 use feishu::FeishuClient;
 
-mod biz;
+pub mod biz;
 pub mod config;
 pub mod db;
 pub mod feishu;
 mod http;
+pub mod metrics;
+pub mod supervisor;
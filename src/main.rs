@@ -15,6 +15,8 @@ mod config;
 pub mod db;
 mod feishu;
 mod http;
+mod metrics;
+mod supervisor;
 
 // #[tokio::main]
 #[actix_web::main]
@@ -28,26 +30,81 @@ async fn main() -> Result<()> {
     // 连 db
     let db_pool = db::init(&config.sqlite_url).await?;
     sqlx::migrate!().run(&db_pool).await?;
+    biz::cards::backfill_legacy_items(&db_pool)
+        .await
+        .context("Backfill legacy items failed")?;
+    biz::category::init(&db_pool)
+        .await
+        .context("Init categories failed")?;
     debug!("db migration ok");
 
     // 刷新 token
     let token_manager = feishu::TokenManager::new(config.feishu.app_id, config.feishu.app_secret)
         .await
         .context("Init token manger failed")?;
-    let token = token_manager.token();
-    tokio::spawn(async move { token_manager.auto_refresh().await });
+    let _token_manager = token_manager.clone();
+    supervisor::spawn("token", None, move |task| {
+        _token_manager.clone().auto_refresh(task)
+    });
 
-    let feishu_client = FeishuClient::new(token);
+    let feishu_client = FeishuClient::new(token_manager);
+    biz::image::init_fallback(&feishu_client, &db_pool)
+        .await
+        .context("Init fallback image failed")?;
+
+    // 视频和动态共用一个限流
+    let bili_client =
+        bilibili::Client::new(&config.bilibili).context("Init bilibili client failed")?;
 
     // 拉 feed 下的视频
     let _feishu = feishu_client.clone();
+    let _bili = bili_client.clone();
     let _db_pool = db_pool.clone();
-    tokio::spawn(async move { biz::bilibili::video::fetch_forever(_feishu, _db_pool).await });
+    let silence = biz::schedule::Schedule::new(config.schedules.video.clone()).max_silence();
+    supervisor::spawn("video", Some(silence), move |task| {
+        biz::bilibili::video::fetch_forever(_feishu.clone(), _bili.clone(), _db_pool.clone(), task)
+    });
 
     // 拉 feed 动态
     let _feishu = feishu_client.clone();
+    let _bili = bili_client.clone();
     let _db_pool = db_pool.clone();
-    tokio::spawn(async move { biz::bilibili::dynamic::fetch_forever(_feishu, _db_pool).await });
+    let silence = biz::schedule::Schedule::new(config.schedules.dynamic.clone()).max_silence();
+    supervisor::spawn("dynamic", Some(silence), move |task| {
+        biz::bilibili::dynamic::fetch_forever(
+            _feishu.clone(),
+            _bili.clone(),
+            _db_pool.clone(),
+            task,
+        )
+    });
+
+    // 归档不用的群
+    if config.group.archive_after_days.is_some() {
+        let _feishu = feishu_client.clone();
+        let _db_pool = db_pool.clone();
+        supervisor::spawn("group_archive", None, move |task| {
+            biz::group::archive_forever(_feishu.clone(), _db_pool.clone(), task)
+        });
+    }
+
+    // 每周的标记排行榜
+    if let Some(chat_id) = config.kpi.leaderboard_chat_id {
+        let _feishu = feishu_client.clone();
+        let _db_pool = db_pool.clone();
+        supervisor::spawn("leaderboard", None, move |task| {
+            biz::kpi::leaderboard_forever(chat_id.clone(), _feishu.clone(), _db_pool.clone(), task)
+        });
+    }
+
+    // 筛选之后通知其他工具
+    if !config.webhooks.is_empty() {
+        let webhooks = config.webhooks;
+        let _db_pool = db_pool.clone();
+        supervisor::spawn("webhook", None, move |task| {
+            biz::webhook::deliver_forever(webhooks.clone(), _db_pool.clone(), task)
+        });
+    }
 
     http::main(config.http_addr, feishu_client, db_pool).await?;
 
@@ -0,0 +1,172 @@
+//! Prometheus 指标，`/metrics` 返回
+use anyhow::Result;
+use chrono::Utc;
+use prometheus::{core::Collector, Encoder, GaugeVec, HistogramVec, IntCounterVec, TextEncoder};
+
+/// 图片处理的耗时分布，单位秒
+const IMAGE_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10., 30.];
+/// 飞书回调的耗时分布，飞书要求 3 秒内响应
+const CALLBACK_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 1., 2., 3., 5.];
+
+lazy_static::lazy_static! {
+    /// outcome 是 ok、throttled（被风控或者熔断中）或者 error
+    pub static ref BILIBILI_REQUESTS: IntCounterVec = prometheus::register_int_counter_vec!(
+        "bilibili_requests_total",
+        "请求哔哩哔哩接口的次数",
+        &["endpoint", "outcome"]
+    )
+    .unwrap();
+    /// stage 是 fetched（拉到的）、filtered（屏蔽或者推送过的）、sent（推送到飞书的）
+    pub static ref CANDIDATES: IntCounterVec = prometheus::register_int_counter_vec!(
+        "candidates_total",
+        "拉取到的候选数量",
+        &["source", "stage"]
+    )
+    .unwrap();
+    pub static ref FEISHU_REQUESTS: IntCounterVec = prometheus::register_int_counter_vec!(
+        "feishu_requests_total",
+        "请求飞书接口的次数，重试也算",
+        &["api"]
+    )
+    .unwrap();
+    pub static ref FEISHU_ERRORS: IntCounterVec = prometheus::register_int_counter_vec!(
+        "feishu_errors_total",
+        "飞书接口出错的次数",
+        &["api", "kind"]
+    )
+    .unwrap();
+    /// stage 是 download、merge 或者 upload
+    pub static ref IMAGE_SECONDS: HistogramVec = prometheus::register_histogram_vec!(
+        "image_duration_seconds",
+        "图片下载、拼图、上传的耗时",
+        &["stage"],
+        IMAGE_BUCKETS.to_vec()
+    )
+    .unwrap();
+    pub static ref CALLBACK_SECONDS: HistogramVec = prometheus::register_histogram_vec!(
+        "callback_duration_seconds",
+        "处理飞书回调的耗时",
+        &["kind"],
+        CALLBACK_BUCKETS.to_vec()
+    )
+    .unwrap();
+    pub static ref ITEMS_CATEGORIZED: IntCounterVec = prometheus::register_int_counter_vec!(
+        "items_categorized_total",
+        "选入的条目数，reviewer 是筛选的地方：feishu、web 或者 api",
+        &["category", "reviewer"]
+    )
+    .unwrap();
+    pub static ref FETCH_THROTTLED: IntCounterVec = prometheus::register_int_counter_vec!(
+        "fetch_throttled_total",
+        "一轮拉取因为被风控而失败的次数",
+        &["source"]
+    )
+    .unwrap();
+    static ref FETCH_LAST_SUCCESS: GaugeVec = prometheus::register_gauge_vec!(
+        "fetch_last_success_timestamp_seconds",
+        "上一次成功拉取的时间",
+        &["source"]
+    )
+    .unwrap();
+    /// 在 [`render`] 的时候根据 [`FETCH_LAST_SUCCESS`] 更新
+    static ref FETCH_SINCE_SUCCESS: GaugeVec = prometheus::register_gauge_vec!(
+        "fetch_seconds_since_last_success",
+        "距离上一次成功拉取过了多少秒",
+        &["source"]
+    )
+    .unwrap();
+}
+
+pub fn outcome<T, E>(r: &std::result::Result<T, E>) -> &'static str {
+    match r {
+        Ok(_) => "ok",
+        Err(_) => "error",
+    }
+}
+
+/// 记一次哔哩哔哩接口的请求
+pub fn bilibili_request<T>(endpoint: &str, r: &std::result::Result<T, bilibili::Error>) {
+    let outcome = match r {
+        Err(e) if e.is_throttled() => "throttled",
+        r => outcome(r),
+    };
+    BILIBILI_REQUESTS
+        .with_label_values(&[endpoint, outcome])
+        .inc();
+}
+
+pub fn candidates(source: &str, stage: &str, n: usize) {
+    CANDIDATES
+        .with_label_values(&[source, stage])
+        .inc_by(n as u64);
+}
+
+pub fn fetch_throttled(source: &str) {
+    FETCH_THROTTLED.with_label_values(&[source]).inc();
+}
+
+/// 一轮拉取成功结束
+pub fn fetch_succeeded(source: &str) {
+    FETCH_LAST_SUCCESS
+        .with_label_values(&[source])
+        .set(Utc::now().timestamp() as f64);
+}
+
+fn update_since_success(now: f64) {
+    for family in FETCH_LAST_SUCCESS.collect() {
+        for metric in family.get_metric() {
+            let source = match metric.get_label().iter().find(|l| l.get_name() == "source") {
+                Some(label) => label.get_value(),
+                None => continue,
+            };
+            let last = metric.get_gauge().get_value();
+            FETCH_SINCE_SUCCESS
+                .with_label_values(&[source])
+                .set(now - last);
+        }
+    }
+}
+
+/// Prometheus 的文本格式
+pub fn render() -> Result<String> {
+    update_since_success(Utc::now().timestamp() as f64);
+    let mut buf = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        bilibili_request("tag_detail", &Ok(()));
+        let e = bilibili::Error::Api {
+            code: -404,
+            message: String::new(),
+        };
+        bilibili_request::<()>("tag_detail", &Err(e));
+        let e = bilibili::Error::Throttled {
+            host: "api.bilibili.com".to_string(),
+            code: Some(-412),
+            retry_after: std::time::Duration::from_secs(60),
+        };
+        bilibili_request::<()>("tag_detail", &Err(e));
+        candidates("video", "fetched", 3);
+        fetch_succeeded("video");
+        let text = render().unwrap();
+        assert!(
+            text.contains(r#"bilibili_requests_total{endpoint="tag_detail",outcome="error"} 1"#)
+        );
+        assert!(text
+            .contains(r#"bilibili_requests_total{endpoint="tag_detail",outcome="throttled"} 1"#));
+        assert!(text.contains(r#"candidates_total{source="video",stage="fetched"} 3"#));
+        let since = text
+            .lines()
+            .find(|l| l.starts_with(r#"fetch_seconds_since_last_success{source="video"}"#))
+            .unwrap();
+        let since: f64 = since.rsplit(' ').next().unwrap().parse().unwrap();
+        assert!(since < 5.);
+    }
+}
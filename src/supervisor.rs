@@ -0,0 +1,201 @@
+//! 后台任务的监督：任务 panic 或者退出之后按退避重启，记下最近一次成功和出错，给 `/healthz` 和 `/readyz` 用
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// 第一次重启前等多久，之后每次翻倍
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+/// 运行超过这么久才挂掉的，退避从头算
+const STABLE_AFTER: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone)]
+struct State {
+    running: bool,
+    restarts: u32,
+    started_at: DateTime<Utc>,
+    /// 上一次报告活着，成功和出错都算
+    last_seen: Option<DateTime<Utc>>,
+    last_success: Option<DateTime<Utc>>,
+    last_error: Option<(DateTime<Utc>, String)>,
+    /// 超过这么久没有报告就不算新鲜，`None` 表示不检查
+    max_silence: Option<chrono::Duration>,
+}
+
+impl State {
+    fn new(max_silence: Option<chrono::Duration>) -> Self {
+        Self {
+            running: false,
+            restarts: 0,
+            started_at: Utc::now(),
+            last_seen: None,
+            last_success: None,
+            last_error: None,
+            max_silence,
+        }
+    }
+
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        match self.max_silence {
+            // 刚启动还没报告过的，从启动时间开始算
+            Some(max) => now - self.last_seen.unwrap_or(self.started_at) <= max,
+            None => true,
+        }
+    }
+
+    fn health(&self, name: &str, now: DateTime<Utc>) -> api::TaskHealth {
+        api::TaskHealth {
+            name: name.to_string(),
+            running: self.running,
+            fresh: self.is_fresh(now),
+            restarts: self.restarts,
+            started_at: self.started_at,
+            last_success: self.last_success,
+            last_error_at: self.last_error.as_ref().map(|(t, _)| *t),
+            last_error: self.last_error.as_ref().map(|(_, e)| e.clone()),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref TASKS: RwLock<BTreeMap<&'static str, State>> = Default::default();
+}
+
+/// 后台任务用来报告自己的状态
+#[derive(Debug, Clone, Copy)]
+pub struct Task {
+    name: &'static str,
+}
+
+impl Task {
+    fn update(self, f: impl FnOnce(&mut State)) {
+        if let Some(state) = TASKS.write().get_mut(self.name) {
+            f(state);
+        }
+    }
+
+    /// 还活着，但是这一轮什么也没做，比如暂停了
+    pub fn alive(self) {
+        self.update(|s| s.last_seen = Some(Utc::now()));
+    }
+
+    pub fn succeeded(self) {
+        let now = Utc::now();
+        self.update(|s| {
+            s.last_seen = Some(now);
+            s.last_success = Some(now);
+        });
+    }
+
+    pub fn failed(self, e: &anyhow::Error) {
+        let now = Utc::now();
+        self.update(|s| {
+            s.last_seen = Some(now);
+            s.last_error = Some((now, format!("{:#}", e)));
+        });
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(s) => format!("panic: {}", s),
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(s) => format!("panic: {}", s),
+            Err(_) => "panic".to_string(),
+        },
+    }
+}
+
+/// 在后台运行 `run`，panic 或者退出之后按退避重启
+pub fn spawn<F, Fut>(name: &'static str, max_silence: Option<chrono::Duration>, run: F) -> Task
+where
+    F: Fn(Task) -> Fut + Send + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: Send + 'static,
+{
+    let task = Task { name };
+    TASKS.write().insert(name, State::new(max_silence));
+    tokio::spawn(async move {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let started = Instant::now();
+            task.update(|s| {
+                s.running = true;
+                s.started_at = Utc::now();
+            });
+            // 在单独的 task 里运行，panic 不会带走监督的循环
+            let error = match tokio::spawn(run(task)).await {
+                Ok(_) => "任务退出了".to_string(),
+                Err(e) if e.is_panic() => panic_message(e.into_panic()),
+                Err(e) => e.to_string(),
+            };
+            if started.elapsed() >= STABLE_AFTER {
+                backoff = MIN_BACKOFF;
+            }
+            error!("后台任务 {} 挂了，{:?} 后重启：{}", name, backoff, error);
+            task.update(|s| {
+                s.running = false;
+                s.restarts += 1;
+                s.last_error = Some((Utc::now(), error));
+            });
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    });
+    task
+}
+
+/// 所有后台任务的状态
+pub fn report(now: DateTime<Utc>) -> Vec<api::TaskHealth> {
+    TASKS
+        .read()
+        .iter()
+        .map(|(name, state)| state.health(name, now))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_fresh() {
+        let t: DateTime<Utc> = "2021-10-18T04:00:00Z".parse().unwrap();
+        let mut state = State::new(Some(chrono::Duration::minutes(20)));
+        state.started_at = t;
+        assert!(state.is_fresh(t + chrono::Duration::minutes(20)));
+        assert!(!state.is_fresh(t + chrono::Duration::minutes(21)));
+        state.last_seen = Some(t + chrono::Duration::minutes(10));
+        assert!(state.is_fresh(t + chrono::Duration::minutes(30)));
+        assert!(State::new(None).is_fresh(t + chrono::Duration::days(1)));
+    }
+
+    #[tokio::test]
+    async fn test_restart() {
+        let runs = Arc::new(AtomicU32::new(0));
+        let _runs = runs.clone();
+        spawn("test_restart", None, move |task| {
+            let runs = _runs.clone();
+            async move {
+                if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("boom");
+                }
+                task.succeeded();
+                futures::future::pending::<()>().await;
+            }
+        });
+        tokio::time::sleep(MIN_BACKOFF + Duration::from_millis(200)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        let health = report(Utc::now());
+        let health = health.iter().find(|t| t.name == "test_restart").unwrap();
+        assert!(health.running);
+        assert_eq!(health.restarts, 1);
+        assert_eq!(health.last_error.as_deref(), Some("panic: boom"));
+        assert!(health.last_success.is_some());
+    }
+}